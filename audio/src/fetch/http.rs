@@ -0,0 +1,312 @@
+use std::cmp::min;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use bytes::{Buf, Bytes};
+use futures_executor::block_on;
+use hyper::body::HttpBody;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE,
+};
+use hyper::{Body, Response, StatusCode};
+use librespot_core::http_client::{HttpClient, HttpClientError};
+use thiserror::Error;
+
+use super::StreamLoaderController;
+
+#[derive(Debug, Error)]
+pub enum HttpFileError {
+    #[error(transparent)]
+    Client(#[from] HttpClientError),
+    #[error("Unexpected HTTP status {0}")]
+    Status(StatusCode),
+    #[error("The server doesn't tell the length of the file")]
+    UnknownLength,
+}
+
+impl From<HttpFileError> for io::Error {
+    fn from(e: HttpFileError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+}
+
+fn range_from(offset: u64) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    // Panic safety: the header value is ASCII
+    headers.insert(
+        RANGE,
+        HeaderValue::from_str(&format!("bytes={}-", offset)).unwrap(),
+    );
+    headers
+}
+
+fn header_value<'a>(response: &'a Response<Body>, name: &HeaderName) -> Option<&'a str> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn file_length(response: &Response<Body>) -> Option<u64> {
+    match response.status() {
+        // e.g. "bytes 0-1023/4096"
+        StatusCode::PARTIAL_CONTENT => header_value(response, &CONTENT_RANGE)?
+            .rsplit('/')
+            .next()?
+            .parse()
+            .ok(),
+        _ => header_value(response, &CONTENT_LENGTH)?.parse().ok(),
+    }
+}
+
+/// A file that is streamed from a URL, for audio that isn't hosted by Spotify (e.g. podcast
+/// episodes that only exist as the enclosure of an RSS feed).
+///
+/// Reads block until the data has arrived. Reading continues from the response of the last
+/// request, and seeking elsewhere starts a new request for the range from there.
+pub struct HttpFile {
+    client: HttpClient,
+    // where the file ended up after redirects
+    url: String,
+    content_type: Option<String>,
+    len: u64,
+    position: u64,
+    // delivers the data that follows the chunk
+    body: Option<Body>,
+    chunk: Bytes,
+}
+
+impl HttpFile {
+    pub async fn open(client: HttpClient, url: &str) -> Result<HttpFile, HttpFileError> {
+        let (url, response) = client.get(url, &range_from(0)).await?;
+
+        match response.status() {
+            StatusCode::OK | StatusCode::PARTIAL_CONTENT => (),
+            status => return Err(HttpFileError::Status(status)),
+        }
+
+        let len = file_length(&response).ok_or(HttpFileError::UnknownLength)?;
+        let content_type = header_value(&response, &CONTENT_TYPE).map(str::to_owned);
+
+        debug!(
+            "Streaming <{}> with {} bytes of {}",
+            url,
+            len,
+            content_type.as_deref().unwrap_or("unknown content")
+        );
+
+        Ok(HttpFile {
+            client,
+            url: url.into(),
+            content_type,
+            len,
+            position: 0,
+            body: Some(response.into_body()),
+            chunk: Bytes::new(),
+        })
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn get_stream_loader_controller(&self) -> StreamLoaderController {
+        // Data is requested as it is read, so there is no loader to control.
+        StreamLoaderController {
+            channel_tx: None,
+            stream_shared: None,
+            file_size: self.len as usize,
+        }
+    }
+
+    fn request_from(&mut self, offset: u64) -> io::Result<()> {
+        self.chunk = Bytes::new();
+
+        let (_, response) = block_on(self.client.get(&self.url, &range_from(offset)))
+            .map_err(HttpFileError::from)?;
+        let status = response.status();
+        let mut body = response.into_body();
+
+        match status {
+            StatusCode::PARTIAL_CONTENT => (),
+            // The server ignores the range, so skip ahead to it.
+            StatusCode::OK => {
+                let mut skip = offset as usize;
+                while skip > 0 {
+                    match block_on(body.data()) {
+                        Some(Ok(mut data)) if data.len() > skip => {
+                            self.chunk = data.split_off(skip);
+                            skip = 0;
+                        }
+                        Some(Ok(data)) => skip -= data.len(),
+                        Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+                        None => break,
+                    }
+                }
+            }
+            status => return Err(HttpFileError::Status(status).into()),
+        }
+
+        self.body = Some(body);
+        Ok(())
+    }
+}
+
+impl Read for HttpFile {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        let mut requested = false;
+
+        while self.chunk.is_empty() {
+            if self.position >= self.len {
+                return Ok(0);
+            }
+
+            let body = match self.body {
+                Some(ref mut body) => body,
+                None => {
+                    self.request_from(self.position)?;
+                    requested = true;
+                    continue;
+                }
+            };
+
+            match block_on(body.data()) {
+                Some(Ok(data)) => self.chunk = data,
+                Some(Err(e)) => {
+                    self.body = None;
+                    return Err(io::Error::new(io::ErrorKind::Other, e));
+                }
+                // The response ended before the file did, try once more from here.
+                None if !requested => self.body = None,
+                None => return Ok(0),
+            }
+        }
+
+        let length = min(output.len(), self.chunk.len());
+        output[..length].copy_from_slice(&self.chunk[..length]);
+        self.chunk.advance(length);
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl Seek for HttpFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (self.len as i64).checked_add(offset).map(|p| p as u64),
+            SeekFrom::Current(offset) => {
+                (self.position as i64).checked_add(offset).map(|p| p as u64)
+            }
+        }
+        .filter(|&position| position as i64 >= 0)
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative position")
+        })?;
+
+        // What has already arrived doesn't have to be requested again.
+        if position >= self.position && position - self.position <= self.chunk.len() as u64 {
+            self.chunk.advance((position - self.position) as usize);
+        } else if position != self.position {
+            self.body = None;
+            self.chunk = Bytes::new();
+        }
+
+        self.position = position;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn test_data() -> Vec<u8> {
+        (0..100_000).map(|i| (i % 251) as u8).collect()
+    }
+
+    // Serves the test data at /file, with support for ranges, and redirects /episode.mp3 there.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let data = test_data();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut path = String::new();
+                let mut offset = None;
+
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if line.starts_with("GET ") {
+                        path = line.split(' ').nth(1).unwrap().to_string();
+                    } else if let Some(range) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        offset = range.trim().trim_end_matches('-').parse::<usize>().ok();
+                    }
+                    line.clear();
+                }
+
+                let _ = match (path.as_str(), offset) {
+                    ("/episode.mp3", _) => write!(
+                        stream,
+                        "HTTP/1.1 302 Found\r\nLocation: /file\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    ),
+                    ("/file", Some(offset)) => write!(
+                        stream,
+                        "HTTP/1.1 206 Partial Content\r\nContent-Type: audio/mpeg\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        offset,
+                        data.len() - 1,
+                        data.len(),
+                        data.len() - offset
+                    )
+                    .and_then(|_| stream.write_all(&data[offset..])),
+                    _ => write!(
+                        stream,
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    ),
+                };
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    #[test]
+    fn stream_with_ranges() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = HttpClient::new(None, runtime.handle().clone());
+        let server = serve();
+
+        let missing = block_on(HttpFile::open(
+            client.clone(),
+            &format!("{}/missing", server),
+        ));
+        assert!(matches!(
+            missing,
+            Err(HttpFileError::Status(StatusCode::NOT_FOUND))
+        ));
+
+        let mut file =
+            block_on(HttpFile::open(client, &format!("{}/episode.mp3", server))).unwrap();
+        assert_eq!(file.content_type(), Some("audio/mpeg"));
+        assert_eq!(file.get_stream_loader_controller().len(), 100_000);
+
+        let mut start = [0; 1000];
+        file.read_exact(&mut start).unwrap();
+        assert_eq!(&start[..], &test_data()[..1000]);
+
+        let mut middle = [0; 1000];
+        file.seek(SeekFrom::Start(50_000)).unwrap();
+        file.read_exact(&mut middle).unwrap();
+        assert_eq!(&middle[..], &test_data()[50_000..51_000]);
+
+        let mut end = Vec::new();
+        assert_eq!(file.seek(SeekFrom::End(-10)).unwrap(), 99_990);
+        file.read_to_end(&mut end).unwrap();
+        assert_eq!(&end[..], &test_data()[99_990..]);
+    }
+}
@@ -1,3 +1,4 @@
+mod http;
 mod receive;
 
 use std::cmp::{max, min};
@@ -15,6 +16,7 @@ use librespot_core::spotify_id::FileId;
 use tempfile::NamedTempFile;
 use tokio::sync::{mpsc, oneshot};
 
+pub use self::http::{HttpFile, HttpFileError};
 use self::receive::{audio_file_fetch, request_range};
 use crate::range_set::{Range, RangeSet};
 
@@ -83,6 +85,10 @@ const MAX_PREFETCH_REQUESTS: usize = 4;
 /// The time we will wait to obtain status updates on downloading.
 const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(1);
 
+/// The download rate of a file is only told once this much of it has arrived, so that the latency
+/// of the first request doesn't make up most of the measurement.
+const MINIMUM_DOWNLOAD_RATE_SIZE: usize = 1024 * 64;
+
 pub enum AudioFile {
     Cached(fs::File),
     Streaming(AudioFileStreaming),
@@ -96,6 +102,32 @@ enum StreamLoaderCommand {
     Close(),            // terminate and don't load any more data
 }
 
+/// A snapshot of how far the download of a file has come, e.g. to tell why playback has to wait
+/// for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// The size of the file in bytes.
+    pub file_size: usize,
+    /// The number of bytes that have been downloaded.
+    pub downloaded: usize,
+    /// The position in the file that is read next.
+    pub read_position: usize,
+    /// The number of bytes that are available from the read position on.
+    pub available: usize,
+    /// The number of bytes that have been requested but haven't arrived yet.
+    pub pending: usize,
+    /// The number of requests that are still open.
+    pub open_requests: usize,
+    /// The round trip time to the Spotify servers, as measured during the download.
+    pub ping_time: Duration,
+}
+
+impl DownloadProgress {
+    pub fn is_complete(&self) -> bool {
+        self.downloaded >= self.file_size
+    }
+}
+
 #[derive(Clone)]
 pub struct StreamLoaderController {
     channel_tx: Option<mpsc::UnboundedSender<StreamLoaderCommand>>,
@@ -137,6 +169,60 @@ impl StreamLoaderController {
         }))
     }
 
+    // The rate that the file is downloaded with in bytes per second, counting only the time when
+    // requests for it were open. None if too little of it has arrived to tell, or if it isn't
+    // downloaded at all.
+    pub fn download_rate(&self) -> Option<usize> {
+        self.stream_shared
+            .as_ref()
+            .and_then(|shared| shared.download_measurement.lock().unwrap().rate())
+    }
+
+    pub fn download_progress(&self) -> DownloadProgress {
+        match self.stream_shared {
+            Some(ref shared) => {
+                let read_position = shared.read_position.load(atomic::Ordering::Relaxed);
+                let download_status = shared.download_status.lock().unwrap();
+                DownloadProgress {
+                    file_size: self.len(),
+                    downloaded: download_status.downloaded.len(),
+                    read_position,
+                    available: download_status
+                        .downloaded
+                        .contained_length_from_value(read_position),
+                    pending: download_status
+                        .requested
+                        .minus(&download_status.downloaded)
+                        .len(),
+                    open_requests: shared
+                        .number_of_open_requests
+                        .load(atomic::Ordering::SeqCst),
+                    ping_time: self.ping_time(),
+                }
+            }
+            // Everything is there already.
+            None => DownloadProgress {
+                file_size: self.len(),
+                downloaded: self.len(),
+                available: self.len(),
+                ..Default::default()
+            },
+        }
+    }
+
+    // Whether the next `length` bytes from the read position on are there, or as much of them
+    // as the file has.
+    pub fn next_available(&self, length: usize) -> bool {
+        match self.stream_shared {
+            Some(ref shared) => {
+                let read_position = shared.read_position.load(atomic::Ordering::Relaxed);
+                let length = min(length, self.len().saturating_sub(read_position));
+                self.range_available(Range::new(read_position, length))
+            }
+            None => true,
+        }
+    }
+
     fn send_stream_loader_command(&self, command: StreamLoaderCommand) {
         if let Some(ref channel) = self.channel_tx {
             // ignore the error in case the channel has been closed already.
@@ -149,9 +235,14 @@ impl StreamLoaderController {
         self.send_stream_loader_command(StreamLoaderCommand::Fetch(range));
     }
 
-    pub fn fetch_blocking(&self, mut range: Range) {
+    pub fn fetch_blocking(&self, range: Range) {
         // signal the stream loader to tech a range of the file and block until it is loaded.
+        self.fetch_blocking_until(range, None);
+    }
 
+    // Same as fetch_blocking(), but gives up at the deadline, if any. Returns whether the range
+    // has been loaded.
+    fn fetch_blocking_until(&self, mut range: Range, deadline: Option<Instant>) -> bool {
         // ensure the range is within the file's bounds.
         if range.start >= self.len() {
             range.length = 0;
@@ -168,9 +259,19 @@ impl StreamLoaderController {
                     .downloaded
                     .contained_length_from_value(range.start)
             {
+                let timeout = match deadline {
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return false;
+                        }
+                        min(deadline - now, DOWNLOAD_TIMEOUT)
+                    }
+                    None => DOWNLOAD_TIMEOUT,
+                };
                 download_status = shared
                     .cond
-                    .wait_timeout(download_status, DOWNLOAD_TIMEOUT)
+                    .wait_timeout(download_status, timeout)
                     .unwrap()
                     .0;
                 if range.length
@@ -185,6 +286,8 @@ impl StreamLoaderController {
                 }
             }
         }
+
+        true
     }
 
     pub fn fetch_next(&self, length: usize) {
@@ -207,6 +310,21 @@ impl StreamLoaderController {
         }
     }
 
+    // Same as fetch_next_blocking(), but waits for at most `timeout`. Returns whether the data
+    // has been loaded.
+    pub fn fetch_next_blocking_timeout(&self, length: usize, timeout: Duration) -> bool {
+        match self.stream_shared {
+            Some(ref shared) => {
+                let range = Range {
+                    start: shared.read_position.load(atomic::Ordering::Relaxed),
+                    length,
+                };
+                self.fetch_blocking_until(range, Some(Instant::now() + timeout))
+            }
+            None => true,
+        }
+    }
+
     pub fn set_random_access_mode(&self) {
         // optimise download strategy for random access
         self.send_stream_loader_command(StreamLoaderCommand::RandomAccessMode());
@@ -251,6 +369,32 @@ struct AudioFileShared {
     number_of_open_requests: AtomicUsize,
     ping_time_ms: AtomicUsize,
     read_position: AtomicUsize,
+    download_measurement: Mutex<DownloadMeasurement>,
+}
+
+// How much of a file has arrived, and over how long requests for it were open. Time when nothing
+// was requested (e.g. while enough is buffered) doesn't count, so that it doesn't make the
+// download look slower than it is.
+#[derive(Default)]
+struct DownloadMeasurement {
+    bytes: usize,
+    duration: Duration,
+    // since when requests have been open, if any are
+    open_since: Option<Instant>,
+}
+
+impl DownloadMeasurement {
+    // in bytes per second
+    fn rate(&self) -> Option<usize> {
+        let duration = match self.open_since {
+            Some(open_since) => self.duration + open_since.elapsed(),
+            None => self.duration,
+        };
+        if self.bytes < MINIMUM_DOWNLOAD_RATE_SIZE || duration.as_nanos() == 0 {
+            return None;
+        }
+        Some((self.bytes as f64 / duration.as_secs_f64()) as usize)
+    }
 }
 
 impl AudioFile {
@@ -360,6 +504,7 @@ impl AudioFileStreaming {
             number_of_open_requests: AtomicUsize::new(0),
             ping_time_ms: AtomicUsize::new(0),
             read_position: AtomicUsize::new(0),
+            download_measurement: Mutex::new(DownloadMeasurement::default()),
         });
 
         let mut write_file = NamedTempFile::new().unwrap();
@@ -511,3 +656,107 @@ impl Seek for AudioFile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_for_download() {
+        let shared = Arc::new(AudioFileShared {
+            file_id: FileId([0; 20]),
+            file_size: 1000,
+            stream_data_rate: 100,
+            cond: Condvar::new(),
+            download_status: Mutex::new(AudioFileDownloadStatus {
+                requested: RangeSet::new(),
+                downloaded: RangeSet::new(),
+            }),
+            download_strategy: Mutex::new(DownloadStrategy::Streaming()),
+            number_of_open_requests: AtomicUsize::new(1),
+            ping_time_ms: AtomicUsize::new(40),
+            read_position: AtomicUsize::new(100),
+            download_measurement: Mutex::new(DownloadMeasurement::default()),
+        });
+        let (channel_tx, mut channel_rx) = mpsc::unbounded_channel();
+        let controller = StreamLoaderController {
+            channel_tx: Some(channel_tx),
+            stream_shared: Some(shared.clone()),
+            file_size: 1000,
+        };
+
+        shared
+            .download_status
+            .lock()
+            .unwrap()
+            .requested
+            .add_range(&Range::new(0, 500));
+        shared
+            .download_status
+            .lock()
+            .unwrap()
+            .downloaded
+            .add_range(&Range::new(0, 150));
+
+        assert_eq!(
+            controller.download_progress(),
+            DownloadProgress {
+                file_size: 1000,
+                downloaded: 150,
+                read_position: 100,
+                available: 50,
+                pending: 350,
+                open_requests: 1,
+                ping_time: Duration::from_millis(40),
+            }
+        );
+        assert!(controller.next_available(50));
+        assert!(!controller.next_available(51));
+
+        assert!(!controller.fetch_next_blocking_timeout(100, Duration::from_millis(10)));
+        assert!(matches!(
+            channel_rx.try_recv(),
+            Ok(StreamLoaderCommand::Fetch(range)) if range.start == 100 && range.length == 100
+        ));
+
+        let arrived = shared.clone();
+        let download = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            arrived
+                .download_status
+                .lock()
+                .unwrap()
+                .downloaded
+                .add_range(&Range::new(150, 50));
+            arrived.cond.notify_all();
+        });
+        assert!(controller.fetch_next_blocking_timeout(100, Duration::from_secs(10)));
+        download.join().unwrap();
+
+        // Nothing is needed past the end of the file.
+        shared.read_position.store(1000, atomic::Ordering::Relaxed);
+        assert!(controller.next_available(100));
+        assert!(!controller.download_progress().is_complete());
+    }
+
+    #[test]
+    fn download_rate_leaves_out_idle_time() {
+        let mut measurement = DownloadMeasurement {
+            bytes: MINIMUM_DOWNLOAD_RATE_SIZE / 2,
+            duration: Duration::from_millis(500),
+            open_since: None,
+        };
+        assert_eq!(measurement.rate(), None);
+
+        // However long ago the requests were, only the time they took counts.
+        measurement.bytes = MINIMUM_DOWNLOAD_RATE_SIZE;
+        assert_eq!(measurement.rate(), Some(2 * MINIMUM_DOWNLOAD_RATE_SIZE));
+
+        // The time of requests that are still open counts up to now.
+        measurement.open_since = Some(Instant::now());
+        std::thread::sleep(Duration::from_millis(100));
+        let rate = measurement.rate().unwrap();
+        assert!(rate <= MINIMUM_DOWNLOAD_RATE_SIZE * 5 / 3, "{}", rate);
+        assert!(rate > MINIMUM_DOWNLOAD_RATE_SIZE / 3, "{}", rate);
+    }
+}
@@ -73,9 +73,18 @@ async fn receive_data(
     let mut data_offset = initial_data_offset;
     let mut request_length = initial_request_length;
 
-    let old_number_of_request = shared
-        .number_of_open_requests
-        .fetch_add(1, Ordering::SeqCst);
+    // The requests are counted along with the measurement, so that they agree on whether any
+    // are open.
+    let old_number_of_request = {
+        let mut download_measurement = shared.download_measurement.lock().unwrap();
+        let old_number_of_request = shared
+            .number_of_open_requests
+            .fetch_add(1, Ordering::SeqCst);
+        if old_number_of_request == 0 {
+            download_measurement.open_since = Some(request_sent_time);
+        }
+        old_number_of_request
+    };
 
     let mut measure_ping_time = old_number_of_request == 0;
 
@@ -95,6 +104,7 @@ async fn receive_data(
             measure_ping_time = false;
         }
         let data_size = data.len();
+        shared.download_measurement.lock().unwrap().bytes += data_size;
         let _ = file_data_tx.send(ReceivedData::Data(PartialFileData {
             offset: data_offset,
             data,
@@ -123,9 +133,17 @@ async fn receive_data(
         shared.cond.notify_all();
     }
 
-    shared
-        .number_of_open_requests
-        .fetch_sub(1, Ordering::SeqCst);
+    {
+        let mut download_measurement = shared.download_measurement.lock().unwrap();
+        let old_number_of_request = shared
+            .number_of_open_requests
+            .fetch_sub(1, Ordering::SeqCst);
+        if old_number_of_request == 1 {
+            if let Some(open_since) = download_measurement.open_since.take() {
+                download_measurement.duration += open_since.elapsed();
+            }
+        }
+    }
 
     if result.is_err() {
         warn!(
@@ -9,6 +9,7 @@ use crate::core::session::Session;
 use crate::core::spotify_id::{SpotifyAudioType, SpotifyId, SpotifyIdError};
 use crate::core::util::SeqGenerator;
 use crate::core::version;
+use crate::playback::config::SleepTimer;
 use crate::playback::mixer::Mixer;
 use crate::playback::player::{Player, PlayerEvent, PlayerEventChannel};
 use crate::protocol;
@@ -55,6 +56,10 @@ struct SpircTask {
     state: State,
     play_request_id: Option<u64>,
     play_status: SpircPlayStatus,
+    // the player reports where a seek ended up, which is taken over whatever the difference
+    seek_pending: bool,
+    // as the player reported it, for extrapolating the position while playing
+    speed: f64,
 
     subscription: BoxedStream<Frame>,
     sender: MercurySender,
@@ -78,6 +83,8 @@ pub enum SpircCommand {
     VolumeDown,
     Shutdown,
     Shuffle,
+    SetSleepTimer(Option<SleepTimer>),
+    SetSpeed(f64),
 }
 
 struct SpircTaskConfig {
@@ -219,6 +226,15 @@ fn url_encode(bytes: impl AsRef<[u8]>) -> String {
     form_urlencoded::byte_serialize(bytes.as_ref()).collect()
 }
 
+// Tracks are told apart by their gid, but local files don't have one.
+fn track_ref_is_for(track_ref: &TrackRef, track_id: &SpotifyId) -> bool {
+    match track_id.audio_type {
+        SpotifyAudioType::Local => SpotifyId::from_local_uri(track_ref.get_uri())
+            .map_or(false, |local_id| local_id == *track_id),
+        _ => track_ref.get_gid() == track_id.to_raw(),
+    }
+}
+
 impl Spirc {
     pub fn new(
         config: ConnectConfig,
@@ -273,6 +289,8 @@ impl Spirc {
             state: initial_state(),
             play_request_id: None,
             play_status: SpircPlayStatus::Stopped,
+            seek_pending: false,
+            speed: 1.0,
 
             subscription,
             sender,
@@ -328,6 +346,14 @@ impl Spirc {
     pub fn shuffle(&self) {
         let _ = self.commands.send(SpircCommand::Shuffle);
     }
+    pub fn set_sleep_timer(&self, timer: Option<SleepTimer>) {
+        let _ = self.commands.send(SpircCommand::SetSleepTimer(timer));
+    }
+    // There is no message for the speed in the Connect protocol, so other devices extrapolate
+    // the position at normal speed in between the updates they get.
+    pub fn set_speed(&self, speed: f64) {
+        let _ = self.commands.send(SpircCommand::SetSpeed(speed));
+    }
 }
 
 impl SpircTask {
@@ -486,10 +512,24 @@ impl SpircTask {
             SpircCommand::Shuffle => {
                 CommandSender::new(self, MessageType::kMessageTypeShuffle).send();
             }
+            SpircCommand::SetSleepTimer(timer) => self.player.set_sleep_timer(timer),
+            SpircCommand::SetSpeed(speed) => self.player.set_speed(speed),
         }
     }
 
     fn handle_player_event(&mut self, event: PlayerEvent) {
+        // The player pauses on its own, and the Paused event that follows is only taken as an
+        // update of the position otherwise.
+        if let PlayerEvent::SleepTimerFired { .. } = event {
+            self.handle_sleep_timer_fired();
+            return;
+        }
+
+        if let PlayerEvent::SpeedChanged { speed } = event {
+            self.handle_speed_changed(speed);
+            return;
+        }
+
         // we only process events if the play_request_id matches. If it doesn't, it is
         // an event that belongs to a previous track and only arrives now due to a race
         // condition. In this case we have updated the state already and don't want to
@@ -500,13 +540,16 @@ impl SpircTask {
                     PlayerEvent::EndOfTrack { .. } => self.handle_end_of_track(),
                     PlayerEvent::Loading { .. } => self.notify(None, false),
                     PlayerEvent::Playing { position_ms, .. } => {
-                        let new_nominal_start_time = self.now_ms() - position_ms as i64;
+                        let new_nominal_start_time = self.nominal_start_time(position_ms);
+                        let seeked = std::mem::take(&mut self.seek_pending);
                         match self.play_status {
                             SpircPlayStatus::Playing {
                                 ref mut nominal_start_time,
                                 ..
                             } => {
-                                if (*nominal_start_time - new_nominal_start_time).abs() > 100 {
+                                if seeked
+                                    || (*nominal_start_time - new_nominal_start_time).abs() > 100
+                                {
                                     *nominal_start_time = new_nominal_start_time;
                                     self.update_state_position(position_ms);
                                     self.notify(None, true);
@@ -530,6 +573,7 @@ impl SpircTask {
                         position_ms: new_position_ms,
                         ..
                     } => {
+                        self.seek_pending = false;
                         match self.play_status {
                             SpircPlayStatus::Paused {
                                 ref mut position_ms,
@@ -704,9 +748,7 @@ impl SpircTask {
                 {
                     if preloading_of_next_track_triggered {
                         // Get the next track_id in the playlist
-                        if let Some(track_id) = self.preview_next_track() {
-                            self.player.preload(track_id);
-                        }
+                        self.preload_next_track();
                     }
                 }
             }
@@ -748,7 +790,7 @@ impl SpircTask {
                 self.state.set_status(PlayStatus::kPlayStatusPlay);
                 self.update_state_position(position_ms);
                 self.play_status = SpircPlayStatus::Playing {
-                    nominal_start_time: self.now_ms() as i64 - position_ms as i64,
+                    nominal_start_time: self.nominal_start_time(position_ms),
                     preloading_of_next_track_triggered,
                 };
             }
@@ -780,7 +822,7 @@ impl SpircTask {
             } => {
                 self.player.pause();
                 self.state.set_status(PlayStatus::kPlayStatusPause);
-                let position_ms = (self.now_ms() - nominal_start_time) as u32;
+                let position_ms = self.position_since(nominal_start_time);
                 self.update_state_position(position_ms);
                 self.play_status = SpircPlayStatus::Paused {
                     position_ms,
@@ -795,10 +837,34 @@ impl SpircTask {
         }
     }
 
+    fn handle_sleep_timer_fired(&mut self) {
+        match self.play_status {
+            SpircPlayStatus::Playing {
+                nominal_start_time,
+                preloading_of_next_track_triggered,
+            } => {
+                self.state.set_status(PlayStatus::kPlayStatusPause);
+                let position_ms = self.position_since(nominal_start_time);
+                self.update_state_position(position_ms);
+                self.notify(None, true);
+                self.play_status = SpircPlayStatus::Paused {
+                    position_ms,
+                    preloading_of_next_track_triggered,
+                };
+            }
+            // The track is loaded paused.
+            SpircPlayStatus::LoadingPlay { position_ms } => {
+                self.play_status = SpircPlayStatus::LoadingPause { position_ms };
+            }
+            _ => (),
+        }
+    }
+
     fn handle_seek(&mut self, position_ms: u32) {
         self.update_state_position(position_ms);
         self.player.seek(position_ms);
-        let now = self.now_ms();
+        self.seek_pending = !matches!(self.play_status, SpircPlayStatus::Stopped);
+        let new_nominal_start_time = self.nominal_start_time(position_ms);
         match self.play_status {
             SpircPlayStatus::Stopped => (),
             SpircPlayStatus::LoadingPause {
@@ -814,10 +880,26 @@ impl SpircTask {
             SpircPlayStatus::Playing {
                 ref mut nominal_start_time,
                 ..
-            } => *nominal_start_time = now - position_ms as i64,
+            } => *nominal_start_time = new_nominal_start_time,
         };
     }
 
+    fn handle_speed_changed(&mut self, speed: f64) {
+        // The position so far was reached at the old speed.
+        let position_ms = self.position();
+        self.speed = speed;
+        let new_nominal_start_time = self.nominal_start_time(position_ms);
+        if let SpircPlayStatus::Playing {
+            ref mut nominal_start_time,
+            ..
+        } = self.play_status
+        {
+            *nominal_start_time = new_nominal_start_time;
+            self.update_state_position(position_ms);
+            self.notify(None, true);
+        }
+    }
+
     fn consume_queued_track(&mut self) -> usize {
         // Removes current track if it is queued
         // Returns the index of the next track
@@ -832,9 +914,23 @@ impl SpircTask {
         }
     }
 
-    fn preview_next_track(&mut self) -> Option<SpotifyId> {
-        self.get_track_id_to_play_from_playlist(self.state.get_playing_track_index() + 1)
-            .map(|(track_id, _)| track_id)
+    fn preload_next_track(&mut self) {
+        let next_index = self.state.get_playing_track_index() + 1;
+        if let Some((track_id, index)) = self.get_track_id_to_play_from_playlist(next_index) {
+            match self.local_uri(track_id, index) {
+                Some(uri) => self.player.preload_local(track_id, uri),
+                None => self.player.preload(track_id),
+            }
+        }
+    }
+
+    // The player can't tell the URI of a local file from its id, it gets it from the track ref.
+    fn local_uri(&self, track_id: SpotifyId, index: u32) -> Option<String> {
+        if track_id.audio_type == SpotifyAudioType::Local {
+            Some(self.state.get_track()[index as usize].get_uri().to_owned())
+        } else {
+            None
+        }
     }
 
     fn handle_preload_next_track(&mut self) {
@@ -849,9 +945,7 @@ impl SpircTask {
                 ..
             } => {
                 *preloading_of_next_track_triggered = true;
-                if let Some(track_id) = self.preview_next_track() {
-                    self.player.preload(track_id);
-                }
+                self.preload_next_track();
             }
             SpircPlayStatus::LoadingPause { .. }
             | SpircPlayStatus::LoadingPlay { .. }
@@ -863,9 +957,11 @@ impl SpircTask {
     fn handle_unavailable(&mut self, track_id: SpotifyId) {
         let unavailables = self.get_track_index_for_spotify_id(&track_id, 0);
         for &index in unavailables.iter() {
-            debug_assert_eq!(self.state.get_track()[index].get_gid(), track_id.to_raw());
+            debug_assert!(track_ref_is_for(&self.state.get_track()[index], &track_id));
             let mut unplayable_track_ref = TrackRef::new();
             unplayable_track_ref.set_gid(self.state.get_track()[index].get_gid().to_vec());
+            // local files only have their URI
+            unplayable_track_ref.set_uri(self.state.get_track()[index].get_uri().to_owned());
             // Misuse context field to flag the track
             unplayable_track_ref.set_context(String::from("NonPlayable"));
             std::mem::swap(
@@ -992,10 +1088,19 @@ impl SpircTask {
             | SpircPlayStatus::Paused { position_ms, .. } => position_ms,
             SpircPlayStatus::Playing {
                 nominal_start_time, ..
-            } => (self.now_ms() - nominal_start_time) as u32,
+            } => self.position_since(nominal_start_time),
         }
     }
 
+    // The time at which playback would have started to reach `position_ms` by now.
+    fn nominal_start_time(&mut self, position_ms: u32) -> i64 {
+        self.now_ms() - (position_ms as f64 / self.speed) as i64
+    }
+
+    fn position_since(&mut self, nominal_start_time: i64) -> u32 {
+        ((self.now_ms() - nominal_start_time) as f64 * self.speed) as u32
+    }
+
     fn resolve_station(&self, uri: &str) -> BoxedFuture<Result<serde_json::Value, MercuryError>> {
         let radio_uri = format!("hm://radio-apollo/v3/stations/{}", uri);
 
@@ -1108,6 +1213,10 @@ impl SpircTask {
 
     // should this be a method of SpotifyId directly?
     fn get_spotify_id_for_track(&self, track_ref: &TrackRef) -> Result<SpotifyId, SpotifyIdError> {
+        if track_ref.get_uri().starts_with("spotify:local:") {
+            return SpotifyId::from_local_uri(track_ref.get_uri());
+        }
+
         SpotifyId::from_raw(track_ref.get_gid()).or_else(|_| {
             let uri = track_ref.get_uri();
             debug!("Malformed or no gid, attempting to parse URI <{}>", uri);
@@ -1124,7 +1233,7 @@ impl SpircTask {
         let index: Vec<usize> = self.state.get_track()[start_index..]
             .iter()
             .enumerate()
-            .filter(|&(_, track_ref)| track_ref.get_gid() == track_id.to_raw())
+            .filter(|&(_, track_ref)| track_ref_is_for(track_ref, track_id))
             .map(|(idx, _)| start_index + idx)
             .collect();
         // Sanity check
@@ -1197,7 +1306,13 @@ impl SpircTask {
             Some((track, index)) => {
                 self.state.set_playing_track_index(index);
 
-                self.play_request_id = Some(self.player.load(track, start_playing, position_ms));
+                let play_request_id = match self.local_uri(track, index) {
+                    Some(uri) => self
+                        .player
+                        .load_local(track, uri, start_playing, position_ms),
+                    None => self.player.load(track, start_playing, position_ms),
+                };
+                self.play_request_id = Some(play_request_id);
 
                 self.update_state_position(position_ms);
                 if start_playing {
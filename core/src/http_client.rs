@@ -0,0 +1,118 @@
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, LOCATION};
+use hyper::{Body, Client, Request, Response, StatusCode};
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_rustls::HttpsConnector;
+use rustls::{ClientConfig, RootCertStore};
+use thiserror::Error;
+use tokio::runtime::Handle;
+use url::Url;
+
+// Browsers give up after about 20 redirects as well.
+const MAX_REDIRECTS: usize = 20;
+
+#[derive(Debug, Error)]
+pub enum HttpClientError {
+    #[error("Invalid URL {0}")]
+    InvalidUrl(String),
+    #[error("HTTP request failed: {0}")]
+    Request(#[from] hyper::Error),
+    #[error("HTTP request failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+    #[error("Too many redirects")]
+    TooManyRedirects,
+}
+
+fn tls_config() -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config.root_store = match rustls_native_certs::load_native_certs() {
+        Ok(roots) => roots,
+        // The certificates that could be loaded are used, and plain http still works.
+        Err((roots, e)) => {
+            warn!("Unable to load CA certificates: {}", e);
+            roots.unwrap_or_else(RootCertStore::empty)
+        }
+    };
+    config
+}
+
+// A client for HTTP(S) requests outside of the access point connection, e.g. for content that is
+// hosted elsewhere. It goes through the proxy of the session, if any, and requests are run on the
+// runtime of the session, so that they can also be made from the player's threads.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client<HttpsConnector<ProxyConnector<HttpConnector>>>,
+    handle: Handle,
+}
+
+impl HttpClient {
+    pub fn new(proxy: Option<&Url>, handle: Handle) -> Self {
+        let mut http = HttpConnector::new();
+        // Requests for https URLs are passed on to the TLS connector wrapped around this one.
+        http.enforce_http(false);
+
+        let mut proxy_connector = ProxyConnector::unsecured(http);
+        if let Some(url) = proxy {
+            // Panic safety: all URLs are valid URIs
+            let uri = url.to_string().parse().unwrap();
+            proxy_connector.add_proxy(Proxy::new(Intercept::All, uri));
+        }
+
+        let connector = HttpsConnector::from((proxy_connector, tls_config()));
+
+        Self {
+            client: Client::builder().build(connector),
+            handle,
+        }
+    }
+
+    pub async fn request(&self, req: Request<Body>) -> Result<Response<Body>, HttpClientError> {
+        let client = self.client.clone();
+        Ok(self
+            .handle
+            .spawn(async move { client.request(req).await })
+            .await??)
+    }
+
+    // Gets a URL with the given headers, following redirects. Returns the URL that the response
+    // came from along with it.
+    pub async fn get(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+    ) -> Result<(Url, Response<Body>), HttpClientError> {
+        let mut url = Url::parse(url).map_err(|_| HttpClientError::InvalidUrl(url.to_string()))?;
+
+        for _ in 0..MAX_REDIRECTS {
+            let mut req = Request::get(url.as_str())
+                .body(Body::empty())
+                .map_err(|_| HttpClientError::InvalidUrl(url.to_string()))?;
+            req.headers_mut().extend(headers.clone());
+
+            let response = self.request(req).await?;
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok());
+
+            match (response.status(), location) {
+                (
+                    StatusCode::MOVED_PERMANENTLY
+                    | StatusCode::FOUND
+                    | StatusCode::SEE_OTHER
+                    | StatusCode::TEMPORARY_REDIRECT
+                    | StatusCode::PERMANENT_REDIRECT,
+                    Some(location),
+                ) => {
+                    url = url
+                        .join(location)
+                        .map_err(|_| HttpClientError::InvalidUrl(location.to_string()))?;
+                    debug!("Following redirect to <{}>", url);
+                }
+                _ => return Ok((url, response)),
+            }
+        }
+
+        Err(HttpClientError::TooManyRedirects)
+    }
+}
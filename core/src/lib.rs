@@ -17,6 +17,7 @@ pub mod config;
 mod connection;
 #[doc(hidden)]
 pub mod diffie_hellman;
+pub mod http_client;
 pub mod keymaster;
 pub mod mercury;
 mod proxytunnel;
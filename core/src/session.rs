@@ -23,6 +23,7 @@ use crate::cache::Cache;
 use crate::channel::ChannelManager;
 use crate::config::SessionConfig;
 use crate::connection::{self, AuthenticationError};
+use crate::http_client::HttpClient;
 use crate::mercury::MercuryManager;
 
 #[derive(Debug, Error)]
@@ -50,6 +51,7 @@ struct SessionInternal {
     channel: OnceCell<ChannelManager>,
     mercury: OnceCell<MercuryManager>,
     cache: Option<Arc<Cache>>,
+    http_client: OnceCell<HttpClient>,
 
     handle: tokio::runtime::Handle,
 
@@ -114,6 +116,7 @@ impl Session {
             }),
             tx_connection: sender_tx,
             cache: cache.map(Arc::new),
+            http_client: OnceCell::new(),
             audio_key: OnceCell::new(),
             channel: OnceCell::new(),
             mercury: OnceCell::new(),
@@ -210,6 +213,12 @@ impl Session {
         self.0.tx_connection.send((cmd, data)).unwrap();
     }
 
+    pub fn http_client(&self) -> &HttpClient {
+        self.0
+            .http_client
+            .get_or_init(|| HttpClient::new(self.0.config.proxy.as_ref(), self.0.handle.clone()))
+    }
+
     pub fn cache(&self) -> Option<&Arc<Cache>> {
         self.0.cache.as_ref()
     }
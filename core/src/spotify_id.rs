@@ -3,10 +3,14 @@
 use std::convert::TryInto;
 use std::fmt;
 
+use sha1::{Digest, Sha1};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SpotifyAudioType {
     Track,
     Podcast,
+    // a file on the user's computer, `spotify:local:{artist}:{album}:{title}:{duration}`
+    Local,
     NonPlayable,
 }
 
@@ -15,6 +19,7 @@ impl From<&str> for SpotifyAudioType {
         match v {
             "track" => SpotifyAudioType::Track,
             "episode" => SpotifyAudioType::Podcast,
+            "local" => SpotifyAudioType::Local,
             _ => SpotifyAudioType::NonPlayable,
         }
     }
@@ -25,6 +30,7 @@ impl From<SpotifyAudioType> for &str {
         match audio_type {
             SpotifyAudioType::Track => "track",
             SpotifyAudioType::Podcast => "episode",
+            SpotifyAudioType::Local => "local",
             SpotifyAudioType::NonPlayable => "unknown",
         }
     }
@@ -109,13 +115,38 @@ impl SpotifyId {
         }
     }
 
+    /// Creates a `SpotifyId` of type `SpotifyAudioType::Local` for the URI of a local file, in
+    /// the form `spotify:local:{artist}:{album}:{title}:{duration}`.
+    ///
+    /// The ID is derived from the URI, and the same URI always results in the same ID. The URI
+    /// can't be told from the ID though, so it has to be kept along with it where it is needed.
+    pub fn from_local_uri(uri: &str) -> Result<SpotifyId, SpotifyIdError> {
+        if !uri.starts_with("spotify:local:") {
+            return Err(SpotifyIdError);
+        }
+
+        let hash = Sha1::digest(uri.as_bytes());
+        // Panic safety: a SHA-1 hash has more than 16 bytes
+        let id = u128::from_be_bytes(hash[..SpotifyId::SIZE].try_into().unwrap());
+
+        Ok(SpotifyId {
+            id,
+            audio_type: SpotifyAudioType::Local,
+        })
+    }
+
     /// Parses a [Spotify URI] into a `SpotifyId`.
     ///
     /// `uri` is expected to be in the canonical form `spotify:{type}:{id}`, where `{type}`
     /// can be arbitrary while `{id}` is a 22-character long, base62 encoded Spotify ID.
+    /// URIs of local files are handled by [`SpotifyId::from_local_uri`].
     ///
     /// [Spotify URI]: https://developer.spotify.com/documentation/web-api/#spotify-uris-and-ids
     pub fn from_uri(src: &str) -> Result<SpotifyId, SpotifyIdError> {
+        if src.starts_with("spotify:local:") {
+            return SpotifyId::from_local_uri(src);
+        }
+
         let src = src.strip_prefix("spotify:").ok_or(SpotifyIdError)?;
 
         if src.len() <= SpotifyId::SIZE_BASE62 {
@@ -199,7 +230,8 @@ impl SpotifyId {
     /// Spotify ID.
     ///
     /// If the `SpotifyId` has an associated type unrecognized by the library, `{type}` will
-    /// be encoded as `unknown`.
+    /// be encoded as `unknown`. Local files are encoded as `spotify:local:{id}`, which is not the
+    /// URI that they were created from.
     ///
     /// [Spotify URI]: https://developer.spotify.com/documentation/web-api/#spotify-uris-and-ids
     pub fn to_uri(&self) -> String {
@@ -419,6 +451,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn local_uri() {
+        let uri = "spotify:local:Daft+Punk:Discovery:One+More+Time:320";
+        let id = SpotifyId::from_uri(uri).unwrap();
+
+        assert_eq!(id.audio_type, SpotifyAudioType::Local);
+        assert_eq!(id, SpotifyId::from_local_uri(uri).unwrap());
+        assert_ne!(
+            id,
+            SpotifyId::from_uri("spotify:local:Daft+Punk:Discovery:Aerodynamic:212").unwrap()
+        );
+        assert_eq!(id.to_uri(), format!("spotify:local:{}", id.to_base62()));
+
+        assert_eq!(
+            SpotifyId::from_local_uri("spotify:track:5sWHDYs0csV6RS48xBl0tH"),
+            Err(SpotifyIdError)
+        );
+    }
+
     #[test]
     fn from_raw() {
         for c in &CONV_VALID {
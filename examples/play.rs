@@ -30,8 +30,9 @@ async fn main() {
         .await
         .unwrap();
 
+    let sample_rate = player_config.sample_rate;
     let (mut player, _) = Player::new(player_config, session, None, move || {
-        backend(None, audio_format)
+        backend(None, audio_format, sample_rate)
     });
 
     player.load(track, true, 0);
@@ -53,6 +53,18 @@ where
         && (!has_allowed || countrylist_contains(allowed.as_str(), country))
 }
 
+fn parse_covers(images: &[protocol::metadata::Image]) -> Vec<FileId> {
+    images
+        .iter()
+        .filter(|image| image.has_file_id())
+        .map(|image| {
+            let mut dst = [0u8; 20];
+            dst.clone_from_slice(image.get_file_id());
+            FileId(dst)
+        })
+        .collect()
+}
+
 // A wrapper with fields the player needs
 #[derive(Debug, Clone)]
 pub struct AudioItem {
@@ -60,9 +72,19 @@ pub struct AudioItem {
     pub uri: String,
     pub files: HashMap<FileFormat, FileId>,
     pub name: String,
+    pub artists: Vec<String>,
+    // the album, or the show for episodes
+    pub album: String,
+    // the id of the album or show, which local files don't have
+    pub album_id: Option<SpotifyId>,
     pub duration: i32,
     pub available: bool,
     pub alternatives: Option<Vec<SpotifyId>>,
+    // the cover art of the album, or of the episode
+    pub covers: Vec<FileId>,
+    pub is_podcast: bool,
+    // where episodes that aren't hosted by Spotify can be streamed from
+    pub external_url: Option<String>,
 }
 
 impl AudioItem {
@@ -70,7 +92,8 @@ impl AudioItem {
         match id.audio_type {
             SpotifyAudioType::Track => Track::get_audio_item(session, id).await,
             SpotifyAudioType::Podcast => Episode::get_audio_item(session, id).await,
-            SpotifyAudioType::NonPlayable => Err(MercuryError),
+            // local files are only known to the client that added them
+            SpotifyAudioType::Local | SpotifyAudioType::NonPlayable => Err(MercuryError),
         }
     }
 }
@@ -83,15 +106,34 @@ trait AudioFiles {
 #[async_trait]
 impl AudioFiles for Track {
     async fn get_audio_item(session: &Session, id: SpotifyId) -> Result<AudioItem, MercuryError> {
-        let item = Self::get(session, id).await?;
+        // The track message also carries the names of its album and artists, and the covers of
+        // the album.
+        let msg = Self::get_message(session, id).await?;
+        let item = Self::parse(&msg, session);
+        let album = Album::parse(msg.get_album(), session);
         Ok(AudioItem {
             id,
             uri: format!("spotify:track:{}", id.to_base62()),
             files: item.files,
             name: item.name,
+            artists: msg
+                .get_artist()
+                .iter()
+                .map(|artist| artist.get_name().to_owned())
+                .collect(),
+            album: album.name,
+            album_id: Some(item.album),
             duration: item.duration,
             available: item.available,
             alternatives: Some(item.alternatives),
+            // older albums only have the covers that predate cover groups
+            covers: if album.covers.is_empty() {
+                parse_covers(msg.get_album().get_cover())
+            } else {
+                album.covers
+            },
+            is_podcast: false,
+            external_url: None,
         })
     }
 }
@@ -99,16 +141,32 @@ impl AudioFiles for Track {
 #[async_trait]
 impl AudioFiles for Episode {
     async fn get_audio_item(session: &Session, id: SpotifyId) -> Result<AudioItem, MercuryError> {
-        let item = Self::get(session, id).await?;
+        let msg = Self::get_message(session, id).await?;
+        let item = Self::parse(&msg, session);
+        let show = msg.get_show();
 
         Ok(AudioItem {
             id,
             uri: format!("spotify:episode:{}", id.to_base62()),
             files: item.files,
             name: item.name,
+            artists: if show.get_publisher().is_empty() {
+                Vec::new()
+            } else {
+                vec![show.get_publisher().to_owned()]
+            },
+            album: show.get_name().to_owned(),
+            album_id: Some(item.show),
             duration: item.duration,
             available: item.available,
             alternatives: None,
+            covers: item.covers,
+            is_podcast: true,
+            external_url: if item.external_url.is_empty() {
+                None
+            } else {
+                Some(item.external_url)
+            },
         })
     }
 }
@@ -120,12 +178,15 @@ pub trait Metadata: Send + Sized + 'static {
     fn request_url(id: SpotifyId) -> String;
     fn parse(msg: &Self::Message, session: &Session) -> Self;
 
-    async fn get(session: &Session, id: SpotifyId) -> Result<Self, MercuryError> {
+    async fn get_message(session: &Session, id: SpotifyId) -> Result<Self::Message, MercuryError> {
         let uri = Self::request_url(id);
         let response = session.mercury().get(uri).await?;
         let data = response.payload.first().expect("Empty payload");
-        let msg = Self::Message::parse_from_bytes(data).unwrap();
+        Ok(Self::Message::parse_from_bytes(data).unwrap())
+    }
 
+    async fn get(session: &Session, id: SpotifyId) -> Result<Self, MercuryError> {
+        let msg = Self::get_message(session, id).await?;
         Ok(Self::parse(&msg, session))
     }
 }
@@ -257,17 +318,7 @@ impl Metadata for Album {
             .map(|track| SpotifyId::from_raw(track.get_gid()).unwrap())
             .collect::<Vec<_>>();
 
-        let covers = msg
-            .get_cover_group()
-            .get_image()
-            .iter()
-            .filter(|image| image.has_file_id())
-            .map(|image| {
-                let mut dst = [0u8; 20];
-                dst.clone_from_slice(image.get_file_id());
-                FileId(dst)
-            })
-            .collect::<Vec<_>>();
+        let covers = parse_covers(msg.get_cover_group().get_image());
 
         Album {
             id: SpotifyId::from_raw(msg.get_gid()).unwrap(),
@@ -369,17 +420,7 @@ impl Metadata for Episode {
             })
             .collect();
 
-        let covers = msg
-            .get_covers()
-            .get_image()
-            .iter()
-            .filter(|image| image.has_file_id())
-            .map(|image| {
-                let mut dst = [0u8; 20];
-                dst.clone_from_slice(image.get_file_id());
-                FileId(dst)
-            })
-            .collect::<Vec<_>>();
+        let covers = parse_covers(msg.get_covers().get_image());
 
         Episode {
             id: SpotifyId::from_raw(msg.get_gid()).unwrap(),
@@ -411,17 +452,7 @@ impl Metadata for Show {
             .map(|episode| SpotifyId::from_raw(episode.get_gid()).unwrap())
             .collect::<Vec<_>>();
 
-        let covers = msg
-            .get_covers()
-            .get_image()
-            .iter()
-            .filter(|image| image.has_file_id())
-            .map(|image| {
-                let mut dst = [0u8; 20];
-                dst.clone_from_slice(image.get_file_id());
-                FileId(dst)
-            })
-            .collect::<Vec<_>>();
+        let covers = parse_covers(msg.get_covers().get_image());
 
         Show {
             id: SpotifyId::from_raw(msg.get_gid()).unwrap(),
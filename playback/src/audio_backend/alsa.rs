@@ -2,7 +2,7 @@ use super::{Open, Sink, SinkAsBytes, SinkError, SinkResult};
 use crate::config::AudioFormat;
 use crate::convert::Converter;
 use crate::decoder::AudioPacket;
-use crate::{NUM_CHANNELS, SAMPLE_RATE};
+use crate::NUM_CHANNELS;
 use alsa::device_name::HintIter;
 use alsa::pcm::{Access, Format, Frames, HwParams, PCM};
 use alsa::{Direction, ValueOr};
@@ -10,8 +10,9 @@ use std::cmp::min;
 use std::process::exit;
 use thiserror::Error;
 
-const MAX_BUFFER: Frames = (SAMPLE_RATE / 2) as Frames;
-const MIN_BUFFER: Frames = (SAMPLE_RATE / 10) as Frames;
+// Buffer sizes in milliseconds, converted to frames at the output sample rate.
+const MAX_BUFFER_MS: Frames = 500;
+const MIN_BUFFER_MS: Frames = 100;
 const ZERO_FRAMES: Frames = 0;
 
 const MAX_PERIOD_DIVISOR: Frames = 4;
@@ -103,10 +104,11 @@ pub struct AlsaSink {
     pcm: Option<PCM>,
     format: AudioFormat,
     device: String,
+    sample_rate: u32,
     period_buffer: Vec<u8>,
 }
 
-fn list_compatible_devices() -> SinkResult<()> {
+fn list_compatible_devices(sample_rate: u32) -> SinkResult<()> {
     let i = HintIter::new_str(None, "pcm").map_err(AlsaError::Parsing)?;
 
     println!("\n\n\tCompatible alsa device(s):\n");
@@ -118,10 +120,10 @@ fn list_compatible_devices() -> SinkResult<()> {
                 if let Ok(pcm) = PCM::new(&name, Direction::Playback, false) {
                     if let Ok(hwp) = HwParams::any(&pcm) {
                         // Only show devices that support
-                        // 2 ch 44.1 Interleaved.
+                        // 2 ch Interleaved at the output sample rate.
 
                         if hwp.set_access(Access::RWInterleaved).is_ok()
-                            && hwp.set_rate(SAMPLE_RATE, ValueOr::Nearest).is_ok()
+                            && hwp.set_rate(sample_rate, ValueOr::Nearest).is_ok()
                             && hwp.set_channels(NUM_CHANNELS as u32).is_ok()
                         {
                             let mut supported_formats = vec![];
@@ -166,7 +168,7 @@ fn list_compatible_devices() -> SinkResult<()> {
     Ok(())
 }
 
-fn open_device(dev_name: &str, format: AudioFormat) -> SinkResult<(PCM, usize)> {
+fn open_device(dev_name: &str, format: AudioFormat, sample_rate: u32) -> SinkResult<(PCM, usize)> {
     let pcm = PCM::new(dev_name, Direction::Playback, false).map_err(|e| AlsaError::PcmSetUp {
         device: dev_name.to_string(),
         e,
@@ -191,10 +193,10 @@ fn open_device(dev_name: &str, format: AudioFormat) -> SinkResult<(PCM, usize)>
                 e,
             })?;
 
-        hwp.set_rate(SAMPLE_RATE, ValueOr::Nearest).map_err(|e| {
+        hwp.set_rate(sample_rate, ValueOr::Nearest).map_err(|e| {
             AlsaError::UnsupportedSampleRate {
                 device: dev_name.to_string(),
-                samplerate: SAMPLE_RATE,
+                samplerate: sample_rate,
                 e,
             }
         })?;
@@ -215,6 +217,9 @@ fn open_device(dev_name: &str, format: AudioFormat) -> SinkResult<(PCM, usize)>
         // error state.
         let hwp_clone = hwp.clone();
 
+        let max_buffer = sample_rate as Frames * MAX_BUFFER_MS / 1000;
+        let min_buffer = sample_rate as Frames * MIN_BUFFER_MS / 1000;
+
         // At a sampling rate of 44100:
         // The largest buffer is 22050 Frames (500ms) with 5512 Frame periods (125ms).
         // The smallest buffer is 4410 Frames (100ms) with 441 Frame periods (10ms).
@@ -245,7 +250,7 @@ fn open_device(dev_name: &str, format: AudioFormat) -> SinkResult<(PCM, usize)>
             };
 
             let buffer_size = if min < max {
-                match (MIN_BUFFER..=MAX_BUFFER)
+                match (min_buffer..=max_buffer)
                     .rev()
                     .find(|f| (min..=max).contains(f))
                 {
@@ -273,8 +278,8 @@ fn open_device(dev_name: &str, format: AudioFormat) -> SinkResult<(PCM, usize)>
             if buffer_size == ZERO_FRAMES {
                 trace!(
                     "Desired Buffer Frame range: {:?} - {:?}",
-                    MIN_BUFFER,
-                    MAX_BUFFER
+                    min_buffer,
+                    max_buffer
                 );
 
                 trace!(
@@ -398,9 +403,9 @@ fn open_device(dev_name: &str, format: AudioFormat) -> SinkResult<(PCM, usize)>
 }
 
 impl Open for AlsaSink {
-    fn open(device: Option<String>, format: AudioFormat) -> Self {
+    fn open(device: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
         let name = match device.as_deref() {
-            Some("?") => match list_compatible_devices() {
+            Some("?") => match list_compatible_devices(sample_rate) {
                 Ok(_) => {
                     exit(0);
                 }
@@ -414,12 +419,16 @@ impl Open for AlsaSink {
         }
         .to_string();
 
-        info!("Using AlsaSink with format: {:?}", format);
+        info!(
+            "Using AlsaSink with format: {:?}, sample rate: {} Hz",
+            format, sample_rate
+        );
 
         Self {
             pcm: None,
             format,
             device: name,
+            sample_rate,
             period_buffer: vec![],
         }
     }
@@ -428,7 +437,7 @@ impl Open for AlsaSink {
 impl Sink for AlsaSink {
     fn start(&mut self) -> SinkResult<()> {
         if self.pcm.is_none() {
-            let (pcm, bytes_per_period) = open_device(&self.device, self.format)?;
+            let (pcm, bytes_per_period) = open_device(&self.device, self.format, self.sample_rate)?;
             self.pcm = Some(pcm);
 
             if self.period_buffer.capacity() != bytes_per_period {
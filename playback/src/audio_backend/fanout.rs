@@ -0,0 +1,596 @@
+use super::{Open, Sink, SinkBuilder, SinkError, SinkResult, StreamProperties, BACKENDS};
+use crate::config::AudioFormat;
+use crate::convert::Converter;
+use crate::decoder::AudioPacket;
+use crate::dither::DithererBuilder;
+use crate::NUM_CHANNELS;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Packets that may wait for an output before writing to it blocks.
+const MAX_QUEUED_PACKETS: usize = 4;
+
+// How long an output may keep a packet, a start or a stop waiting before it is
+// considered stalled and left out.
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+enum OutputItem {
+    Start,
+    Stop,
+    Write(AudioPacket, Option<DithererBuilder>),
+}
+
+#[derive(Default)]
+struct OutputState {
+    queue: VecDeque<OutputItem>,
+    // frames in the queue and in the packet that is being written
+    queued_frames: usize,
+    properties: Option<StreamProperties>,
+    reply: Option<SinkResult<()>>,
+    error: Option<SinkError>,
+    latency: Option<Duration>,
+    exited: bool,
+    shutdown: bool,
+}
+
+#[derive(Default)]
+struct OutputShared {
+    state: Mutex<OutputState>,
+    condvar: Condvar,
+}
+
+impl OutputShared {
+    fn lock(&self) -> MutexGuard<'_, OutputState> {
+        // The worker calls into its sink without holding the lock, so a sink that panics
+        // leaves the state usable.
+        self.state
+            .lock()
+            .expect("<FanoutSink> Output State Lock Poisoned")
+    }
+}
+
+// Lets the waiting side know when a worker is gone, also when its sink panicked.
+struct ExitGuard(Arc<OutputShared>);
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        self.0.lock().exited = true;
+        self.0.condvar.notify_all();
+    }
+}
+
+// Every output is written by a thread of its own, so that an output that blocks doesn't hold
+// up the others. The sink is created on that thread, as sinks don't need to be `Send`.
+fn run_output(shared: Arc<OutputShared>, build: impl FnOnce() -> Box<dyn Sink>) {
+    let _guard = ExitGuard(shared.clone());
+    let mut sink = build();
+    // every output converts on its own, so that it can have its own format and ditherer state
+    let mut converter = None;
+
+    loop {
+        let (properties, item) = {
+            let mut state = shared.lock();
+            while !state.shutdown && state.properties.is_none() && state.queue.is_empty() {
+                state = shared
+                    .condvar
+                    .wait(state)
+                    .expect("<FanoutSink> Output State Lock Poisoned");
+            }
+            if state.shutdown {
+                return;
+            }
+            (state.properties.take(), state.queue.pop_front())
+        };
+
+        if let Some(properties) = properties {
+            sink.set_stream_properties(&properties);
+        }
+
+        let (result, frames, is_request) = match item {
+            None => continue,
+            Some(OutputItem::Start) => (sink.start(), 0, true),
+            Some(OutputItem::Stop) => (sink.stop(), 0, true),
+            Some(OutputItem::Write(packet, dither_config)) => {
+                let frames = packet_frames(&packet);
+                let converter = converter.get_or_insert_with(|| Converter::new(dither_config));
+                (sink.write(packet, converter), frames, false)
+            }
+        };
+        let latency = sink.latency();
+
+        let mut state = shared.lock();
+        state.latency = latency;
+        state.queued_frames = state.queued_frames.saturating_sub(frames);
+        if is_request {
+            state.reply = Some(result);
+        } else if let Err(e) = result {
+            state.error.get_or_insert(e);
+        }
+        shared.condvar.notify_all();
+    }
+}
+
+fn packet_frames(packet: &AudioPacket) -> usize {
+    match packet {
+        AudioPacket::Samples(samples) => samples.len() / NUM_CHANNELS as usize,
+        // the length of encoded audio is not known here
+        AudioPacket::OggData(_) => 0,
+    }
+}
+
+struct FanoutOutput {
+    name: String,
+    shared: Arc<OutputShared>,
+    thread: Option<thread::JoinHandle<()>>,
+    sample_rate: u32,
+    stall_timeout: Duration,
+    failed: bool,
+}
+
+impl FanoutOutput {
+    fn spawn(
+        name: String,
+        sample_rate: u32,
+        build: impl FnOnce() -> Box<dyn Sink> + Send + 'static,
+    ) -> Self {
+        let shared = Arc::new(OutputShared::default());
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name(format!("fanout {}", name))
+            .spawn(move || run_output(thread_shared, build))
+            .expect("<FanoutSink> Failed to Spawn Output Thread");
+
+        Self {
+            name,
+            shared,
+            thread: Some(thread),
+            sample_rate,
+            stall_timeout: STALL_TIMEOUT,
+            failed: false,
+        }
+    }
+
+    fn fail(&mut self, e: SinkError) -> SinkError {
+        error!("<FanoutSink> Output {} Failed, {}", self.name, e);
+        self.failed = true;
+        let mut state = self.shared.lock();
+        state.queue.clear();
+        state.queued_frames = 0;
+        e
+    }
+
+    // Waits until `ready` holds for the state, checking for a worker that is gone or
+    // stuck on the way.
+    fn wait<'a>(
+        &self,
+        mut state: MutexGuard<'a, OutputState>,
+        ready: impl Fn(&OutputState) -> bool,
+    ) -> SinkResult<MutexGuard<'a, OutputState>> {
+        let deadline = Instant::now() + self.stall_timeout;
+        loop {
+            if let Some(e) = state.error.take() {
+                return Err(e);
+            }
+            if ready(&state) {
+                return Ok(state);
+            }
+            if state.exited {
+                return Err(SinkError::NotConnected(
+                    "<FanoutSink> Output Exited".to_string(),
+                ));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(SinkError::OnWrite(
+                    "<FanoutSink> Output Stalled".to_string(),
+                ));
+            }
+            state = self
+                .shared
+                .condvar
+                .wait_timeout(state, deadline - now)
+                .expect("<FanoutSink> Output State Lock Poisoned")
+                .0;
+        }
+    }
+
+    fn request(&mut self, item: OutputItem) {
+        let mut state = self.shared.lock();
+        state.reply = None;
+        state.queue.push_back(item);
+        self.shared.condvar.notify_all();
+    }
+
+    fn reply(&mut self) -> SinkResult<()> {
+        let result = self
+            .wait(self.shared.lock(), |state| state.reply.is_some())
+            .and_then(|mut state| state.reply.take().unwrap_or(Ok(())));
+        result.map_err(|e| self.fail(e))
+    }
+
+    fn write(
+        &mut self,
+        packet: AudioPacket,
+        dither_config: Option<DithererBuilder>,
+    ) -> SinkResult<()> {
+        let frames = packet_frames(&packet);
+        let result = self
+            .wait(self.shared.lock(), |state| {
+                state.queue.len() < MAX_QUEUED_PACKETS
+            })
+            .map(|mut state| {
+                state.queued_frames += frames;
+                state
+                    .queue
+                    .push_back(OutputItem::Write(packet, dither_config));
+                self.shared.condvar.notify_all();
+            });
+        result.map_err(|e| self.fail(e))
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        let state = self.shared.lock();
+        let queued = Duration::from_secs_f64(state.queued_frames as f64 / self.sample_rate as f64);
+        state.latency.map(|latency| latency + queued)
+    }
+}
+
+impl Drop for FanoutOutput {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.condvar.notify_all();
+
+        // An output that failed may still be stuck in its sink, don't wait for it.
+        if let Some(thread) = self.thread.take() {
+            if !self.failed {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+// Writes every packet to several outputs. An output that fails or stalls is logged and left
+// out until the sink is started again, so that it does not take the other outputs down with it.
+pub struct FanoutSink {
+    outputs: Vec<FanoutOutput>,
+}
+
+// Parses `BACKEND[@FORMAT][:DEVICE]`, e.g. `alsa@S32:hw:0,0` or `pipe:/tmp/librespot`.
+fn parse_output(
+    spec: &str,
+    default_format: AudioFormat,
+) -> Result<(String, AudioFormat, Option<String>), String> {
+    let mut parts = spec.trim().splitn(2, ':');
+    let head = parts.next().unwrap_or_default();
+    let device = parts.next().map(|device| device.to_string());
+
+    let mut parts = head.splitn(2, '@');
+    let backend = parts.next().unwrap_or_default();
+    let (backend, format) = match parts.next() {
+        Some(format) => (
+            backend,
+            AudioFormat::from_str(format).map_err(|_| format!("Invalid Format {}", format))?,
+        ),
+        None => (head, default_format),
+    };
+
+    if backend.is_empty() {
+        return Err(format!("Missing Backend in {:?}", spec));
+    }
+
+    Ok((backend.to_string(), format, device))
+}
+
+type OutputSpec = (String, SinkBuilder, AudioFormat, Option<String>);
+
+fn parse_outputs(
+    device: Option<&str>,
+    default_format: AudioFormat,
+) -> Result<Vec<OutputSpec>, String> {
+    let outputs = device
+        .unwrap_or_default()
+        .split(FanoutSink::SEPARATOR)
+        .filter(|spec| !spec.trim().is_empty())
+        .map(|spec| {
+            let (backend, format, device) = parse_output(spec, default_format)?;
+            let builder = match super::find(Some(backend.clone())) {
+                Some(_) if backend == FanoutSink::NAME => {
+                    return Err("Outputs can't be fanout sinks themselves".to_string())
+                }
+                Some(builder) => builder,
+                None => return Err(format!("Unknown Backend {}", backend)),
+            };
+            Ok((spec.trim().to_string(), builder, format, device))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if outputs.is_empty() {
+        return Err(format!(
+            "Missing Outputs, specify them as BACKEND[@FORMAT][:DEVICE] separated by {:?}",
+            FanoutSink::SEPARATOR
+        ));
+    }
+
+    Ok(outputs)
+}
+
+impl Open for FanoutSink {
+    fn open(device: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
+        if device.as_deref() == Some("?") {
+            println!(
+                "Specify the outputs as BACKEND[@FORMAT][:DEVICE] separated by {:?}, with BACKEND one of:",
+                Self::SEPARATOR
+            );
+            for (name, _) in BACKENDS.iter().filter(|(name, _)| *name != Self::NAME) {
+                println!("- {}", name);
+            }
+            return Self {
+                outputs: Vec::new(),
+            };
+        }
+
+        info!("Using fanout sink with default format: {:?}", format);
+
+        let outputs = parse_outputs(device.as_deref(), format).unwrap_or_else(|e| {
+            error!("<FanoutSink> {}", e);
+            Vec::new()
+        });
+
+        let outputs = outputs
+            .into_iter()
+            .map(|(name, builder, format, device)| {
+                info!("Adding fanout output {} with format: {:?}", name, format);
+                FanoutOutput::spawn(name, sample_rate, move || {
+                    builder(device, format, sample_rate)
+                })
+            })
+            .collect();
+
+        Self { outputs }
+    }
+}
+
+impl Sink for FanoutSink {
+    fn start(&mut self) -> SinkResult<()> {
+        if self.outputs.is_empty() {
+            return Err(SinkError::InvalidParams(
+                "<FanoutSink> No Outputs".to_string(),
+            ));
+        }
+
+        // Starting is also when failed outputs get another chance. What is still queued
+        // for them is of no use anymore.
+        for output in self.outputs.iter_mut() {
+            {
+                let mut state = output.shared.lock();
+                state.queue.clear();
+                state.queued_frames = 0;
+                state.error = None;
+            }
+            output.request(OutputItem::Start);
+        }
+
+        let mut result = Ok(());
+        let mut any_started = false;
+
+        for output in self.outputs.iter_mut() {
+            match output.reply() {
+                Ok(()) => {
+                    output.failed = false;
+                    any_started = true;
+                }
+                Err(e) => result = Err(e),
+            }
+        }
+
+        if any_started {
+            Ok(())
+        } else {
+            result
+        }
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        for output in self.outputs.iter_mut().filter(|output| !output.failed) {
+            output.request(OutputItem::Stop);
+        }
+
+        let mut result = Ok(());
+        let mut any_stopped = false;
+
+        for output in self.outputs.iter_mut().filter(|output| !output.failed) {
+            match output.reply() {
+                Ok(()) => any_stopped = true,
+                Err(e) => result = Err(e),
+            }
+        }
+
+        if any_stopped {
+            Ok(())
+        } else {
+            result
+        }
+    }
+
+    fn write(&mut self, packet: AudioPacket, converter: &mut Converter) -> SinkResult<()> {
+        let mut result = Err(SinkError::NotConnected(
+            "<FanoutSink> No Working Outputs".to_string(),
+        ));
+        let mut any_written = false;
+
+        for output in self.outputs.iter_mut().filter(|output| !output.failed) {
+            match output.write(packet.clone(), converter.dither_config()) {
+                Ok(()) => any_written = true,
+                Err(e) => result = Err(e),
+            }
+        }
+
+        if any_written {
+            Ok(())
+        } else {
+            result
+        }
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        // Go by the output that is heard last.
+        self.outputs
+            .iter()
+            .filter(|output| !output.failed)
+            .filter_map(|output| output.latency())
+            .max()
+    }
+
+    fn set_stream_properties(&mut self, properties: &StreamProperties) {
+        for output in self.outputs.iter() {
+            output.shared.lock().properties = Some(properties.clone());
+            output.shared.condvar.notify_all();
+        }
+    }
+}
+
+impl FanoutSink {
+    pub const NAME: &'static str = "fanout";
+    pub const SEPARATOR: char = ';';
+
+    // For checking the outputs up front, before anything is opened.
+    pub fn check_device(device: Option<&str>) -> Result<(), String> {
+        parse_outputs(device, AudioFormat::default()).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    struct TestSink {
+        written: Arc<Mutex<usize>>,
+        fail: bool,
+        // the sink blocks on every write until this is dropped
+        block: Option<mpsc::Receiver<()>>,
+    }
+
+    impl Sink for TestSink {
+        fn write(&mut self, _: AudioPacket, _: &mut Converter) -> SinkResult<()> {
+            if let Some(ref block) = self.block {
+                let _ = block.recv();
+            }
+            if self.fail {
+                return Err(SinkError::OnWrite("<TestSink> Broken".to_string()));
+            }
+            *self.written.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    fn output(
+        written: &Arc<Mutex<usize>>,
+        fail: bool,
+        block: Option<mpsc::Receiver<()>>,
+    ) -> FanoutOutput {
+        let written = written.clone();
+        let mut output = FanoutOutput::spawn("test".to_string(), 44100, move || {
+            Box::new(TestSink {
+                written,
+                fail,
+                block,
+            })
+        });
+        output.stall_timeout = Duration::from_millis(100);
+        output
+    }
+
+    fn packet() -> AudioPacket {
+        AudioPacket::Samples(vec![0.0; 4])
+    }
+
+    #[test]
+    fn parse_outputs() {
+        assert_eq!(
+            parse_output("alsa@S32:hw:0,0", AudioFormat::S16),
+            Ok((
+                "alsa".to_string(),
+                AudioFormat::S32,
+                Some("hw:0,0".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_output(" pulseaudio ", AudioFormat::S16),
+            Ok(("pulseaudio".to_string(), AudioFormat::S16, None))
+        );
+        assert!(parse_output("pipe@S12:/tmp/librespot", AudioFormat::S16).is_err());
+        assert!(parse_output("@S16", AudioFormat::S16).is_err());
+
+        assert!(FanoutSink::check_device(Some("pipe:/tmp/a;subprocess@S32:cat")).is_ok());
+        assert!(FanoutSink::check_device(Some("pipe;unknown")).is_err());
+        assert!(FanoutSink::check_device(Some("fanout")).is_err());
+        assert!(FanoutSink::check_device(Some(" ; ")).is_err());
+        assert!(FanoutSink::check_device(None).is_err());
+    }
+
+    #[test]
+    fn bad_device_does_not_panic() {
+        let mut sink = FanoutSink::open(Some("?".to_string()), AudioFormat::S16, 44100);
+        assert!(sink.start().is_err());
+
+        let mut sink = FanoutSink::open(Some("unknown".to_string()), AudioFormat::S16, 44100);
+        assert!(sink.start().is_err());
+    }
+
+    #[test]
+    fn failing_output_is_isolated() {
+        let working = Arc::new(Mutex::new(0));
+        let broken = Arc::new(Mutex::new(0));
+        let mut sink = FanoutSink {
+            outputs: vec![output(&broken, true, None), output(&working, false, None)],
+        };
+        let mut converter = Converter::new(None);
+
+        assert!(sink.start().is_ok());
+        for _ in 0..3 {
+            assert!(sink.write(packet(), &mut converter).is_ok());
+        }
+        assert!(sink.stop().is_ok());
+        assert_eq!(*working.lock().unwrap(), 3);
+        assert!(sink.outputs[0].failed);
+        assert!(!sink.outputs[1].failed);
+
+        let mut sink = FanoutSink {
+            outputs: vec![output(&broken, true, None)],
+        };
+        assert!(sink.start().is_ok());
+        assert!(sink.write(packet(), &mut converter).is_ok());
+        assert!(sink.stop().is_err());
+        assert!(sink.write(packet(), &mut converter).is_err());
+    }
+
+    #[test]
+    fn blocking_output_does_not_stall_the_others() {
+        let working = Arc::new(Mutex::new(0));
+        let blocked = Arc::new(Mutex::new(0));
+        let (unblock, block) = mpsc::channel();
+        let mut sink = FanoutSink {
+            outputs: vec![
+                output(&blocked, false, Some(block)),
+                output(&working, false, None),
+            ],
+        };
+        let mut converter = Converter::new(None);
+
+        assert!(sink.start().is_ok());
+        for _ in 0..2 * MAX_QUEUED_PACKETS {
+            assert!(sink.write(packet(), &mut converter).is_ok());
+        }
+        assert!(sink.stop().is_ok());
+        assert_eq!(*working.lock().unwrap(), 2 * MAX_QUEUED_PACKETS);
+        assert_eq!(*blocked.lock().unwrap(), 0);
+        assert!(sink.outputs[0].failed);
+
+        drop(unblock);
+    }
+}
@@ -2,7 +2,7 @@ use super::{Open, Sink, SinkAsBytes, SinkResult};
 use crate::config::AudioFormat;
 use crate::convert::Converter;
 use crate::decoder::AudioPacket;
-use crate::{NUM_CHANNELS, SAMPLE_RATE};
+use crate::NUM_CHANNELS;
 
 use gstreamer as gst;
 use gstreamer_app as gst_app;
@@ -21,8 +21,11 @@ pub struct GstreamerSink {
 }
 
 impl Open for GstreamerSink {
-    fn open(device: Option<String>, format: AudioFormat) -> Self {
-        info!("Using GStreamer sink with format: {:?}", format);
+    fn open(device: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
+        info!(
+            "Using GStreamer sink with format: {:?}, sample rate: {} Hz",
+            format, sample_rate
+        );
         gst::init().expect("failed to init GStreamer!");
 
         // GStreamer calls S24 and S24_3 different from the rest of the world
@@ -41,7 +44,7 @@ impl Open for GstreamerSink {
 
         let pipeline_str_preamble = format!(
             "appsrc caps=\"audio/x-raw,format={}{},layout=interleaved,channels={},rate={}\" block=true max-bytes={} name=appsrc0 ",
-            gst_format, ENDIANNESS, NUM_CHANNELS, SAMPLE_RATE, gst_bytes
+            gst_format, ENDIANNESS, NUM_CHANNELS, sample_rate, gst_bytes
         );
         // no need to dither twice; use librespot dithering instead
         let pipeline_str_rest = r#" ! audioconvert dithering=none ! autoaudiosink"#;
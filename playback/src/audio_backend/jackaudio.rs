@@ -38,7 +38,7 @@ impl ProcessHandler for JackData {
 }
 
 impl Open for JackSink {
-    fn open(client_name: Option<String>, format: AudioFormat) -> Self {
+    fn open(client_name: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
         if format != AudioFormat::F32 {
             warn!("JACK currently does not support {:?} output", format);
         }
@@ -47,6 +47,14 @@ impl Open for JackSink {
         let client_name = client_name.unwrap_or_else(|| "librespot".to_string());
         let (client, _status) =
             Client::new(&client_name[..], ClientOptions::NO_START_SERVER).unwrap();
+        // JACK dictates the sample rate, so we can only point out a mismatch.
+        if client.sample_rate() != sample_rate as usize {
+            warn!(
+                "JACK server runs at {} Hz, but the output sample rate is {} Hz",
+                client.sample_rate(),
+                sample_rate
+            );
+        }
         let ch_r = client.register_port("out_0", AudioOut::default()).unwrap();
         let ch_l = client.register_port("out_1", AudioOut::default()).unwrap();
         // buffer for samples from librespot (~10ms)
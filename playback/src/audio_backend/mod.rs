@@ -1,6 +1,7 @@
 use crate::config::AudioFormat;
 use crate::convert::Converter;
 use crate::decoder::AudioPacket;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -18,7 +19,19 @@ pub enum SinkError {
 pub type SinkResult<T> = Result<T, SinkError>;
 
 pub trait Open {
-    fn open(_: Option<String>, format: AudioFormat) -> Self;
+    fn open(_: Option<String>, format: AudioFormat, sample_rate: u32) -> Self;
+}
+
+// What is playing, for sinks that pass it on to their receivers along with the audio.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamProperties {
+    pub uri: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: String,
+    pub duration_ms: u32,
+    pub position_ms: u32,
+    pub playing: bool,
 }
 
 pub trait Sink {
@@ -29,16 +42,25 @@ pub trait Sink {
         Ok(())
     }
     fn write(&mut self, packet: AudioPacket, converter: &mut Converter) -> SinkResult<()>;
+    fn set_stream_properties(&mut self, _properties: &StreamProperties) {}
+    // How long it takes until audio that is written now is heard, if the backend can tell.
+    fn latency(&self) -> Option<Duration> {
+        None
+    }
 }
 
-pub type SinkBuilder = fn(Option<String>, AudioFormat) -> Box<dyn Sink>;
+pub type SinkBuilder = fn(Option<String>, AudioFormat, u32) -> Box<dyn Sink>;
 
 pub trait SinkAsBytes {
     fn write_bytes(&mut self, data: &[u8]) -> SinkResult<()>;
 }
 
-fn mk_sink<S: Sink + Open + 'static>(device: Option<String>, format: AudioFormat) -> Box<dyn Sink> {
-    Box::new(S::open(device, format))
+fn mk_sink<S: Sink + Open + 'static>(
+    device: Option<String>,
+    format: AudioFormat,
+    sample_rate: u32,
+) -> Box<dyn Sink> {
+    Box::new(S::open(device, format, sample_rate))
 }
 
 // reuse code for various backends
@@ -112,9 +134,21 @@ mod sdl;
 #[cfg(feature = "sdl-backend")]
 use self::sdl::SdlSink;
 
+mod null;
+pub use self::null::{Clock, NullSink, NullSinkStats, RealClock, VirtualClock};
+
 mod pipe;
 use self::pipe::StdoutSink;
 
+mod fanout;
+pub use self::fanout::FanoutSink;
+
+mod rtp;
+use self::rtp::RtpSink;
+
+mod snapcast;
+use self::snapcast::SnapcastSink;
+
 mod subprocess;
 use self::subprocess::SubprocessSink;
 
@@ -137,6 +171,10 @@ pub const BACKENDS: &[(&str, SinkBuilder)] = &[
     (SdlSink::NAME, mk_sink::<SdlSink>),
     (StdoutSink::NAME, mk_sink::<StdoutSink>),
     (SubprocessSink::NAME, mk_sink::<SubprocessSink>),
+    (RtpSink::NAME, mk_sink::<RtpSink>),
+    (SnapcastSink::NAME, mk_sink::<SnapcastSink>),
+    (FanoutSink::NAME, mk_sink::<FanoutSink>),
+    (NullSink::NAME, mk_sink::<NullSink>),
 ];
 
 pub fn find(name: Option<String>) -> Option<SinkBuilder> {
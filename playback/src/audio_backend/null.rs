@@ -0,0 +1,319 @@
+use super::{Open, Sink, SinkResult};
+use crate::config::AudioFormat;
+use crate::convert::Converter;
+use crate::decoder::AudioPacket;
+use crate::NUM_CHANNELS;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How far a paced sink lets writing run ahead of the clock, like the buffer of a device.
+const BUFFER: Duration = Duration::from_millis(100);
+
+// Where the null sink takes the time from when it paces the audio.
+pub trait Clock: Send + Sync {
+    // The time since the clock was started.
+    fn now(&self) -> Duration;
+    // Blocks until the clock reaches the given time.
+    fn sleep_until(&self, deadline: Duration);
+}
+
+pub struct RealClock {
+    start: Instant,
+}
+
+impl RealClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: Duration) {
+        if let Some(remaining) = deadline.checked_sub(self.now()) {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+struct VirtualClockShared {
+    now: Mutex<Duration>,
+    advanced: Condvar,
+    auto_advance: bool,
+}
+
+// A clock that only moves when it is told to, for tests that don't want to wait for the audio
+// to play in real time. Clones share the same time.
+#[derive(Clone)]
+pub struct VirtualClock {
+    shared: Arc<VirtualClockShared>,
+}
+
+impl VirtualClock {
+    // Time only passes with advance(), and the sink blocks until the audio it has written
+    // has been played as far as it would be on a device.
+    pub fn new() -> Self {
+        Self::with_auto_advance(false)
+    }
+
+    // Time jumps ahead to whatever the sink waits for, so that audio is played as fast as it
+    // is written while the clock tells how long it would have taken.
+    pub fn auto_advancing() -> Self {
+        Self::with_auto_advance(true)
+    }
+
+    fn with_auto_advance(auto_advance: bool) -> Self {
+        Self {
+            shared: Arc::new(VirtualClockShared {
+                now: Mutex::new(Duration::new(0, 0)),
+                advanced: Condvar::new(),
+                auto_advance,
+            }),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        // Panic safety: the time is only read, compared and added to while the lock is held.
+        *self.shared.now.lock().unwrap() += duration;
+        self.shared.advanced.notify_all();
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        *self.shared.now.lock().unwrap()
+    }
+
+    fn sleep_until(&self, deadline: Duration) {
+        let mut now = self.shared.now.lock().unwrap();
+        if self.shared.auto_advance {
+            if *now < deadline {
+                *now = deadline;
+                self.shared.advanced.notify_all();
+            }
+        } else {
+            while *now < deadline {
+                now = self.shared.advanced.wait(now).unwrap();
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NullSinkStats {
+    pub starts: u64,
+    pub stops: u64,
+    pub packets_written: u64,
+    pub samples_written: u64,
+    // how often the audio ran out while the sink was started, and for how long in total, as far
+    // as it is paced against a clock
+    pub gaps: u64,
+    pub gap_duration: Duration,
+}
+
+// Discards the audio. With a clock it consumes it at the pace it would be played at, so that
+// the player runs as it would with a device, e.g. for tests or for running headless.
+pub struct NullSink {
+    clock: Option<Arc<dyn Clock>>,
+    sample_rate: u32,
+    // the time on the clock that the audio written so far has been played by
+    played_until: Option<Duration>,
+    stats: Arc<Mutex<NullSinkStats>>,
+}
+
+impl Open for NullSink {
+    fn open(device: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
+        let clock: Option<Arc<dyn Clock>> = match device.as_deref() {
+            None | Some("") => None,
+            Some("realtime") => Some(Arc::new(RealClock::new())),
+            Some(device) => panic!(
+                "Invalid device {} for the null sink, expected realtime or nothing",
+                device
+            ),
+        };
+
+        info!(
+            "Using null sink with format: {:?}, sample rate: {} Hz{}",
+            format,
+            sample_rate,
+            if clock.is_some() {
+                ", paced in real time"
+            } else {
+                ""
+            }
+        );
+
+        match clock {
+            Some(clock) => Self::with_clock(clock, sample_rate),
+            None => Self::new(sample_rate),
+        }
+    }
+}
+
+impl NullSink {
+    pub const NAME: &'static str = "null";
+
+    // A sink that takes the audio as fast as it is written.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            clock: None,
+            sample_rate,
+            played_until: None,
+            stats: Arc::new(Mutex::new(NullSinkStats::default())),
+        }
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>, sample_rate: u32) -> Self {
+        Self {
+            clock: Some(clock),
+            ..Self::new(sample_rate)
+        }
+    }
+
+    // The statistics of the sink, which stay up to date after it has been handed to the player.
+    pub fn stats(&self) -> Arc<Mutex<NullSinkStats>> {
+        self.stats.clone()
+    }
+}
+
+impl Sink for NullSink {
+    fn start(&mut self) -> SinkResult<()> {
+        self.stats.lock().unwrap().starts += 1;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        // Silence after stopping is no gap.
+        self.played_until = None;
+        self.stats.lock().unwrap().stops += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, packet: AudioPacket, _: &mut Converter) -> SinkResult<()> {
+        // There is no telling how long passthrough data plays for.
+        let samples = match packet {
+            AudioPacket::Samples(ref samples) => samples.len(),
+            AudioPacket::OggData(_) => 0,
+        };
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.packets_written += 1;
+        stats.samples_written += samples as u64;
+
+        let clock = match self.clock {
+            Some(ref clock) => clock,
+            None => return Ok(()),
+        };
+
+        let now = clock.now();
+        let played_until = match self.played_until {
+            Some(played_until) if now > played_until => {
+                stats.gaps += 1;
+                stats.gap_duration += now - played_until;
+                now
+            }
+            Some(played_until) => played_until,
+            None => now,
+        };
+        drop(stats);
+
+        let frames = samples / NUM_CHANNELS as usize;
+        let played_until = played_until
+            + Duration::from_nanos(frames as u64 * 1_000_000_000 / self.sample_rate as u64);
+        self.played_until = Some(played_until);
+
+        clock.sleep_until(played_until.saturating_sub(BUFFER));
+        Ok(())
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        let clock = self.clock.as_ref()?;
+        Some(self.played_until?.saturating_sub(clock.now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn write_ms(sink: &mut NullSink, ms: usize) {
+        let samples = vec![0.0; 44100 * ms / 1000 * NUM_CHANNELS as usize];
+        sink.write(AudioPacket::Samples(samples), &mut Converter::new(None))
+            .unwrap();
+    }
+
+    #[test]
+    fn paced_by_virtual_clock() {
+        let clock = VirtualClock::auto_advancing();
+        let mut sink = NullSink::with_clock(Arc::new(clock.clone()), 44100);
+        let stats = sink.stats();
+
+        sink.start().unwrap();
+        for _ in 0..10 {
+            write_ms(&mut sink, 50);
+        }
+        // Writing runs ahead of the clock by the buffer.
+        assert_eq!(clock.now(), Duration::from_millis(400));
+        assert_eq!(sink.latency(), Some(BUFFER));
+
+        // The audio runs out while nothing is written.
+        clock.advance(Duration::from_millis(300));
+        write_ms(&mut sink, 50);
+
+        sink.stop().unwrap();
+        clock.advance(Duration::from_secs(1));
+        sink.start().unwrap();
+        write_ms(&mut sink, 50);
+
+        assert_eq!(
+            *stats.lock().unwrap(),
+            NullSinkStats {
+                starts: 2,
+                stops: 1,
+                packets_written: 12,
+                samples_written: 12 * 2205 * NUM_CHANNELS as u64,
+                gaps: 1,
+                gap_duration: Duration::from_millis(200),
+            }
+        );
+    }
+
+    #[test]
+    fn blocks_until_clock_advances() {
+        let clock = VirtualClock::new();
+        let mut sink = NullSink::with_clock(Arc::new(clock.clone()), 44100);
+
+        let (written_tx, written_rx) = mpsc::channel();
+        thread::spawn(move || {
+            sink.start().unwrap();
+            for _ in 0..4 {
+                write_ms(&mut sink, 50);
+            }
+            written_tx.send(()).unwrap();
+        });
+
+        // 200 ms of audio need the clock at 100 ms to be written.
+        assert!(written_rx.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(Duration::from_millis(100));
+        written_rx.recv().unwrap();
+    }
+}
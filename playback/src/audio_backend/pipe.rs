@@ -5,19 +5,36 @@ use crate::decoder::AudioPacket;
 use std::fs::OpenOptions;
 use std::io::{self, Write};
 
+#[cfg(unix)]
+use crate::NUM_CHANNELS;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::time::Duration;
+
 pub struct StdoutSink {
     output: Option<Box<dyn Write>>,
+    // to ask how much of what was written is still waiting in the pipe
+    #[cfg(unix)]
+    fd: Option<RawFd>,
     path: Option<String>,
     format: AudioFormat,
+    sample_rate: u32,
 }
 
 impl Open for StdoutSink {
-    fn open(path: Option<String>, format: AudioFormat) -> Self {
-        info!("Using pipe sink with format: {:?}", format);
+    fn open(path: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
+        info!(
+            "Using pipe sink with format: {:?}, sample rate: {} Hz",
+            format, sample_rate
+        );
         Self {
             output: None,
+            #[cfg(unix)]
+            fd: None,
             path,
             format,
+            sample_rate,
         }
     }
 }
@@ -31,9 +48,19 @@ impl Sink for StdoutSink {
                         .write(true)
                         .open(path)
                         .map_err(|e| SinkError::ConnectionRefused(e.to_string()))?;
+                    #[cfg(unix)]
+                    {
+                        self.fd = Some(open_op.as_raw_fd());
+                    }
                     Box::new(open_op)
                 }
-                None => Box::new(io::stdout()),
+                None => {
+                    #[cfg(unix)]
+                    {
+                        self.fd = Some(io::stdout().as_raw_fd());
+                    }
+                    Box::new(io::stdout())
+                }
             };
 
             self.output = Some(output);
@@ -42,6 +69,21 @@ impl Sink for StdoutSink {
         Ok(())
     }
 
+    #[cfg(unix)]
+    fn latency(&self) -> Option<Duration> {
+        let fd = self.fd?;
+        let mut queued: libc::c_int = 0;
+        // Safe, FIONREAD only writes the number of bytes that are waiting to be read.
+        if unsafe { libc::ioctl(fd, libc::FIONREAD, &mut queued) } != 0 {
+            return None;
+        }
+
+        let frame_size = self.format.size() * NUM_CHANNELS as usize;
+        Some(Duration::from_secs_f64(
+            queued as f64 / frame_size as f64 / self.sample_rate as f64,
+        ))
+    }
+
     sink_as_bytes!();
 }
 
@@ -68,3 +110,39 @@ impl SinkAsBytes for StdoutSink {
 impl StdoutSink {
     pub const NAME: &'static str = "pipe";
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::fs;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    #[test]
+    fn latency_of_unread_audio() {
+        let path = std::env::temp_dir().join(format!("librespot-pipe-{}", std::process::id()));
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        // Open the reading end first, so that opening the writing end doesn't block.
+        let _reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .unwrap();
+
+        let mut sink = StdoutSink::open(
+            Some(path.to_str().unwrap().to_string()),
+            AudioFormat::S16,
+            44100,
+        );
+        let mut converter = Converter::new(None);
+        sink.start().unwrap();
+        let samples = vec![0.0; 441 * NUM_CHANNELS as usize];
+        sink.write(AudioPacket::Samples(samples), &mut converter)
+            .unwrap();
+
+        assert_eq!(sink.latency(), Some(Duration::from_millis(10)));
+        fs::remove_file(&path).unwrap();
+    }
+}
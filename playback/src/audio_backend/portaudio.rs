@@ -2,7 +2,7 @@ use super::{Open, Sink, SinkError, SinkResult};
 use crate::config::AudioFormat;
 use crate::convert::Converter;
 use crate::decoder::AudioPacket;
-use crate::{NUM_CHANNELS, SAMPLE_RATE};
+use crate::NUM_CHANNELS;
 use portaudio_rs::device::{get_default_output_index, DeviceIndex, DeviceInfo};
 use portaudio_rs::stream::*;
 use std::process::exit;
@@ -12,14 +12,17 @@ pub enum PortAudioSink<'a> {
     F32(
         Option<portaudio_rs::stream::Stream<'a, f32, f32>>,
         StreamParameters<f32>,
+        u32,
     ),
     S32(
         Option<portaudio_rs::stream::Stream<'a, i32, i32>>,
         StreamParameters<i32>,
+        u32,
     ),
     S16(
         Option<portaudio_rs::stream::Stream<'a, i16, i16>>,
         StreamParameters<i16>,
+        u32,
     ),
 }
 
@@ -51,8 +54,11 @@ fn find_output(device: &str) -> Option<DeviceIndex> {
 }
 
 impl<'a> Open for PortAudioSink<'a> {
-    fn open(device: Option<String>, format: AudioFormat) -> PortAudioSink<'a> {
-        info!("Using PortAudio sink with format: {:?}", format);
+    fn open(device: Option<String>, format: AudioFormat, sample_rate: u32) -> PortAudioSink<'a> {
+        info!(
+            "Using PortAudio sink with format: {:?}, sample rate: {} Hz",
+            format, sample_rate
+        );
 
         portaudio_rs::initialize().unwrap();
 
@@ -80,7 +86,7 @@ impl<'a> Open for PortAudioSink<'a> {
                     suggested_latency: latency,
                     data: 0.0 as $type,
                 };
-                $sink(None, params)
+                $sink(None, params, sample_rate)
             }};
         }
         match format {
@@ -97,13 +103,13 @@ impl<'a> Open for PortAudioSink<'a> {
 impl<'a> Sink for PortAudioSink<'a> {
     fn start(&mut self) -> SinkResult<()> {
         macro_rules! start_sink {
-            (ref mut $stream: ident, ref $parameters: ident) => {{
+            (ref mut $stream: ident, ref $parameters: ident, $sample_rate: expr) => {{
                 if $stream.is_none() {
                     *$stream = Some(
                         Stream::open(
                             None,
                             Some(*$parameters),
-                            SAMPLE_RATE as f64,
+                            $sample_rate as f64,
                             FRAMES_PER_BUFFER_UNSPECIFIED,
                             StreamFlags::DITHER_OFF, // no need to dither twice; use librespot dithering instead
                             None,
@@ -116,9 +122,15 @@ impl<'a> Sink for PortAudioSink<'a> {
         }
 
         match self {
-            Self::F32(stream, parameters) => start_sink!(ref mut stream, ref parameters),
-            Self::S32(stream, parameters) => start_sink!(ref mut stream, ref parameters),
-            Self::S16(stream, parameters) => start_sink!(ref mut stream, ref parameters),
+            Self::F32(stream, parameters, sample_rate) => {
+                start_sink!(ref mut stream, ref parameters, *sample_rate)
+            }
+            Self::S32(stream, parameters, sample_rate) => {
+                start_sink!(ref mut stream, ref parameters, *sample_rate)
+            }
+            Self::S16(stream, parameters, sample_rate) => {
+                start_sink!(ref mut stream, ref parameters, *sample_rate)
+            }
         };
 
         Ok(())
@@ -132,9 +144,9 @@ impl<'a> Sink for PortAudioSink<'a> {
             }};
         }
         match self {
-            Self::F32(stream, _) => stop_sink!(ref mut stream),
-            Self::S32(stream, _) => stop_sink!(ref mut stream),
-            Self::S16(stream, _) => stop_sink!(ref mut stream),
+            Self::F32(stream, _, _) => stop_sink!(ref mut stream),
+            Self::S32(stream, _, _) => stop_sink!(ref mut stream),
+            Self::S16(stream, _, _) => stop_sink!(ref mut stream),
         };
 
         Ok(())
@@ -152,15 +164,15 @@ impl<'a> Sink for PortAudioSink<'a> {
             .map_err(|e| SinkError::OnWrite(e.to_string()))?;
 
         let result = match self {
-            Self::F32(stream, _parameters) => {
+            Self::F32(stream, _parameters, _) => {
                 let samples_f32: &[f32] = &converter.f64_to_f32(&samples);
                 write_sink!(ref mut stream, samples_f32)
             }
-            Self::S32(stream, _parameters) => {
+            Self::S32(stream, _parameters, _) => {
                 let samples_s32: &[i32] = &converter.f64_to_s32(&samples);
                 write_sink!(ref mut stream, samples_s32)
             }
-            Self::S16(stream, _parameters) => {
+            Self::S16(stream, _parameters, _) => {
                 let samples_s16: &[i16] = &converter.f64_to_s16(&samples);
                 write_sink!(ref mut stream, samples_s16)
             }
@@ -2,9 +2,10 @@ use super::{Open, Sink, SinkAsBytes, SinkError, SinkResult};
 use crate::config::AudioFormat;
 use crate::convert::Converter;
 use crate::decoder::AudioPacket;
-use crate::{NUM_CHANNELS, SAMPLE_RATE};
+use crate::NUM_CHANNELS;
 use libpulse_binding::{self as pulse, error::PAErr, stream::Direction};
 use libpulse_simple_binding::Simple;
+use std::time::Duration;
 use thiserror::Error;
 
 const APP_NAME: &str = "librespot";
@@ -50,10 +51,11 @@ pub struct PulseAudioSink {
     s: Option<Simple>,
     device: Option<String>,
     format: AudioFormat,
+    sample_rate: u32,
 }
 
 impl Open for PulseAudioSink {
-    fn open(device: Option<String>, format: AudioFormat) -> Self {
+    fn open(device: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
         let mut actual_format = format;
 
         if actual_format == AudioFormat::F64 {
@@ -61,12 +63,16 @@ impl Open for PulseAudioSink {
             actual_format = AudioFormat::F32;
         }
 
-        info!("Using PulseAudioSink with format: {:?}", actual_format);
+        info!(
+            "Using PulseAudioSink with format: {:?}, sample rate: {} Hz",
+            actual_format, sample_rate
+        );
 
         Self {
             s: None,
             device,
             format: actual_format,
+            sample_rate,
         }
     }
 }
@@ -87,7 +93,7 @@ impl Sink for PulseAudioSink {
             let ss = pulse::sample::Spec {
                 format: pulse_format,
                 channels: NUM_CHANNELS,
-                rate: SAMPLE_RATE,
+                rate: self.sample_rate,
             };
 
             if !ss.is_valid() {
@@ -95,7 +101,7 @@ impl Sink for PulseAudioSink {
                     pulse_format,
                     format: self.format,
                     channels: NUM_CHANNELS,
-                    rate: SAMPLE_RATE,
+                    rate: self.sample_rate,
                 };
 
                 return Err(SinkError::from(pulse_error));
@@ -128,6 +134,18 @@ impl Sink for PulseAudioSink {
         Ok(())
     }
 
+    fn latency(&self) -> Option<Duration> {
+        let s = self.s.as_ref()?;
+
+        match s.get_latency() {
+            Some(latency) => Some(Duration::from_micros(latency.0)),
+            None => {
+                warn!("<PulseAudioSink> Failed to Get Latency");
+                None
+            }
+        }
+    }
+
     sink_as_bytes!();
 }
 
@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::process::exit;
 use std::thread;
 use std::time::Duration;
@@ -9,7 +10,7 @@ use super::{Sink, SinkError, SinkResult};
 use crate::config::AudioFormat;
 use crate::convert::Converter;
 use crate::decoder::AudioPacket;
-use crate::{NUM_CHANNELS, SAMPLE_RATE};
+use crate::NUM_CHANNELS;
 
 #[cfg(all(
     feature = "rodiojack-backend",
@@ -18,16 +19,21 @@ use crate::{NUM_CHANNELS, SAMPLE_RATE};
 compile_error!("Rodio JACK backend is currently only supported on linux.");
 
 #[cfg(feature = "rodio-backend")]
-pub fn mk_rodio(device: Option<String>, format: AudioFormat) -> Box<dyn Sink> {
-    Box::new(open(cpal::default_host(), device, format))
+pub fn mk_rodio(device: Option<String>, format: AudioFormat, sample_rate: u32) -> Box<dyn Sink> {
+    Box::new(open(cpal::default_host(), device, format, sample_rate))
 }
 
 #[cfg(feature = "rodiojack-backend")]
-pub fn mk_rodiojack(device: Option<String>, format: AudioFormat) -> Box<dyn Sink> {
+pub fn mk_rodiojack(
+    device: Option<String>,
+    format: AudioFormat,
+    sample_rate: u32,
+) -> Box<dyn Sink> {
     Box::new(open(
         cpal::host_from_id(cpal::HostId::Jack).unwrap(),
         device,
         format,
+        sample_rate,
     ))
 }
 
@@ -62,6 +68,9 @@ impl From<RodioError> for SinkError {
 pub struct RodioSink {
     rodio_sink: rodio::Sink,
     format: AudioFormat,
+    sample_rate: u32,
+    // frames of the packets appended to the rodio sink, the newest last
+    queued_frames: VecDeque<usize>,
     _stream: rodio::OutputStream,
 }
 
@@ -167,10 +176,16 @@ fn create_sink(
     Ok((sink, stream))
 }
 
-pub fn open(host: cpal::Host, device: Option<String>, format: AudioFormat) -> RodioSink {
+pub fn open(
+    host: cpal::Host,
+    device: Option<String>,
+    format: AudioFormat,
+    sample_rate: u32,
+) -> RodioSink {
     info!(
-        "Using Rodio sink with format {:?} and cpal host: {}",
+        "Using Rodio sink with format {:?}, sample rate {} Hz and cpal host: {}",
         format,
+        sample_rate,
         host.id().name()
     );
 
@@ -184,6 +199,8 @@ pub fn open(host: cpal::Host, device: Option<String>, format: AudioFormat) -> Ro
     RodioSink {
         rodio_sink: sink,
         format,
+        sample_rate,
+        queued_frames: VecDeque::new(),
         _stream: stream,
     }
 }
@@ -198,7 +215,7 @@ impl Sink for RodioSink {
                 let samples_f32: &[f32] = &converter.f64_to_f32(samples);
                 let source = rodio::buffer::SamplesBuffer::new(
                     NUM_CHANNELS as u16,
-                    SAMPLE_RATE,
+                    self.sample_rate,
                     samples_f32,
                 );
                 self.rodio_sink.append(source);
@@ -207,7 +224,7 @@ impl Sink for RodioSink {
                 let samples_s16: &[i16] = &converter.f64_to_s16(samples);
                 let source = rodio::buffer::SamplesBuffer::new(
                     NUM_CHANNELS as u16,
-                    SAMPLE_RATE,
+                    self.sample_rate,
                     samples_s16,
                 );
                 self.rodio_sink.append(source);
@@ -215,6 +232,9 @@ impl Sink for RodioSink {
             _ => unreachable!(),
         };
 
+        self.queued_frames
+            .push_back(samples.len() / NUM_CHANNELS as usize);
+
         // Chunk sizes seem to be about 256 to 3000 ish items long.
         // Assuming they're on average 1628 then a half second buffer is:
         // 44100 elements --> about 27 chunks
@@ -222,8 +242,27 @@ impl Sink for RodioSink {
             // sleep and wait for rodio to drain a bit
             thread::sleep(Duration::from_millis(10));
         }
+
+        // Forget about the packets that have been played.
+        while self.queued_frames.len() > self.rodio_sink.len() {
+            self.queued_frames.pop_front();
+        }
+
         Ok(())
     }
+
+    fn latency(&self) -> Option<Duration> {
+        // The packet that is playing counts in full, which is good enough at a few ms each.
+        let frames: usize = self
+            .queued_frames
+            .iter()
+            .rev()
+            .take(self.rodio_sink.len())
+            .sum();
+        Some(Duration::from_secs_f64(
+            frames as f64 / self.sample_rate as f64,
+        ))
+    }
 }
 
 impl RodioSink {
@@ -0,0 +1,411 @@
+use super::{Open, Sink, SinkError, SinkResult};
+use crate::config::AudioFormat;
+use crate::convert::Converter;
+use crate::decoder::AudioPacket;
+use crate::NUM_CHANNELS;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const RTP_VERSION: u8 = 2;
+const HEADER_SIZE: usize = 12;
+
+// Static payload type for L16 stereo at 44.1 kHz, see RFC 3551. Everything else needs a
+// dynamic payload type that the receiver is told about out of band (e.g. with SDP).
+const PAYLOAD_TYPE_L16_STEREO: u8 = 10;
+const PAYLOAD_TYPE_DYNAMIC: u8 = 96;
+
+const DEFAULT_PACKET_TIME_MS: u32 = 5;
+const DEFAULT_MULTICAST_TTL: u32 = 1;
+
+// Payloads beyond this don't fit an Ethernet frame together with the IP, UDP and RTP headers.
+const MAX_UNFRAGMENTED_PAYLOAD: usize = 1500 - 20 - 8 - HEADER_SIZE;
+
+// When writing falls behind the clock by more than this (e.g. while buffering), don't try
+// to catch up by sending a burst of packets but continue in real time.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Error)]
+enum RtpError {
+    #[error("<RtpSink> Invalid Destination {0}, Expected HOST:PORT[?OPTION=VALUE&...]")]
+    InvalidDestination(String),
+
+    #[error("<RtpSink> Invalid Option {0}, Expected pt, ssrc, ptime, ttl or bind")]
+    InvalidOption(String),
+
+    #[error("<RtpSink> Failed to Open Socket, {0}")]
+    Socket(io::Error),
+
+    #[error("<RtpSink> {0}")]
+    OnWrite(io::Error),
+
+    #[error("<RtpSink> {0}")]
+    Samples(String),
+}
+
+impl From<RtpError> for SinkError {
+    fn from(e: RtpError) -> SinkError {
+        use RtpError::*;
+        let es = e.to_string();
+        match e {
+            Socket(_) => SinkError::ConnectionRefused(es),
+            OnWrite(_) | Samples(_) => SinkError::OnWrite(es),
+            InvalidDestination(_) | InvalidOption(_) => SinkError::InvalidParams(es),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RtpEncoding {
+    L16,
+    L24,
+}
+
+impl RtpEncoding {
+    fn sample_size(&self) -> usize {
+        match self {
+            Self::L16 => 2,
+            Self::L24 => 3,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct RtpConfig {
+    destination: SocketAddr,
+    payload_type: Option<u8>,
+    ssrc: Option<u32>,
+    packet_time_ms: u32,
+    ttl: u32,
+    bind: Option<IpAddr>,
+}
+
+// Parses `HOST:PORT[?OPTION=VALUE&...]`, e.g. `239.0.0.1:5004?pt=97&ptime=1&ttl=4`.
+impl std::str::FromStr for RtpConfig {
+    type Err = RtpError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '?');
+        // Panic safety: splitn always yields at least one part
+        let destination = parts.next().unwrap();
+        let options = parts.next().unwrap_or("");
+
+        let destination = destination
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .ok_or_else(|| RtpError::InvalidDestination(destination.to_string()))?;
+
+        let mut config = Self {
+            destination,
+            payload_type: None,
+            ssrc: None,
+            packet_time_ms: DEFAULT_PACKET_TIME_MS,
+            ttl: DEFAULT_MULTICAST_TTL,
+            bind: None,
+        };
+
+        for option in options.split('&').filter(|option| !option.is_empty()) {
+            let invalid = || RtpError::InvalidOption(option.to_string());
+            let mut parts = option.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return Err(invalid()),
+            };
+            match key {
+                "pt" => match value.parse::<u8>() {
+                    Ok(payload_type) if payload_type < 128 => {
+                        config.payload_type = Some(payload_type)
+                    }
+                    _ => return Err(invalid()),
+                },
+                "ssrc" => config.ssrc = Some(value.parse().map_err(|_| invalid())?),
+                "ptime" => match value.parse::<u32>() {
+                    Ok(packet_time_ms) if (1..=100).contains(&packet_time_ms) => {
+                        config.packet_time_ms = packet_time_ms
+                    }
+                    _ => return Err(invalid()),
+                },
+                "ttl" => config.ttl = value.parse().map_err(|_| invalid())?,
+                "bind" => config.bind = Some(value.parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+// Sends PCM as RTP (RFC 3550) over UDP, as L16 or L24 (RFC 3551, RFC 3190). Packets are sent
+// in real time, so the receiver's jitter buffer is all the buffering there is.
+pub struct RtpSink {
+    config: RtpConfig,
+    encoding: RtpEncoding,
+    payload_type: u8,
+    ssrc: u32,
+    frames_per_packet: usize,
+    packet_duration: Duration,
+    socket: Option<UdpSocket>,
+    sequence_number: u16,
+    timestamp: u32,
+    marker: bool,
+    next_send: Option<Instant>,
+    payload_size: usize,
+    payload: Vec<u8>,
+}
+
+impl Open for RtpSink {
+    fn open(device: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
+        let device = device.unwrap_or_else(|| {
+            panic!("rtp sink requires specifying a destination as HOST:PORT[?OPTION=VALUE&...]")
+        });
+        let config = device
+            .parse::<RtpConfig>()
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let encoding = match format {
+            AudioFormat::S16 => RtpEncoding::L16,
+            AudioFormat::S24 | AudioFormat::S24_3 => RtpEncoding::L24,
+            _ => {
+                warn!(
+                    "RTP does not support {:?} output, using L24 instead",
+                    format
+                );
+                RtpEncoding::L24
+            }
+        };
+
+        let payload_type = config.payload_type.unwrap_or(
+            if encoding == RtpEncoding::L16 && sample_rate == 44100 {
+                PAYLOAD_TYPE_L16_STEREO
+            } else {
+                PAYLOAD_TYPE_DYNAMIC
+            },
+        );
+
+        let frames_per_packet = (sample_rate * config.packet_time_ms / 1000) as usize;
+        let payload_size = frames_per_packet * NUM_CHANNELS as usize * encoding.sample_size();
+        if payload_size > MAX_UNFRAGMENTED_PAYLOAD {
+            warn!(
+                "RTP payloads of {} bytes will be fragmented, consider a shorter packet time",
+                payload_size
+            );
+        }
+
+        let ssrc = config.ssrc.unwrap_or_else(rand::random);
+
+        info!(
+            "Using RTP sink to {} with {:?}/{}/{}, payload type {}, SSRC {:#010x}, {} ms packets",
+            config.destination,
+            encoding,
+            sample_rate,
+            NUM_CHANNELS,
+            payload_type,
+            ssrc,
+            config.packet_time_ms
+        );
+
+        Self {
+            packet_duration: Duration::from_secs_f64(frames_per_packet as f64 / sample_rate as f64),
+            config,
+            encoding,
+            payload_type,
+            ssrc,
+            frames_per_packet,
+            socket: None,
+            // Random initial values, as recommended by RFC 3550.
+            sequence_number: rand::random(),
+            timestamp: rand::random(),
+            marker: true,
+            next_send: None,
+            payload_size,
+            payload: Vec::with_capacity(payload_size),
+        }
+    }
+}
+
+impl Sink for RtpSink {
+    fn start(&mut self) -> SinkResult<()> {
+        if self.socket.is_none() {
+            self.socket = Some(self.open_socket().map_err(RtpError::Socket)?);
+            // Mark the first packet after a silence, so that receivers can resynchronize.
+            self.marker = true;
+            self.next_send = None;
+        }
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        if !self.payload.is_empty() {
+            // Pad the last packet with silence to keep all packets the same length.
+            self.payload.resize(self.payload_size, 0);
+            self.send_packet()?;
+        }
+
+        self.socket = None;
+        Ok(())
+    }
+
+    fn write(&mut self, packet: AudioPacket, converter: &mut Converter) -> SinkResult<()> {
+        let samples = packet
+            .samples()
+            .map_err(|e| RtpError::Samples(e.to_string()))?;
+
+        // RTP carries network byte order, i.e. big endian.
+        match self.encoding {
+            RtpEncoding::L16 => {
+                for sample in converter.f64_to_s16(samples) {
+                    self.append(&sample.to_be_bytes())?;
+                }
+            }
+            RtpEncoding::L24 => {
+                for sample in converter.f64_to_s24(samples) {
+                    self.append(&sample.to_be_bytes()[1..])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RtpSink {
+    pub const NAME: &'static str = "rtp";
+
+    fn open_socket(&self) -> io::Result<UdpSocket> {
+        let destination = self.config.destination;
+        let bind = self.config.bind.unwrap_or(match destination {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        });
+
+        let socket = UdpSocket::bind(SocketAddr::new(bind, 0))?;
+        if destination.ip().is_multicast() {
+            match destination.ip() {
+                IpAddr::V4(_) => socket.set_multicast_ttl_v4(self.config.ttl)?,
+                // IPv6 has no socket option for the hop limit in std, the system default
+                // applies.
+                IpAddr::V6(_) => (),
+            }
+        }
+        socket.connect(destination)?;
+
+        Ok(socket)
+    }
+
+    fn append(&mut self, sample: &[u8]) -> SinkResult<()> {
+        self.payload.extend_from_slice(sample);
+        if self.payload.len() == self.payload_size {
+            self.send_packet()?;
+        }
+        Ok(())
+    }
+
+    fn send_packet(&mut self) -> SinkResult<()> {
+        let socket = self
+            .socket
+            .as_ref()
+            .ok_or_else(|| SinkError::NotConnected("<RtpSink>".to_string()))?;
+
+        let mut packet = Vec::with_capacity(HEADER_SIZE + self.payload.len());
+        packet.push(RTP_VERSION << 6);
+        packet.push((self.marker as u8) << 7 | self.payload_type);
+        // Writing to a Vec can't fail.
+        packet.write_u16::<BigEndian>(self.sequence_number).unwrap();
+        packet.write_u32::<BigEndian>(self.timestamp).unwrap();
+        packet.write_u32::<BigEndian>(self.ssrc).unwrap();
+        packet.extend_from_slice(&self.payload);
+
+        // Pace the packets in real time.
+        let now = Instant::now();
+        let next_send = match self.next_send {
+            Some(next_send) if now < next_send + MAX_LAG => next_send,
+            _ => now,
+        };
+        if next_send > now {
+            thread::sleep(next_send - now);
+        }
+        self.next_send = Some(next_send + self.packet_duration);
+
+        socket.send(&packet).map_err(RtpError::OnWrite)?;
+
+        self.payload.clear();
+        self.marker = false;
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(self.frames_per_packet as u32);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = "239.0.0.1:5004?pt=97&ssrc=1234&ptime=1&ttl=4"
+            .parse::<RtpConfig>()
+            .unwrap();
+        assert_eq!(config.destination, "239.0.0.1:5004".parse().unwrap());
+        assert_eq!(config.payload_type, Some(97));
+        assert_eq!(config.ssrc, Some(1234));
+        assert_eq!(config.packet_time_ms, 1);
+        assert_eq!(config.ttl, 4);
+
+        let config = "127.0.0.1:5004".parse::<RtpConfig>().unwrap();
+        assert_eq!(config.payload_type, None);
+        assert_eq!(config.packet_time_ms, DEFAULT_PACKET_TIME_MS);
+
+        assert!("127.0.0.1".parse::<RtpConfig>().is_err());
+        assert!("127.0.0.1:5004?pt=128".parse::<RtpConfig>().is_err());
+        assert!("127.0.0.1:5004?foo=bar".parse::<RtpConfig>().is_err());
+    }
+
+    #[test]
+    fn send_on_loopback() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let device = format!(
+            "{}?pt=97&ssrc=3735928559&ptime=1",
+            receiver.local_addr().unwrap()
+        );
+
+        let mut sink = RtpSink::open(Some(device), AudioFormat::S24_3, 48000);
+        let mut converter = Converter::new(None);
+        sink.start().unwrap();
+
+        // 1.5 packets of 48 frames each, with a recognizable first sample.
+        let mut samples = vec![0.0; 72 * NUM_CHANNELS as usize];
+        samples[0] = 0.5;
+        sink.write(AudioPacket::Samples(samples), &mut converter)
+            .unwrap();
+        sink.stop().unwrap();
+
+        let payload_size = 48 * NUM_CHANNELS as usize * 3;
+        let mut buffer = [0; 2048];
+        let mut timestamps = Vec::new();
+        for i in 0..2u16 {
+            let size = receiver.recv(&mut buffer).unwrap();
+            assert_eq!(size, HEADER_SIZE + payload_size);
+            assert_eq!(buffer[0], 0x80);
+            let marker = buffer[1] & 0x80 != 0;
+            assert_eq!(marker, i == 0);
+            assert_eq!(buffer[1] & 0x7f, 97);
+            assert_eq!(&buffer[8..12], &0xdeadbeef_u32.to_be_bytes());
+            timestamps.push(u32::from_be_bytes([
+                buffer[4], buffer[5], buffer[6], buffer[7],
+            ]));
+            if i == 0 {
+                // 0.5 as big endian 24 bit
+                assert_eq!(&buffer[HEADER_SIZE..HEADER_SIZE + 3], &[0x40, 0x00, 0x00]);
+            }
+        }
+        assert_eq!(timestamps[1].wrapping_sub(timestamps[0]), 48);
+    }
+}
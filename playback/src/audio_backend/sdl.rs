@@ -2,7 +2,7 @@ use super::{Open, Sink, SinkError, SinkResult};
 use crate::config::AudioFormat;
 use crate::convert::Converter;
 use crate::decoder::AudioPacket;
-use crate::{NUM_CHANNELS, SAMPLE_RATE};
+use crate::NUM_CHANNELS;
 use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use std::thread;
 use std::time::Duration;
@@ -14,8 +14,11 @@ pub enum SdlSink {
 }
 
 impl Open for SdlSink {
-    fn open(device: Option<String>, format: AudioFormat) -> Self {
-        info!("Using SDL sink with format: {:?}", format);
+    fn open(device: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
+        info!(
+            "Using SDL sink with format: {:?}, sample rate: {} Hz",
+            format, sample_rate
+        );
 
         if device.is_some() {
             warn!("SDL sink does not support specifying a device name");
@@ -27,7 +30,7 @@ impl Open for SdlSink {
             .expect("could not initialize SDL audio subsystem");
 
         let desired_spec = AudioSpecDesired {
-            freq: Some(SAMPLE_RATE as i32),
+            freq: Some(sample_rate as i32),
             channels: Some(NUM_CHANNELS),
             samples: None,
         };
@@ -86,7 +89,8 @@ impl Sink for SdlSink {
         macro_rules! drain_sink {
             ($queue: expr, $size: expr) => {{
                 // sleep and wait for sdl thread to drain the queue a bit
-                while $queue.size() > (NUM_CHANNELS as u32 * $size as u32 * SAMPLE_RATE) {
+                let sample_rate = $queue.spec().freq as u32;
+                while $queue.size() > (NUM_CHANNELS as u32 * $size as u32 * sample_rate) {
                     thread::sleep(Duration::from_millis(10));
                 }
             }};
@@ -0,0 +1,423 @@
+use super::{Open, Sink, SinkError, SinkResult, StreamProperties};
+use crate::config::AudioFormat;
+use crate::convert::Converter;
+use crate::decoder::AudioPacket;
+use crate::NUM_CHANNELS;
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+// Message types of the snapcast binary protocol.
+const MESSAGE_CODEC_HEADER: u16 = 1;
+const MESSAGE_WIRE_CHUNK: u16 = 2;
+const MESSAGE_STREAM_TAGS: u16 = 6;
+
+const BASE_HEADER_SIZE: usize = 26;
+const WAVE_HEADER_SIZE: u32 = 44;
+
+// Same chunk length as snapserver reads its own streams with.
+const CHUNK_MS: u32 = 20;
+
+// When writing falls behind the clock by more than this (e.g. while buffering), don't try
+// to catch up by sending a burst of chunks but continue in real time.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Error)]
+enum SnapcastError {
+    #[error("<SnapcastSink> Invalid Server {0}, Expected HOST:PORT")]
+    InvalidServer(String),
+
+    #[error("<SnapcastSink> Failed to Connect to {0}, {1}")]
+    Connect(SocketAddr, io::Error),
+
+    #[error("<SnapcastSink> {0}")]
+    OnWrite(io::Error),
+
+    #[error("<SnapcastSink> {0}")]
+    Samples(String),
+}
+
+impl From<SnapcastError> for SinkError {
+    fn from(e: SnapcastError) -> SinkError {
+        use SnapcastError::*;
+        let es = e.to_string();
+        match e {
+            Connect(_, _) => SinkError::ConnectionRefused(es),
+            OnWrite(_) | Samples(_) => SinkError::OnWrite(es),
+            InvalidServer(_) => SinkError::InvalidParams(es),
+        }
+    }
+}
+
+fn write_timestamp(buffer: &mut Vec<u8>, time: SystemTime) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    // Writing to a Vec can't fail.
+    buffer
+        .write_i32::<LittleEndian>(since_epoch.as_secs() as i32)
+        .unwrap();
+    buffer
+        .write_i32::<LittleEndian>(since_epoch.subsec_micros() as i32)
+        .unwrap();
+}
+
+// Frames a payload with the base message header: type, id, refers to, sent and received
+// time and payload size, all little endian.
+fn message(message_type: u16, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(BASE_HEADER_SIZE + payload.len());
+    message.write_u16::<LittleEndian>(message_type).unwrap();
+    message.write_u16::<LittleEndian>(0).unwrap();
+    message.write_u16::<LittleEndian>(0).unwrap();
+    write_timestamp(&mut message, SystemTime::now());
+    write_timestamp(&mut message, UNIX_EPOCH);
+    message
+        .write_u32::<LittleEndian>(payload.len() as u32)
+        .unwrap();
+    message.extend_from_slice(payload);
+    message
+}
+
+fn codec_header(sample_rate: u32, bits_per_sample: u16, sample_size: u16) -> Vec<u8> {
+    let channels = NUM_CHANNELS as u16;
+    let block_align = channels * sample_size;
+
+    // The header of a wave file is how snapcast describes raw PCM.
+    let mut wave = Vec::with_capacity(WAVE_HEADER_SIZE as usize);
+    wave.extend_from_slice(b"RIFF");
+    wave.write_u32::<LittleEndian>(WAVE_HEADER_SIZE - 8)
+        .unwrap();
+    wave.extend_from_slice(b"WAVEfmt ");
+    wave.write_u32::<LittleEndian>(16).unwrap();
+    wave.write_u16::<LittleEndian>(1).unwrap();
+    wave.write_u16::<LittleEndian>(channels).unwrap();
+    wave.write_u32::<LittleEndian>(sample_rate).unwrap();
+    wave.write_u32::<LittleEndian>(sample_rate * block_align as u32)
+        .unwrap();
+    wave.write_u16::<LittleEndian>(block_align).unwrap();
+    wave.write_u16::<LittleEndian>(bits_per_sample).unwrap();
+    wave.extend_from_slice(b"data");
+    wave.write_u32::<LittleEndian>(0).unwrap();
+
+    let mut payload = Vec::new();
+    payload.write_u32::<LittleEndian>(3).unwrap();
+    payload.extend_from_slice(b"pcm");
+    payload
+        .write_u32::<LittleEndian>(wave.len() as u32)
+        .unwrap();
+    payload.extend_from_slice(&wave);
+    message(MESSAGE_CODEC_HEADER, &payload)
+}
+
+// The same properties that snapcast stream plugins report for their players.
+fn stream_tags(properties: &StreamProperties) -> Vec<u8> {
+    let tags = serde_json::json!({
+        "playbackStatus": if properties.playing { "playing" } else { "paused" },
+        "position": properties.position_ms as f64 / 1000.0,
+        "metadata": {
+            "trackId": properties.uri,
+            "title": properties.title,
+            "artist": properties.artists,
+            "album": properties.album,
+            "duration": properties.duration_ms as f64 / 1000.0,
+        },
+    })
+    .to_string();
+
+    let mut payload = Vec::with_capacity(4 + tags.len());
+    payload
+        .write_u32::<LittleEndian>(tags.len() as u32)
+        .unwrap();
+    payload.extend_from_slice(tags.as_bytes());
+    message(MESSAGE_STREAM_TAGS, &payload)
+}
+
+// Streams PCM to a snapcast server over TCP as timestamped wire chunks, the way snapserver
+// sends them to its clients, together with the properties of what is playing. Chunks are
+// sent in real time and stamped with the wall-clock time at which they are due, so that the
+// receiver can keep its clients in sync.
+pub struct SnapcastSink {
+    server: SocketAddr,
+    format: AudioFormat,
+    sample_rate: u32,
+    frames_per_chunk: usize,
+    chunk_duration: Duration,
+    stream: Option<TcpStream>,
+    properties: Option<StreamProperties>,
+    // the instant and wall-clock time at which the next chunk is due
+    next_send: Option<(Instant, SystemTime)>,
+    chunk: Vec<u8>,
+}
+
+impl Open for SnapcastSink {
+    fn open(device: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
+        let device = device
+            .unwrap_or_else(|| panic!("snapcast sink requires specifying a server as HOST:PORT"));
+        let server = device
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .unwrap_or_else(|| panic!("{}", SnapcastError::InvalidServer(device)));
+
+        let format = match format {
+            AudioFormat::S16 | AudioFormat::S24 | AudioFormat::S32 => format,
+            AudioFormat::S24_3 => AudioFormat::S24,
+            _ => {
+                warn!(
+                    "Snapcast does not support {:?} output, using S16 instead",
+                    format
+                );
+                AudioFormat::S16
+            }
+        };
+
+        info!(
+            "Using snapcast sink to {} with format: {:?}, sample rate: {}",
+            server, format, sample_rate
+        );
+
+        let frames_per_chunk = (sample_rate * CHUNK_MS / 1000) as usize;
+
+        Self {
+            server,
+            format,
+            sample_rate,
+            frames_per_chunk,
+            chunk_duration: Duration::from_secs_f64(frames_per_chunk as f64 / sample_rate as f64),
+            stream: None,
+            properties: None,
+            next_send: None,
+            chunk: Vec::new(),
+        }
+    }
+}
+
+impl Sink for SnapcastSink {
+    fn start(&mut self) -> SinkResult<()> {
+        if self.stream.is_none() {
+            let stream = TcpStream::connect(self.server)
+                .map_err(|e| SnapcastError::Connect(self.server, e))?;
+            // Chunks are small and due right away.
+            stream.set_nodelay(true).map_err(SnapcastError::OnWrite)?;
+            self.stream = Some(stream);
+
+            let (bits_per_sample, sample_size) = self.sample_format();
+            self.send(&codec_header(
+                self.sample_rate,
+                bits_per_sample,
+                sample_size,
+            ))?;
+            if let Some(properties) = &self.properties {
+                self.send(&stream_tags(properties))?;
+            }
+        }
+
+        // Timestamps start over from the current time after a silence.
+        self.next_send = None;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        if !self.chunk.is_empty() {
+            self.send_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, packet: AudioPacket, converter: &mut Converter) -> SinkResult<()> {
+        let samples = packet
+            .samples()
+            .map_err(|e| SnapcastError::Samples(e.to_string()))?;
+
+        let data: Vec<u8> = match self.format {
+            AudioFormat::S16 => converter
+                .f64_to_s16(samples)
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect(),
+            AudioFormat::S24 => converter
+                .f64_to_s24(samples)
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect(),
+            _ => converter
+                .f64_to_s32(samples)
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect(),
+        };
+
+        let (_, sample_size) = self.sample_format();
+        let chunk_size = self.frames_per_chunk * NUM_CHANNELS as usize * sample_size as usize;
+        let mut data = &data[..];
+        while !data.is_empty() {
+            let size = usize::min(chunk_size - self.chunk.len(), data.len());
+            self.chunk.extend_from_slice(&data[..size]);
+            data = &data[size..];
+            if self.chunk.len() == chunk_size {
+                self.send_chunk()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_stream_properties(&mut self, properties: &StreamProperties) {
+        self.properties = Some(properties.clone());
+
+        if self.stream.is_some() {
+            if let Err(e) = self.send(&stream_tags(properties)) {
+                warn!("{}", e);
+            }
+        }
+    }
+}
+
+impl SnapcastSink {
+    pub const NAME: &'static str = "snapcast";
+
+    // Bits per sample and bytes per sample, 24 bit samples are padded to 4 bytes.
+    fn sample_format(&self) -> (u16, u16) {
+        match self.format {
+            AudioFormat::S16 => (16, 2),
+            AudioFormat::S24 => (24, 4),
+            _ => (32, 4),
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) -> SinkResult<()> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| SinkError::NotConnected("<SnapcastSink>".to_string()))?;
+
+        if let Err(e) = stream.write_all(message) {
+            // Connect again on the next start.
+            self.stream = None;
+            return Err(SnapcastError::OnWrite(e).into());
+        }
+
+        Ok(())
+    }
+
+    fn send_chunk(&mut self) -> SinkResult<()> {
+        // Pace the chunks in real time.
+        let now = Instant::now();
+        let (next_send, timestamp) = match self.next_send {
+            Some((next_send, timestamp)) if now < next_send + MAX_LAG => (next_send, timestamp),
+            _ => (now, SystemTime::now()),
+        };
+        if next_send > now {
+            thread::sleep(next_send - now);
+        }
+
+        let mut payload = Vec::with_capacity(12 + self.chunk.len());
+        write_timestamp(&mut payload, timestamp);
+        payload
+            .write_u32::<LittleEndian>(self.chunk.len() as u32)
+            .unwrap();
+        payload.extend_from_slice(&self.chunk);
+        self.send(&message(MESSAGE_WIRE_CHUNK, &payload))?;
+
+        // A partial chunk (when stopping) is followed by a new start, which starts over.
+        self.next_send = Some((
+            next_send + self.chunk_duration,
+            timestamp + self.chunk_duration,
+        ));
+        self.chunk.clear();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    // Reads a message from a stand-in server connection, returning its type and payload.
+    fn read_message(stream: &mut TcpStream) -> (u16, Vec<u8>) {
+        let mut header = [0; BASE_HEADER_SIZE];
+        stream.read_exact(&mut header).unwrap();
+        let message_type = u16::from_le_bytes([header[0], header[1]]);
+        let size = u32::from_le_bytes([header[22], header[23], header[24], header[25]]);
+        let mut payload = vec![0; size as usize];
+        stream.read_exact(&mut payload).unwrap();
+        (message_type, payload)
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn stream_to_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let device = listener.local_addr().unwrap().to_string();
+
+        let mut sink = SnapcastSink::open(Some(device), AudioFormat::S16, 48000);
+        let mut converter = Converter::new(None);
+        sink.set_stream_properties(&StreamProperties {
+            uri: "spotify:track:4uLU6hMCjMI75M1A2tKUQC".to_string(),
+            title: "Title".to_string(),
+            artists: vec!["Artist".to_string()],
+            album: "Album".to_string(),
+            duration_ms: 180_000,
+            position_ms: 1500,
+            playing: true,
+        });
+        sink.start().unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // One and a half chunks of 960 frames each, with a recognizable first sample.
+        let mut samples = vec![0.0; 1440 * NUM_CHANNELS as usize];
+        samples[0] = 0.5;
+        sink.write(AudioPacket::Samples(samples), &mut converter)
+            .unwrap();
+        sink.stop().unwrap();
+
+        let (message_type, codec) = read_message(&mut server);
+        assert_eq!(message_type, MESSAGE_CODEC_HEADER);
+        assert_eq!(&codec[4..7], b"pcm");
+        let wave = &codec[11..];
+        assert_eq!(&wave[0..4], b"RIFF");
+        assert_eq!(read_u32(wave, 24), 48000);
+        assert_eq!(u16::from_le_bytes([wave[34], wave[35]]), 16);
+
+        let (message_type, tags) = read_message(&mut server);
+        assert_eq!(message_type, MESSAGE_STREAM_TAGS);
+        let tags: serde_json::Value = serde_json::from_slice(&tags[4..]).unwrap();
+        assert_eq!(tags["playbackStatus"], "playing");
+        assert_eq!(tags["position"], 1.5);
+        assert_eq!(tags["metadata"]["title"], "Title");
+        assert_eq!(tags["metadata"]["artist"][0], "Artist");
+
+        let mut timestamps = Vec::new();
+        for &frames in &[960, 480] {
+            let (message_type, chunk) = read_message(&mut server);
+            assert_eq!(message_type, MESSAGE_WIRE_CHUNK);
+            let seconds = read_u32(&chunk, 0) as f64;
+            let microseconds = read_u32(&chunk, 4) as f64;
+            timestamps.push(seconds + microseconds / 1e6);
+            assert_eq!(
+                read_u32(&chunk, 8) as usize,
+                frames * NUM_CHANNELS as usize * 2
+            );
+            if frames == 960 {
+                // 0.5 as little endian 16 bit
+                assert_eq!(&chunk[12..14], &[0x00, 0x40]);
+            }
+        }
+        assert!((timestamps[1] - timestamps[0] - 0.02).abs() < 1e-5);
+    }
+}
@@ -14,8 +14,11 @@ pub struct SubprocessSink {
 }
 
 impl Open for SubprocessSink {
-    fn open(shell_command: Option<String>, format: AudioFormat) -> Self {
-        info!("Using subprocess sink with format: {:?}", format);
+    fn open(shell_command: Option<String>, format: AudioFormat, sample_rate: u32) -> Self {
+        info!(
+            "Using subprocess sink with format: {:?}, sample rate: {} Hz",
+            format, sample_rate
+        );
 
         if let Some(shell_command) = shell_command {
             SubprocessSink {
@@ -1,8 +1,12 @@
 use super::player::db_to_ratio;
 use crate::convert::i24;
 pub use crate::dither::{mk_ditherer, DithererBuilder, TriangularDitherer};
+pub use crate::filter::equalizer::{EqualizerBand, EqualizerBandType};
+use crate::mixer::mappings::VolumeTable;
+use crate::SAMPLE_RATE;
 
 use std::mem;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -11,6 +15,8 @@ pub enum Bitrate {
     Bitrate96,
     Bitrate160,
     Bitrate320,
+    // picked for every track from how fast the link is
+    Auto,
 }
 
 impl FromStr for Bitrate {
@@ -20,6 +26,7 @@ impl FromStr for Bitrate {
             "96" => Ok(Self::Bitrate96),
             "160" => Ok(Self::Bitrate160),
             "320" => Ok(Self::Bitrate320),
+            "auto" => Ok(Self::Auto),
             _ => Err(()),
         }
     }
@@ -31,6 +38,33 @@ impl Default for Bitrate {
     }
 }
 
+// The codecs that Spotify has files in, each at several bitrates.
+#[derive(Clone, Copy, Debug, Hash, PartialOrd, Ord, PartialEq, Eq)]
+pub enum AudioCodec {
+    Vorbis,
+    Mp3,
+    // in ADTS or MP4 files
+    Aac,
+}
+
+impl FromStr for AudioCodec {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "vorbis" => Ok(Self::Vorbis),
+            "mp3" => Ok(Self::Mp3),
+            "aac" => Ok(Self::Aac),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for AudioCodec {
+    fn default() -> Self {
+        Self::Vorbis
+    }
+}
+
 #[derive(Clone, Copy, Debug, Hash, PartialOrd, Ord, PartialEq, Eq)]
 pub enum AudioFormat {
     F64,
@@ -124,12 +158,96 @@ impl Default for NormalisationMethod {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FadeCurve {
+    Linear,
+    EqualPower,
+    SCurve,
+}
+
+impl FromStr for FadeCurve {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "linear" => Ok(Self::Linear),
+            "equal-power" => Ok(Self::EqualPower),
+            "s-curve" => Ok(Self::SCurve),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for FadeCurve {
+    fn default() -> Self {
+        Self::EqualPower
+    }
+}
+
+impl FadeCurve {
+    // Gain of the incoming signal at `progress` (0.0 to 1.0) of the fade.
+    pub fn fade_in_gain(&self, progress: f64) -> f64 {
+        let progress = progress.max(0.0).min(1.0);
+        match self {
+            Self::Linear => progress,
+            // Keeps the summed power of both signals constant, so that
+            // uncorrelated material does not dip in loudness halfway.
+            Self::EqualPower => f64::sin(progress * std::f64::consts::FRAC_PI_2),
+            Self::SCurve => progress * progress * (3.0 - 2.0 * progress),
+        }
+    }
+
+    // Gain of the outgoing signal at `progress` (0.0 to 1.0) of the fade.
+    pub fn fade_out_gain(&self, progress: f64) -> f64 {
+        self.fade_in_gain(1.0 - progress)
+    }
+}
+
+// When the sleep timer pauses playback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SleepTimer {
+    // after some time from when it is set
+    After(Duration),
+    // at the end of the track that is playing
+    EndOfTrack,
+    // at the end of the album that is playing, i.e. before a track from another album
+    EndOfAlbum,
+}
+
+impl FromStr for SleepTimer {
+    type Err = ();
+    // either a number of minutes, or the end of what is playing
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "end-of-track" => Ok(Self::EndOfTrack),
+            "end-of-album" => Ok(Self::EndOfAlbum),
+            minutes => match minutes.parse::<u64>() {
+                Ok(minutes) if minutes > 0 => Ok(Self::After(Duration::from_secs(minutes * 60))),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct PlayerConfig {
     pub bitrate: Bitrate,
+    // the codec of the file that is played when there are several, if it can be decoded, with the
+    // others to fall back to; only Vorbis can be passed through
+    pub preferred_codec: AudioCodec,
     pub gapless: bool,
     pub passthrough: bool,
 
+    // audio is resampled when the output runs at another rate than the decoded tracks
+    pub sample_rate: u32,
+
+    // crossfading between consecutive tracks requires gapless playback
+    pub crossfade: Duration,
+    pub crossfade_curve: FadeCurve,
+
+    // a short ramp when playback starts or stops in the middle of a track (play, pause, seek and
+    // stop), against clicks
+    pub fade: Duration,
+
     pub normalisation: bool,
     pub normalisation_type: NormalisationType,
     pub normalisation_method: NormalisationMethod,
@@ -139,6 +257,22 @@ pub struct PlayerConfig {
     pub normalisation_release: Duration,
     pub normalisation_knee: f64,
 
+    pub equalizer: Vec<EqualizerBand>,
+
+    // a sink that fails is restarted up to `sink_retries` times, waiting twice as long before
+    // every retry, before the player switches to the fallback sink or pauses
+    pub sink_retries: u32,
+    pub sink_retry_backoff: Duration,
+
+    // where the files of `spotify:local` URIs are looked for
+    pub local_files_dir: Option<PathBuf>,
+
+    // how long the audio is faded out before the sleep timer pauses playback
+    pub sleep_timer_fade: Duration,
+
+    // how much decoded audio is kept ahead of the sink, against dropouts when decoding is held up
+    pub output_buffer: Duration,
+
     // pass function pointers so they can be lazily instantiated *after* spawning a thread
     // (thereby circumventing Send bounds that they might not satisfy)
     pub ditherer: Option<DithererBuilder>,
@@ -148,7 +282,12 @@ impl Default for PlayerConfig {
     fn default() -> Self {
         Self {
             bitrate: Bitrate::default(),
+            preferred_codec: AudioCodec::default(),
             gapless: true,
+            sample_rate: SAMPLE_RATE,
+            crossfade: Duration::default(),
+            crossfade_curve: FadeCurve::default(),
+            fade: Duration::default(),
             normalisation: false,
             normalisation_type: NormalisationType::default(),
             normalisation_method: NormalisationMethod::default(),
@@ -157,19 +296,26 @@ impl Default for PlayerConfig {
             normalisation_attack: Duration::from_millis(5),
             normalisation_release: Duration::from_millis(100),
             normalisation_knee: 1.0,
+            equalizer: Vec::new(),
+            sink_retries: 3,
+            sink_retry_backoff: Duration::from_millis(500),
+            local_files_dir: None,
+            sleep_timer_fade: Duration::from_secs(30),
+            output_buffer: Duration::from_millis(500),
             passthrough: false,
             ditherer: Some(mk_ditherer::<TriangularDitherer>),
         }
     }
 }
 
-// fields are intended for volume control range in dB
-#[derive(Clone, Copy, Debug)]
+// fields are intended for volume control range in dB, except for the table which has its own
+#[derive(Clone, Debug)]
 pub enum VolumeCtrl {
     Cubic(f64),
     Fixed,
     Linear,
     Log(f64),
+    Table(VolumeTable),
 }
 
 impl FromStr for VolumeCtrl {
@@ -191,8 +337,15 @@ impl VolumeCtrl {
     // Taken from: https://www.dr-lex.be/info-stuff/volumecontrols.html
     pub const DEFAULT_DB_RANGE: f64 = 60.0;
 
+    // A table is read from the file that follows `table:`.
     pub fn from_str_with_range(s: &str, db_range: f64) -> Result<Self, <Self as FromStr>::Err> {
         use self::VolumeCtrl::*;
+        if let Some(path) = s.strip_prefix("table:") {
+            return VolumeTable::from_file(path)
+                .map(Table)
+                .map_err(|e| error!("{}", e));
+        }
+
         match s.to_lowercase().as_ref() {
             "cubic" => Ok(Cubic(db_range)),
             "fixed" => Ok(Fixed),
@@ -202,3 +355,27 @@ impl VolumeCtrl {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleep_timer_from_str() {
+        assert_eq!(
+            SleepTimer::from_str("30"),
+            Ok(SleepTimer::After(Duration::from_secs(30 * 60)))
+        );
+        assert_eq!(
+            SleepTimer::from_str("end-of-track"),
+            Ok(SleepTimer::EndOfTrack)
+        );
+        assert_eq!(
+            SleepTimer::from_str("End-Of-Album"),
+            Ok(SleepTimer::EndOfAlbum)
+        );
+        for invalid in &["0", "-5", "1.5", "", "end-of-playlist"] {
+            assert_eq!(SleepTimer::from_str(invalid), Err(()), "{}", invalid);
+        }
+    }
+}
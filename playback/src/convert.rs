@@ -19,6 +19,7 @@ impl i24 {
 
 pub struct Converter {
     ditherer: Option<Box<dyn Ditherer>>,
+    dither_config: Option<DithererBuilder>,
 }
 
 impl Converter {
@@ -28,12 +29,22 @@ impl Converter {
             info!("Converting with ditherer: {}", ditherer.name());
             Self {
                 ditherer: Some(ditherer),
+                dither_config,
             }
         } else {
-            Self { ditherer: None }
+            Self {
+                ditherer: None,
+                dither_config,
+            }
         }
     }
 
+    // The kind of ditherer, for when the same samples are converted for more than one
+    // output and each needs a converter with its own ditherer state.
+    pub fn dither_config(&self) -> Option<DithererBuilder> {
+        self.dither_config
+    }
+
     /// To convert PCM samples from floating point normalized as `-1.0..=1.0`
     /// to 32-bit signed integer, multiply by 2147483648 (0x80000000) and
     /// saturate at the bounds of `i32`.
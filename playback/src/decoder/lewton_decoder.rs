@@ -8,7 +8,23 @@ use lewton::VorbisError::{BadAudio, OggError};
 
 use std::io::{Read, Seek};
 
-pub struct VorbisDecoder<R: Read + Seek>(OggStreamReader<R>);
+use crate::metadata::FileFormat;
+
+// How far before the position to seek to the page search starts, so that a packet ends before it
+// that decoding can start with. Even the longest Vorbis blocks produce fewer samples than this.
+pub(super) const SEEK_PRE_ROLL: u64 = 8192;
+
+pub const FORMATS: &[FileFormat] = &[
+    FileFormat::OGG_VORBIS_96,
+    FileFormat::OGG_VORBIS_160,
+    FileFormat::OGG_VORBIS_320,
+];
+
+pub struct VorbisDecoder<R: Read + Seek> {
+    reader: OggStreamReader<R>,
+    // what is left of the packet that a seek ended up in
+    pending: Option<Vec<f32>>,
+}
 
 impl<R> VorbisDecoder<R>
 where
@@ -17,7 +33,25 @@ where
     pub fn new(input: R) -> DecoderResult<VorbisDecoder<R>> {
         let reader =
             OggStreamReader::new(input).map_err(|e| DecoderError::LewtonDecoder(e.to_string()))?;
-        Ok(VorbisDecoder(reader))
+        Ok(VorbisDecoder {
+            reader,
+            pending: None,
+        })
+    }
+
+    fn read_samples(&mut self) -> DecoderResult<Option<Vec<f32>>> {
+        loop {
+            match self
+                .reader
+                .read_dec_packet_generic::<InterleavedSamples<f32>>()
+            {
+                Ok(Some(packet)) => return Ok(Some(packet.samples)),
+                Ok(None) => return Ok(None),
+                Err(BadAudio(AudioIsHeader)) => (),
+                Err(OggError(NoCapturePatternFound)) => (),
+                Err(e) => return Err(DecoderError::LewtonDecoder(e.to_string())),
+            }
+        }
     }
 }
 
@@ -25,22 +59,100 @@ impl<R> AudioDecoder for VorbisDecoder<R>
 where
     R: Read + Seek,
 {
-    fn seek(&mut self, absgp: u64) -> DecoderResult<()> {
-        self.0
-            .seek_absgp_pg(absgp)
+    // Ogg only tells positions by page, so this seeks to a page a bit before the position and
+    // decodes from there. Once a page ends, the positions of the packets decoded so far are
+    // known, and what comes before the position is dropped.
+    fn seek(&mut self, absgp: u64) -> DecoderResult<u64> {
+        self.pending = None;
+        self.reader
+            .seek_absgp_pg(absgp.saturating_sub(SEEK_PRE_ROLL))
             .map_err(|e| DecoderError::LewtonDecoder(e.to_string()))?;
-        Ok(())
-    }
 
-    fn next_packet(&mut self) -> DecoderResult<Option<AudioPacket>> {
+        let channels = usize::max(self.reader.ident_hdr.audio_channels as usize, 1);
+        let mut unplaced: Vec<Vec<f32>> = Vec::new();
+
         loop {
-            match self.0.read_dec_packet_generic::<InterleavedSamples<f32>>() {
-                Ok(Some(packet)) => return Ok(Some(AudioPacket::samples_from_f32(packet.samples))),
-                Ok(None) => return Ok(None),
-                Err(BadAudio(AudioIsHeader)) => (),
-                Err(OggError(NoCapturePatternFound)) => (),
-                Err(e) => return Err(DecoderError::LewtonDecoder(e.to_string())),
+            let samples = match self.read_samples()? {
+                Some(samples) => samples,
+                // The position is past the end.
+                None => return Ok(self.reader.get_last_absgp().unwrap_or(absgp)),
+            };
+            unplaced.push(samples);
+
+            let end = match self.reader.get_last_absgp() {
+                Some(end) => end,
+                None => continue,
+            };
+            let frames: usize = unplaced
+                .iter()
+                .map(|samples| samples.len() / channels)
+                .sum();
+            let mut position = end.saturating_sub(frames as u64);
+
+            let mut start = None;
+            let mut kept = Vec::new();
+            for samples in unplaced.drain(..) {
+                let frames = (samples.len() / channels) as u64;
+                if position + frames > absgp {
+                    let skip = absgp.saturating_sub(position);
+                    start.get_or_insert(position + skip);
+                    // Panic safety: only what comes before the end of the packet is skipped
+                    kept.extend_from_slice(&samples[skip as usize * channels..]);
+                }
+                position += frames;
+            }
+
+            if let Some(start) = start {
+                self.pending = Some(kept);
+                return Ok(start);
             }
         }
     }
+
+    fn next_packet(&mut self) -> DecoderResult<Option<AudioPacket>> {
+        if let Some(samples) = self.pending.take() {
+            return Ok(Some(AudioPacket::samples_from_f32(samples)));
+        }
+
+        Ok(self.read_samples()?.map(AudioPacket::samples_from_f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::{vorbis_stream, PACKET_FRAMES};
+    use std::io::Cursor;
+
+    const PACKETS: usize = 400;
+
+    fn frames_left(decoder: &mut VorbisDecoder<Cursor<Vec<u8>>>) -> u64 {
+        let mut frames = 0;
+        while let Some(packet) = decoder.next_packet().unwrap() {
+            frames += packet.samples().unwrap().len() as u64 / 2;
+        }
+        frames
+    }
+
+    #[test]
+    fn seek_starts_at_the_position() {
+        let end = (PACKETS as u64 - 1) * PACKET_FRAMES;
+        let mut decoder = VorbisDecoder::new(Cursor::new(vorbis_stream(PACKETS))).unwrap();
+        assert_eq!(frames_left(&mut decoder), end);
+
+        // at the start, within a packet, at the end of a packet and page, and after the pre-roll
+        for &position in &[0, 1, 100, 4 * PACKET_FRAMES, 20000, end - 1] {
+            assert_eq!(decoder.seek(position).unwrap(), position);
+            // what is decoded from there on starts exactly at the position
+            assert_eq!(frames_left(&mut decoder), end - position, "{}", position);
+        }
+    }
+
+    #[test]
+    fn seek_past_the_end() {
+        let end = (PACKETS as u64 - 1) * PACKET_FRAMES;
+        let mut decoder = VorbisDecoder::new(Cursor::new(vorbis_stream(PACKETS))).unwrap();
+        assert_eq!(decoder.seek(end + 1000).unwrap(), end);
+        assert_eq!(frames_left(&mut decoder), 0);
+    }
 }
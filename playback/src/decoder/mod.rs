@@ -1,17 +1,28 @@
+use std::io::{Read, Seek};
+
 use thiserror::Error;
 
+use crate::metadata::FileFormat;
+
 mod lewton_decoder;
 pub use lewton_decoder::VorbisDecoder;
 
 mod passthrough_decoder;
 pub use passthrough_decoder::PassthroughDecoder;
 
+#[cfg(feature = "symphonia-decoder")]
+mod symphonia_decoder;
+#[cfg(feature = "symphonia-decoder")]
+pub use symphonia_decoder::SymphoniaDecoder;
+
 #[derive(Error, Debug)]
 pub enum DecoderError {
     #[error("Lewton Decoder Error: {0}")]
     LewtonDecoder(String),
     #[error("Passthrough Decoder Error: {0}")]
     PassthroughDecoder(String),
+    #[error("Symphonia Decoder Error: {0}")]
+    SymphoniaDecoder(String),
 }
 
 pub type DecoderResult<T> = Result<T, DecoderError>;
@@ -26,6 +37,7 @@ pub enum AudioPacketError {
 
 pub type AudioPacketResult<T> = Result<T, AudioPacketError>;
 
+#[derive(Clone)]
 pub enum AudioPacket {
     Samples(Vec<f64>),
     OggData(Vec<u8>),
@@ -60,6 +72,168 @@ impl AudioPacket {
 }
 
 pub trait AudioDecoder {
-    fn seek(&mut self, absgp: u64) -> DecoderResult<()>;
+    // Seeks to a position in samples per channel, and returns the position that decoding actually
+    // continues from, which only differs from it if the position is past the end.
+    fn seek(&mut self, absgp: u64) -> DecoderResult<u64>;
     fn next_packet(&mut self) -> DecoderResult<Option<AudioPacket>>;
 }
+
+// What decoders read from, e.g. a decrypted audio file.
+pub trait DecoderInput: Read + Seek + Send + Sync {}
+
+impl<T: Read + Seek + Send + Sync> DecoderInput for T {}
+
+pub type DecoderBuilder =
+    fn(Box<dyn DecoderInput>, FileFormat) -> DecoderResult<Box<dyn AudioDecoder + Send>>;
+
+fn mk_vorbis(
+    input: Box<dyn DecoderInput>,
+    _: FileFormat,
+) -> DecoderResult<Box<dyn AudioDecoder + Send>> {
+    Ok(Box::new(VorbisDecoder::new(input)?))
+}
+
+#[cfg(feature = "symphonia-decoder")]
+fn mk_symphonia(
+    input: Box<dyn DecoderInput>,
+    format: FileFormat,
+) -> DecoderResult<Box<dyn AudioDecoder + Send>> {
+    Ok(Box::new(SymphoniaDecoder::new(input, format)?))
+}
+
+// Decoders with the file formats they can decode, the preferred decoder for a format goes first.
+pub const DECODERS: &[(&str, &[FileFormat], DecoderBuilder)] = &[
+    ("lewton", lewton_decoder::FORMATS, mk_vorbis),
+    #[cfg(feature = "symphonia-decoder")]
+    ("symphonia", symphonia_decoder::FORMATS, mk_symphonia),
+];
+
+pub fn find(format: FileFormat) -> Option<(&'static str, DecoderBuilder)> {
+    DECODERS
+        .iter()
+        .find(|decoder| decoder.1.contains(&format))
+        .map(|decoder| (decoder.0, decoder.2))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use ogg::{PacketWriteEndInfo, PacketWriter};
+
+    // Samples per channel that each packet of `vorbis_stream` decodes to, but the first.
+    pub const PACKET_FRAMES: u64 = 128;
+    const PACKETS_PER_PAGE: usize = 4;
+
+    // Vorbis packs its headers and packets least significant bit first.
+    struct BitWriter {
+        data: Vec<u8>,
+        bits: usize,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, bits: usize) {
+            for i in 0..bits {
+                if self.bits == self.data.len() * 8 {
+                    self.data.push(0);
+                }
+                if i < 32 && value >> i & 1 == 1 {
+                    *self.data.last_mut().unwrap() |= 1 << (self.bits % 8);
+                }
+                self.bits += 1;
+            }
+        }
+    }
+
+    fn header(packet_type: u8) -> BitWriter {
+        let mut data = vec![packet_type];
+        data.extend_from_slice(b"vorbis");
+        BitWriter {
+            bits: data.len() * 8,
+            data,
+        }
+    }
+
+    // An Ogg/Vorbis stream of silence, with the simplest setup there is: one codebook, one floor,
+    // residue, mapping and mode, and short blocks of 256 only. Every packet says its floors are
+    // unused, so that it decodes to silence without any residue being coded.
+    pub fn vorbis_stream(packets: usize) -> Vec<u8> {
+        vorbis_stream_with_comments(packets, &[])
+    }
+
+    // The same with comments such as "TITLE=Silence" in its comment header.
+    pub fn vorbis_stream_with_comments(packets: usize, comments: &[&str]) -> Vec<u8> {
+        let mut ident = header(1);
+        ident.write(0, 32); // version
+        ident.write(2, 8); // channels
+        ident.write(44100, 32);
+        ident.write(0, 32 * 3); // bitrates
+        ident.write(8 | 8 << 4, 8); // blocksizes, log2
+        ident.write(1, 8); // framing
+
+        let mut comment = header(3);
+        comment.write(0, 32); // vendor
+        comment.write(comments.len() as u32, 32);
+        for text in comments {
+            comment.write(text.len() as u32, 32);
+            for &byte in text.as_bytes() {
+                comment.write(byte as u32, 8);
+            }
+        }
+        comment.write(1, 8); // framing
+
+        let mut setup = header(5);
+        setup.write(0, 8); // codebooks - 1
+        setup.write(0x564342, 24);
+        setup.write(1, 16); // dimensions
+        setup.write(2, 24); // entries
+        setup.write(0, 2); // not ordered, not sparse
+        setup.write(0, 5 * 2); // lengths - 1
+        setup.write(0, 4); // no lookup
+        setup.write(0, 6 + 16); // time domain transforms
+        setup.write(0, 6); // floors - 1
+        setup.write(1, 16); // floor type
+        setup.write(0, 5); // partitions
+        setup.write(0, 2); // multiplier - 1
+        setup.write(7, 4); // range bits
+        setup.write(0, 6); // residues - 1
+        setup.write(0, 16); // residue type
+        setup.write(0, 24); // begin
+        setup.write(128, 24); // end
+        setup.write(31, 24); // partition size - 1
+        setup.write(0, 6); // classifications - 1
+        setup.write(0, 8); // classbook
+        setup.write(0, 4); // cascade
+        setup.write(0, 6); // mappings - 1
+        setup.write(0, 16); // mapping type
+        setup.write(0, 4); // no submaps, no coupling, reserved
+        setup.write(0, 8 * 3); // time, floor and residue of the submap
+        setup.write(0, 6); // modes - 1
+        setup.write(0, 1 + 16 + 16 + 8); // short block, window, transform, mapping
+        setup.write(1, 1); // framing
+
+        let mut writer = PacketWriter::new(Vec::new());
+        let mut write = |data: Vec<u8>, info, absgp| {
+            writer
+                .write_packet(data.into_boxed_slice(), 1, info, absgp)
+                .unwrap()
+        };
+        write(ident.data, PacketWriteEndInfo::EndPage, 0);
+        write(comment.data, PacketWriteEndInfo::NormalPacket, 0);
+        write(setup.data, PacketWriteEndInfo::EndPage, 0);
+
+        // The first packet only primes the decoder, so each packet ends where the one before it
+        // started decoding to.
+        for packet in 0..packets {
+            let info = if packet + 1 == packets {
+                PacketWriteEndInfo::EndStream
+            } else if packet % PACKETS_PER_PAGE == PACKETS_PER_PAGE - 1 {
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            // audio packet, floors of both channels unused
+            write(vec![0], info, packet as u64 * PACKET_FRAMES);
+        }
+
+        writer.into_inner()
+    }
+}
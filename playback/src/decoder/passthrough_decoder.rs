@@ -1,6 +1,10 @@
 // Passthrough decoder for librespot
+use super::lewton_decoder::SEEK_PRE_ROLL;
 use super::{AudioDecoder, AudioPacket, DecoderError, DecoderResult};
+use lewton::audio::{get_decoded_sample_count, AudioReadError};
+use lewton::header::{read_header_ident, read_header_setup, IdentHeader, SetupHeader};
 use ogg::{OggReadError, Packet, PacketReader, PacketWriteEndInfo, PacketWriter};
+use std::collections::VecDeque;
 use std::io::{Read, Seek};
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -32,6 +36,14 @@ pub struct PassthroughDecoder<R: Read + Seek> {
     ident: Box<[u8]>,
     comment: Box<[u8]>,
     setup: Box<[u8]>,
+    // parsed to tell how many samples the packets decode to
+    ident_hdr: IdentHeader,
+    setup_hdr: SetupHeader,
+    // packets that were read while seeking, and are written before anything else
+    pending: VecDeque<Packet>,
+    // The first packet after a seek has to share a page with the packets that follow it, see
+    // seek().
+    continue_page: bool,
 }
 
 impl<R: Read + Seek> PassthroughDecoder<R> {
@@ -50,6 +62,15 @@ impl<R: Read + Seek> PassthroughDecoder<R> {
         let comment = get_header(3, &mut rdr)?;
         let setup = get_header(5, &mut rdr)?;
 
+        let ident_hdr = read_header_ident(&ident)
+            .map_err(|e| DecoderError::PassthroughDecoder(e.to_string()))?;
+        let setup_hdr = read_header_setup(
+            &setup,
+            ident_hdr.audio_channels,
+            (ident_hdr.blocksize_0, ident_hdr.blocksize_1),
+        )
+        .map_err(|e| DecoderError::PassthroughDecoder(e.to_string()))?;
+
         // remove un-needed packets
         rdr.delete_unread_packets();
 
@@ -61,19 +82,35 @@ impl<R: Read + Seek> PassthroughDecoder<R> {
             ident,
             comment,
             setup,
+            ident_hdr,
+            setup_hdr,
+            pending: VecDeque::new(),
+            continue_page: false,
             eos: false,
             bos: false,
         })
     }
+
+    fn read_packet(&mut self) -> Result<Option<Packet>, OggReadError> {
+        match self.pending.pop_front() {
+            Some(pck) => Ok(Some(pck)),
+            None => self.rdr.read_packet(),
+        }
+    }
 }
 
 impl<R: Read + Seek> AudioDecoder for PassthroughDecoder<R> {
-    fn seek(&mut self, absgp: u64) -> DecoderResult<()> {
+    // The packets are passed on as they are, so the output can't simply start in the middle of
+    // one. Instead, the new stream starts with the last packet that ends before the position,
+    // which a decoder needs to get going anyway but doesn't play. The first page then claims
+    // fewer samples than the packets on it decode to, which tells decoders to drop the excess
+    // from the beginning, so that playback starts exactly at the position.
+    fn seek(&mut self, absgp: u64) -> DecoderResult<u64> {
         // add an eos to previous stream if missing
         if self.bos && !self.eos {
-            match self.rdr.read_packet() {
+            match self.read_packet() {
                 Ok(Some(pck)) => {
-                    let absgp_page = pck.absgp_page() - self.ofsgp_page;
+                    let absgp_page = pck.absgp_page().saturating_sub(self.ofsgp_page);
                     self.wtr
                         .write_packet(
                             pck.data.into_boxed_slice(),
@@ -91,26 +128,96 @@ impl<R: Read + Seek> AudioDecoder for PassthroughDecoder<R> {
         self.bos = false;
         self.ofsgp_page = 0;
         self.stream_serial += 1;
+        self.pending.clear();
+
+        let found = self
+            .rdr
+            .seek_absgp(None, absgp.saturating_sub(SEEK_PRE_ROLL))
+            .map_err(|e| DecoderError::PassthroughDecoder(e.to_string()))?;
+        if !found {
+            return Err(DecoderError::PassthroughDecoder(
+                "Seek position is past the end".to_string(),
+            ));
+        }
+
+        // Where packets end is only known at the end of a page, until then the packets are kept
+        // with the number of samples they decode to.
+        let mut unplaced: Vec<(Packet, u64)> = Vec::new();
+        let mut primer: Option<(Packet, u64)> = None;
+        let mut last_end: Option<u64> = None;
+        let mut after_headers = false;
+
+        loop {
+            let pck = match self.rdr.read_packet() {
+                Ok(Some(pck)) => pck,
+                Ok(None) | Err(OggReadError::NoCapturePatternFound) => {
+                    return Err(DecoderError::PassthroughDecoder(
+                        "Seek position is past the end".to_string(),
+                    ))
+                }
+                Err(e) => return Err(DecoderError::PassthroughDecoder(e.to_string())),
+            };
 
-        match self.rdr.seek_absgp(None, absgp) {
-            Ok(_) => {
-                // need to set some offset for next_page()
-                let pck = self
-                    .rdr
-                    .read_packet()
-                    .map_err(|e| DecoderError::PassthroughDecoder(e.to_string()))?;
-                match pck {
-                    Some(pck) => {
-                        self.ofsgp_page = pck.absgp_page();
-                        debug!("Seek to offset page {}", self.ofsgp_page);
-                        Ok(())
+            let samples =
+                match get_decoded_sample_count(&self.ident_hdr, &self.setup_hdr, &pck.data) {
+                    // The first packet of a stream has nothing to overlap with, so it decodes to
+                    // nothing.
+                    Ok(_) if after_headers => 0,
+                    Ok(samples) => samples as u64,
+                    Err(AudioReadError::AudioIsHeader) => {
+                        after_headers = true;
+                        continue;
                     }
-                    None => Err(DecoderError::PassthroughDecoder(
-                        "Packet is None".to_string(),
-                    )),
+                    Err(e) => return Err(DecoderError::PassthroughDecoder(e.to_string())),
+                };
+            after_headers = false;
+
+            let page_end = if pck.last_in_page() {
+                Some(pck.absgp_page())
+            } else {
+                None
+            };
+            unplaced.push((pck, samples));
+
+            // The first page end tells where the packets so far end, which are then counted on
+            // from. The last page of a stream may end before its last packet, so pages that
+            // follow aren't counted back from.
+            if let (None, Some(page_end)) = (last_end, page_end) {
+                let samples: u64 = unplaced.iter().map(|(_, samples)| samples).sum();
+                last_end = Some(page_end.saturating_sub(samples));
+            }
+            let mut end = match last_end {
+                Some(end) => end,
+                None => continue,
+            };
+
+            let mut placed = unplaced.drain(..);
+            while let Some((pck, samples)) = placed.next() {
+                end += samples;
+                if end <= absgp {
+                    primer = Some((pck, end));
+                    continue;
                 }
+
+                // This is the first packet that ends after the position, so the stream starts
+                // with the one before it, or with this one if the position is before any.
+                let primer_end = match primer.take() {
+                    Some((primer, primer_end)) => {
+                        self.pending.push_back(primer);
+                        primer_end
+                    }
+                    None => end,
+                };
+                self.pending.push_back(pck);
+                self.pending.extend(placed.map(|(pck, _)| pck));
+
+                let position = u64::max(absgp, primer_end);
+                self.ofsgp_page = position;
+                self.continue_page = true;
+                debug!("Seek to {} in a stream from {}", position, primer_end);
+                return Ok(position);
             }
-            Err(e) => Err(DecoderError::PassthroughDecoder(e.to_string())),
+            last_end = Some(end);
         }
     }
 
@@ -146,7 +253,7 @@ impl<R: Read + Seek> AudioDecoder for PassthroughDecoder<R> {
         }
 
         loop {
-            let pck = match self.rdr.read_packet() {
+            let pck = match self.read_packet() {
                 Ok(Some(pck)) => pck,
                 Ok(None) | Err(OggReadError::NoCapturePatternFound) => {
                     info!("end of streaming");
@@ -158,7 +265,7 @@ impl<R: Read + Seek> AudioDecoder for PassthroughDecoder<R> {
             let pckgp_page = pck.absgp_page();
 
             // skip till we have audio and a calculable granule position
-            if pckgp_page == 0 || pckgp_page == self.ofsgp_page {
+            if pckgp_page == 0 {
                 continue;
             }
 
@@ -166,18 +273,19 @@ impl<R: Read + Seek> AudioDecoder for PassthroughDecoder<R> {
             let inf = if pck.last_in_stream() {
                 self.eos = true;
                 PacketWriteEndInfo::EndStream
-            } else if pck.last_in_page() {
+            } else if pck.last_in_page() && !self.continue_page {
                 PacketWriteEndInfo::EndPage
             } else {
                 PacketWriteEndInfo::NormalPacket
             };
+            self.continue_page = false;
 
             self.wtr
                 .write_packet(
                     pck.data.into_boxed_slice(),
                     self.stream_serial,
                     inf,
-                    pckgp_page - self.ofsgp_page,
+                    pckgp_page.saturating_sub(self.ofsgp_page),
                 )
                 .map_err(|e| DecoderError::PassthroughDecoder(e.to_string()))?;
 
@@ -190,3 +298,67 @@ impl<R: Read + Seek> AudioDecoder for PassthroughDecoder<R> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::{vorbis_stream, PACKET_FRAMES};
+    use std::io::Cursor;
+
+    const PACKETS: usize = 400;
+
+    // The audio pages of the passed on stream, with their granule positions and the number of
+    // packets on them.
+    fn audio_pages(decoder: &mut PassthroughDecoder<Cursor<Vec<u8>>>) -> Vec<(u64, u64)> {
+        let mut data = Vec::new();
+        while let Some(packet) = decoder.next_packet().unwrap() {
+            data.extend_from_slice(packet.oggdata().unwrap());
+        }
+
+        let mut rdr = PacketReader::new(Cursor::new(data));
+        let mut pages = Vec::new();
+        let mut packets = 0;
+        while let Some(pck) = rdr.read_packet().unwrap() {
+            // header packets have odd types
+            if pck.data[0] & 1 == 1 {
+                continue;
+            }
+            packets += 1;
+            if pck.last_in_page() {
+                pages.push((pck.absgp_page(), packets));
+                packets = 0;
+            }
+        }
+        pages
+    }
+
+    #[test]
+    fn seek_starts_at_the_position() {
+        let end = (PACKETS as u64 - 1) * PACKET_FRAMES;
+        let mut decoder = PassthroughDecoder::new(Cursor::new(vorbis_stream(PACKETS))).unwrap();
+
+        for &position in &[0, 1, 100, 4 * PACKET_FRAMES, 20000, end - 1] {
+            assert_eq!(decoder.seek(position).unwrap(), position);
+            let pages = audio_pages(&mut decoder);
+
+            // The new stream starts with the packet that ends at or before the position, which
+            // only primes the decoder. The first page claims fewer samples than the packets after
+            // it decode to, and the excess is dropped, so that the first sample played is the one
+            // at the position.
+            let primer_end = position / PACKET_FRAMES * PACKET_FRAMES;
+            let (first_absgp, first_packets) = pages[0];
+            let decoded = (first_packets - 1) * PACKET_FRAMES;
+            assert_eq!(primer_end + decoded - first_absgp, position, "{}", position);
+
+            // and it ends where the original did
+            assert_eq!(pages.last().unwrap().0, end - position, "{}", position);
+        }
+    }
+
+    #[test]
+    fn seek_past_the_end() {
+        let end = (PACKETS as u64 - 1) * PACKET_FRAMES;
+        let mut decoder = PassthroughDecoder::new(Cursor::new(vorbis_stream(PACKETS))).unwrap();
+        assert!(decoder.seek(end + 1000).is_err());
+    }
+}
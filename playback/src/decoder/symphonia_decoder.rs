@@ -0,0 +1,408 @@
+use super::{AudioDecoder, AudioPacket, DecoderError, DecoderResult};
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream, MediaSourceStreamOptions};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::metadata::FileFormat;
+use crate::resampler::Resampler;
+use crate::{NUM_CHANNELS, SAMPLE_RATE};
+
+// Symphonia reads from a `MediaSource`, which also has to tell its length for seeking.
+struct SymphoniaSource<R> {
+    reader: R,
+    byte_len: Option<u64>,
+}
+
+impl<R: Read + Seek> SymphoniaSource<R> {
+    fn new(mut reader: R) -> io::Result<Self> {
+        let byte_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            reader,
+            byte_len: Some(byte_len),
+        })
+    }
+}
+
+impl<R: Read> Read for SymphoniaSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for SymphoniaSource<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+impl<R: Read + Seek + Send + Sync> MediaSource for SymphoniaSource<R> {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.byte_len
+    }
+}
+
+pub const FORMATS: &[FileFormat] = &[
+    FileFormat::OGG_VORBIS_96,
+    FileFormat::OGG_VORBIS_160,
+    FileFormat::OGG_VORBIS_320,
+    FileFormat::MP3_96,
+    FileFormat::MP3_160,
+    FileFormat::MP3_256,
+    FileFormat::MP3_320,
+    FileFormat::AAC_160,
+    FileFormat::AAC_320,
+    FileFormat::MP4_128,
+];
+
+fn hint(format: FileFormat) -> Hint {
+    let mut hint = Hint::new();
+    match format {
+        FileFormat::OGG_VORBIS_96 | FileFormat::OGG_VORBIS_160 | FileFormat::OGG_VORBIS_320 => {
+            hint.with_extension("ogg")
+        }
+        FileFormat::MP3_96 | FileFormat::MP3_160 | FileFormat::MP3_256 | FileFormat::MP3_320 => {
+            hint.with_extension("mp3")
+        }
+        FileFormat::AAC_160 | FileFormat::AAC_320 => hint.with_extension("aac"),
+        _ => hint.with_extension("mp4"),
+    };
+    hint
+}
+
+// Decodes everything that symphonia supports into the 44.1 kHz stereo that the player works
+// with. Mono is played on both channels, and only the first two channels of anything else.
+pub struct SymphoniaDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    sample_buffer: Option<SampleBuffer<f64>>,
+    // for files that are not at 44.1 kHz, along with the rate it was made for
+    resampler: Option<(u32, Resampler)>,
+    // frames that are still to be dropped to get from a seek point to the requested position
+    skip_frames: u64,
+}
+
+impl SymphoniaDecoder {
+    pub fn new<R>(input: R, format: FileFormat) -> DecoderResult<Self>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        Self::with_hint(input, hint(format))
+    }
+
+    // For files that Spotify doesn't have a format for, e.g. local FLAC files.
+    pub fn with_extension<R>(input: R, extension: &str) -> DecoderResult<Self>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        let mut hint = Hint::new();
+        hint.with_extension(extension);
+        Self::with_hint(input, hint)
+    }
+
+    fn with_hint<R>(input: R, hint: Hint) -> DecoderResult<Self>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        let source = SymphoniaSource::new(input)
+            .map_err(|e| DecoderError::SymphoniaDecoder(e.to_string()))?;
+        let stream = MediaSourceStream::new(Box::new(source), MediaSourceStreamOptions::default());
+
+        let format_options = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let probed = symphonia::default::get_probe()
+            .format(&hint, stream, &format_options, &MetadataOptions::default())
+            .map_err(|e| DecoderError::SymphoniaDecoder(e.to_string()))?;
+
+        let track = probed
+            .format
+            .default_track()
+            .ok_or_else(|| DecoderError::SymphoniaDecoder("No audio track in file".to_string()))?;
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|e| DecoderError::SymphoniaDecoder(e.to_string()))?;
+        let track_id = track.id;
+        let time_base = track.codec_params.time_base;
+
+        Ok(Self {
+            format: probed.format,
+            decoder,
+            track_id,
+            time_base,
+            sample_buffer: None,
+            resampler: None,
+            skip_frames: 0,
+        })
+    }
+
+    fn output_samples(&mut self, samples: &[f64], channels: usize, sample_rate: u32) -> Vec<f64> {
+        if channels == 0 {
+            return Vec::new();
+        }
+
+        let frames = samples.len() / channels;
+        let skip = usize::min(self.skip_frames as usize, frames);
+        self.skip_frames -= skip as u64;
+
+        let mut output = Vec::with_capacity((frames - skip) * NUM_CHANNELS as usize);
+        for frame in samples.chunks_exact(channels).skip(skip) {
+            match frame {
+                [mono] => output.extend_from_slice(&[*mono, *mono]),
+                [left, right, ..] => output.extend_from_slice(&[*left, *right]),
+                [] => (),
+            }
+        }
+
+        if sample_rate == SAMPLE_RATE {
+            return output;
+        }
+
+        match self.resampler {
+            Some((rate, ref mut resampler)) if rate == sample_rate => resampler.resample(&output),
+            _ => {
+                let mut resampler = Resampler::new(sample_rate, SAMPLE_RATE);
+                let output = resampler.resample(&output);
+                self.resampler = Some((sample_rate, resampler));
+                output
+            }
+        }
+    }
+}
+
+impl AudioDecoder for SymphoniaDecoder {
+    fn seek(&mut self, absgp: u64) -> DecoderResult<u64> {
+        let seeked_to = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(absgp as f64 / SAMPLE_RATE as f64),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(|e| DecoderError::SymphoniaDecoder(e.to_string()))?;
+
+        self.decoder.reset();
+        if let Some((_, ref mut resampler)) = self.resampler {
+            resampler.reset();
+        }
+
+        // An accurate seek lands on the packet before the requested position, so decode from
+        // there and drop what comes before it.
+        let pre_roll = seeked_to.required_ts.saturating_sub(seeked_to.actual_ts);
+        self.skip_frames = match (self.time_base, self.decoder.codec_params().sample_rate) {
+            (Some(time_base), Some(sample_rate)) => {
+                let time = time_base.calc_time(pre_roll);
+                time.seconds * sample_rate as u64 + (time.frac * sample_rate as f64).round() as u64
+            }
+            _ => pre_roll,
+        };
+
+        // Decoding continues from later on if the position is past the end.
+        match self.time_base {
+            Some(time_base) if seeked_to.actual_ts > seeked_to.required_ts => {
+                let time = time_base.calc_time(seeked_to.actual_ts);
+                Ok(time.seconds * SAMPLE_RATE as u64
+                    + (time.frac * SAMPLE_RATE as f64).round() as u64)
+            }
+            _ => Ok(absgp),
+        }
+    }
+
+    fn next_packet(&mut self) -> DecoderResult<Option<AudioPacket>> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(DecoderError::SymphoniaDecoder(e.to_string())),
+            };
+
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let (channels, sample_rate) = match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let spec = *decoded.spec();
+                    let sample_buffer = match self.sample_buffer {
+                        Some(ref mut buffer) if buffer.capacity() >= decoded.capacity() => buffer,
+                        _ => {
+                            self.sample_buffer =
+                                Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                            // Panic safety: the buffer was put in just above
+                            self.sample_buffer.as_mut().unwrap()
+                        }
+                    };
+                    sample_buffer.copy_interleaved_ref(decoded);
+                    (spec.channels.count(), spec.rate)
+                }
+                // A damaged frame (e.g. in an MP3 that doesn't start on a frame boundary) only
+                // costs that frame.
+                Err(Error::DecodeError(e)) => {
+                    warn!("Symphonia Decoder skipping a packet: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(DecoderError::SymphoniaDecoder(e.to_string())),
+            };
+
+            let samples = match self.sample_buffer.take() {
+                Some(buffer) => {
+                    let samples = self.output_samples(buffer.samples(), channels, sample_rate);
+                    self.sample_buffer = Some(buffer);
+                    samples
+                }
+                None => continue,
+            };
+
+            if !samples.is_empty() {
+                return Ok(Some(AudioPacket::Samples(samples)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Silent MPEG-1 Layer III frames: 128 kbit/s, 44.1 kHz, no padding, no CRC, with all side
+    // information and main data zeroed.
+    fn silent_mp3(frames: usize, channel_mode: u8) -> Vec<u8> {
+        const FRAME_SIZE: usize = 144 * 128_000 / 44_100;
+        let mut data = Vec::with_capacity(frames * FRAME_SIZE);
+        for _ in 0..frames {
+            data.extend_from_slice(&[0xff, 0xfb, 0x90, channel_mode << 6]);
+            data.resize(data.len() + FRAME_SIZE - 4, 0);
+        }
+        data
+    }
+
+    // Silent AAC-LC frames at 44.1 kHz in ADTS: one channel element without any scale factor
+    // bands, so that nothing but the global gain is coded.
+    fn silent_aac(frames: usize, channels: u32) -> Vec<u8> {
+        // ADTS packs its fields most significant bit first.
+        fn write(frame: &mut Vec<u8>, bits: &mut usize, value: u32, width: usize) {
+            for i in (0..width).rev() {
+                if *bits == frame.len() * 8 {
+                    frame.push(0);
+                }
+                if value >> i & 1 == 1 {
+                    *frame.last_mut().unwrap() |= 0x80 >> (*bits % 8);
+                }
+                *bits += 1;
+            }
+        }
+
+        let mut element = Vec::new();
+        let mut bits = 0;
+        let ics_info = |element: &mut Vec<u8>, bits: &mut usize| {
+            // long window, no scale factor bands, no prediction
+            write(element, bits, 0, 1 + 2 + 1 + 6 + 1);
+        };
+        let channel_stream = |element: &mut Vec<u8>, bits: &mut usize| {
+            write(element, bits, 100, 8); // global gain
+            write(element, bits, 0, 3); // no pulse, TNS or gain control data
+        };
+        if channels == 1 {
+            write(&mut element, &mut bits, 0, 3 + 4); // single channel element
+            write(&mut element, &mut bits, 100, 8); // global gain
+            ics_info(&mut element, &mut bits);
+            write(&mut element, &mut bits, 0, 3);
+        } else {
+            write(&mut element, &mut bits, 1 << 4, 3 + 4); // channel pair element
+            write(&mut element, &mut bits, 1, 1); // common window
+            ics_info(&mut element, &mut bits);
+            write(&mut element, &mut bits, 0, 2); // no M/S
+            channel_stream(&mut element, &mut bits);
+            channel_stream(&mut element, &mut bits);
+        }
+        write(&mut element, &mut bits, 7, 3); // end
+
+        let frame_len = 7 + element.len() as u32;
+        let mut data = Vec::with_capacity(frames * frame_len as usize);
+        for _ in 0..frames {
+            let mut header = Vec::new();
+            let mut bits = 0;
+            write(&mut header, &mut bits, 0xfff, 12);
+            write(&mut header, &mut bits, 0b0001, 4); // MPEG-4, no CRC
+            write(&mut header, &mut bits, 1, 2); // LC
+            write(&mut header, &mut bits, 4, 4); // 44.1 kHz
+            write(&mut header, &mut bits, 0, 1);
+            write(&mut header, &mut bits, channels, 3);
+            write(&mut header, &mut bits, 0, 4);
+            write(&mut header, &mut bits, frame_len, 13);
+            write(&mut header, &mut bits, 0x7ff, 11); // variable bitrate
+            write(&mut header, &mut bits, 0, 2); // one raw data block
+            data.extend_from_slice(&header);
+            data.extend_from_slice(&element);
+        }
+        data
+    }
+
+    fn decode_all(decoder: &mut SymphoniaDecoder) -> usize {
+        let mut samples = 0;
+        while let Some(packet) = decoder.next_packet().unwrap() {
+            samples += packet.samples().unwrap().len();
+        }
+        samples
+    }
+
+    #[test]
+    fn decode_mp3() {
+        for &channel_mode in &[0, 3] {
+            let input = Cursor::new(silent_mp3(20, channel_mode));
+            let mut decoder = SymphoniaDecoder::new(input, FileFormat::MP3_96).unwrap();
+            // mono comes out as stereo all the same
+            assert_eq!(decode_all(&mut decoder), 20 * 1152 * NUM_CHANNELS as usize);
+        }
+    }
+
+    #[test]
+    fn seek_mp3() {
+        let input = Cursor::new(silent_mp3(20, 0));
+        let mut decoder = SymphoniaDecoder::new(input, FileFormat::MP3_96).unwrap();
+        assert_eq!(decoder.seek(5000).unwrap(), 5000);
+        assert_eq!(
+            decode_all(&mut decoder),
+            (20 * 1152 - 5000) * NUM_CHANNELS as usize
+        );
+    }
+    #[test]
+    fn decode_aac() {
+        for &channels in &[1, 2] {
+            let input = Cursor::new(silent_aac(20, channels));
+            let mut decoder = SymphoniaDecoder::new(input, FileFormat::AAC_160).unwrap();
+            assert_eq!(decode_all(&mut decoder), 20 * 1024 * NUM_CHANNELS as usize);
+        }
+    }
+
+    #[test]
+    fn seek_aac() {
+        let input = Cursor::new(silent_aac(20, 2));
+        let mut decoder = SymphoniaDecoder::new(input, FileFormat::AAC_160).unwrap();
+        assert_eq!(decoder.seek(5000).unwrap(), 5000);
+        assert_eq!(
+            decode_all(&mut decoder),
+            (20 * 1024 - 5000) * NUM_CHANNELS as usize
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use crate::mixer::AudioFilter;
+use crate::NUM_CHANNELS;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EqualizerBandType {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+impl FromStr for EqualizerBandType {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "peak" => Ok(Self::Peaking),
+            "lowshelf" => Ok(Self::LowShelf),
+            "highshelf" => Ok(Self::HighShelf),
+            "lowpass" => Ok(Self::LowPass),
+            "highpass" => Ok(Self::HighPass),
+            _ => Err(()),
+        }
+    }
+}
+
+// A single equalizer band. The gain (dB) has no effect on low- and high-pass bands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqualizerBand {
+    pub band_type: EqualizerBandType,
+    pub frequency: f64,
+    pub gain_db: f64,
+    pub q: f64,
+}
+
+impl EqualizerBand {
+    // Butterworth response for pass filters, a moderately wide bell for the others.
+    pub const DEFAULT_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+}
+
+// Parses `<type>:<frequency>[:<gain>[:<q>]]`, e.g. `peak:1000:-3.5:1.4` or `highpass:30`.
+impl FromStr for EqualizerBand {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.trim().split(':');
+
+        let band_type = EqualizerBandType::from_str(fields.next().ok_or(())?)?;
+        let frequency = fields.next().ok_or(())?.parse::<f64>().map_err(|_| ())?;
+        let gain_db = match fields.next() {
+            Some(gain) => gain.parse::<f64>().map_err(|_| ())?,
+            None => 0.0,
+        };
+        let q = match fields.next() {
+            Some(q) => q.parse::<f64>().map_err(|_| ())?,
+            None => Self::DEFAULT_Q,
+        };
+
+        let valid = frequency.is_finite() && frequency > 0.0 && gain_db.is_finite();
+        let valid = valid && q.is_finite() && q > 0.0;
+
+        match fields.next() {
+            None if valid => Ok(Self {
+                band_type,
+                frequency,
+                gain_db,
+                q,
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+// Coefficients from the "Audio EQ Cookbook" by Robert Bristow-Johnson,
+// normalized so that a0 equals 1.
+#[derive(Clone, Copy, Debug)]
+struct BiquadCoefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl BiquadCoefficients {
+    fn new(band: &EqualizerBand, sample_rate: u32) -> Self {
+        let a = f64::powf(10.0, band.gain_db / 40.0);
+        let w0 = 2.0 * PI * band.frequency / sample_rate as f64;
+        let (sin_w0, cos_w0) = w0.sin_cos();
+        let alpha = sin_w0 / (2.0 * band.q);
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.band_type {
+            EqualizerBandType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos_w0,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos_w0,
+                1.0 - alpha / a,
+            ),
+            EqualizerBandType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                (a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha,
+            ),
+            EqualizerBandType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                (a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha,
+            ),
+            EqualizerBandType::LowPass => (
+                (1.0 - cos_w0) / 2.0,
+                1.0 - cos_w0,
+                (1.0 - cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+            EqualizerBandType::HighPass => (
+                (1.0 + cos_w0) / 2.0,
+                -(1.0 + cos_w0),
+                (1.0 + cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    // The gain at the angular frequency `w` (radians per sample).
+    fn magnitude(&self, w: f64) -> f64 {
+        let (sin_w, cos_w) = w.sin_cos();
+        let (sin_2w, cos_2w) = (2.0 * w).sin_cos();
+
+        let num_re = self.b0 + self.b1 * cos_w + self.b2 * cos_2w;
+        let num_im = self.b1 * sin_w + self.b2 * sin_2w;
+        let den_re = 1.0 + self.a1 * cos_w + self.a2 * cos_2w;
+        let den_im = self.a1 * sin_w + self.a2 * sin_2w;
+
+        f64::sqrt((num_re * num_re + num_im * num_im) / (den_re * den_re + den_im * den_im))
+    }
+}
+
+// Transposed direct form II, with separate state for every channel.
+struct Biquad {
+    frequency: f64,
+    coefficients: BiquadCoefficients,
+    state: [[f64; 2]; NUM_CHANNELS as usize],
+}
+
+impl Biquad {
+    fn process(&mut self, data: &mut [f64]) {
+        let c = self.coefficients;
+        for frame in data.chunks_mut(NUM_CHANNELS as usize) {
+            for (sample, z) in frame.iter_mut().zip(self.state.iter_mut()) {
+                let x = *sample;
+                let y = c.b0 * x + z[0];
+                z[0] = c.b1 * x - c.a1 * y + z[1];
+                z[1] = c.b2 * x - c.a2 * y;
+                *sample = y;
+            }
+        }
+    }
+}
+
+pub struct Equalizer {
+    sample_rate: u32,
+    bands: Vec<Biquad>,
+    // Attenuation in front of the bands by as much as they boost at most, so that the equalizer
+    // can't make audio clip that the normaliser has already limited.
+    pregain: f64,
+}
+
+impl Equalizer {
+    pub fn new(bands: &[EqualizerBand], sample_rate: u32) -> Self {
+        let mut equalizer = Self {
+            sample_rate,
+            bands: Vec::new(),
+            pregain: 1.0,
+        };
+        equalizer.set_bands(bands);
+        equalizer
+    }
+
+    pub fn set_bands(&mut self, bands: &[EqualizerBand]) {
+        let nyquist = self.sample_rate as f64 / 2.0;
+
+        let old_bands = std::mem::take(&mut self.bands);
+        let mut old_states = old_bands.into_iter().map(|band| band.state);
+
+        self.bands = bands
+            .iter()
+            .filter(|band| {
+                if band.frequency >= nyquist {
+                    warn!(
+                        "Ignoring equalizer band {:?} at or above the Nyquist frequency of {} Hz",
+                        band, nyquist
+                    );
+                    false
+                } else {
+                    true
+                }
+            })
+            .map(|band| Biquad {
+                frequency: band.frequency,
+                coefficients: BiquadCoefficients::new(band, self.sample_rate),
+                // Keep the filter state when the bands are changed at runtime. Resetting it
+                // would produce a discontinuity in the output.
+                state: old_states.next().unwrap_or_default(),
+            })
+            .collect();
+
+        self.pregain = 1.0 / self.max_gain().max(1.0);
+
+        debug!("Equalizer bands: {:?}", bands);
+        if self.pregain < 1.0 {
+            debug!("Equalizer pregain: {:.1} dB", 20.0 * self.pregain.log10());
+        }
+    }
+
+    // The highest gain of all bands together, from their response at the frequencies they are
+    // centered at and at 1/24 octave steps across the audible range.
+    fn max_gain(&self) -> f64 {
+        let nyquist = self.sample_rate as f64 / 2.0;
+        let steps = std::iter::successors(Some(20.0), |frequency| {
+            Some(frequency * f64::powf(2.0, 1.0 / 24.0))
+        })
+        .take_while(|&frequency| frequency < nyquist);
+        let centers = self.bands.iter().map(|band| band.frequency);
+
+        steps
+            .chain(centers)
+            .map(|frequency| {
+                let w = 2.0 * PI * frequency / self.sample_rate as f64;
+                self.bands
+                    .iter()
+                    .map(|band| band.coefficients.magnitude(w))
+                    .product::<f64>()
+            })
+            .fold(0.0, f64::max)
+    }
+}
+
+impl AudioFilter for Equalizer {
+    fn modify_stream(&mut self, data: &mut [f64]) {
+        if self.pregain < 1.0 {
+            for sample in data.iter_mut() {
+                *sample *= self.pregain;
+            }
+        }
+        for band in self.bands.iter_mut() {
+            band.process(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_band() {
+        let band = EqualizerBand::from_str("peak:1000:-3.5:1.4").unwrap();
+        assert_eq!(band.band_type, EqualizerBandType::Peaking);
+        assert_eq!(band.frequency, 1000.0);
+        assert_eq!(band.gain_db, -3.5);
+        assert_eq!(band.q, 1.4);
+
+        let band = EqualizerBand::from_str("highpass:30").unwrap();
+        assert_eq!(band.band_type, EqualizerBandType::HighPass);
+        assert_eq!(band.gain_db, 0.0);
+        assert_eq!(band.q, EqualizerBand::DEFAULT_Q);
+
+        assert!(EqualizerBand::from_str("notch:1000").is_err());
+        assert!(EqualizerBand::from_str("peak").is_err());
+        assert!(EqualizerBand::from_str("peak:-20").is_err());
+        assert!(EqualizerBand::from_str("peak:1000:3:0").is_err());
+        assert!(EqualizerBand::from_str("peak:1000:3:1:1").is_err());
+    }
+
+    #[test]
+    fn flat_peaking_band_is_transparent() {
+        let band = EqualizerBand::from_str("peak:1000:0:1").unwrap();
+        let mut equalizer = Equalizer::new(&[band], 44100);
+
+        let input: Vec<f64> = (0..512).map(|i| f64::sin(i as f64 * 0.1)).collect();
+        let mut output = input.clone();
+        equalizer.modify_stream(&mut output);
+
+        for (x, y) in input.iter().zip(output.iter()) {
+            assert!((x - y).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn low_shelf_boosts_dc() {
+        let band = EqualizerBand::from_str("lowshelf:200:6:0.7071").unwrap();
+        let mut equalizer = Equalizer::new(&[band], 44100);
+
+        // The boost is taken back by the pregain, so that it comes out as a cut of the rest.
+        let mut dc = vec![0.25; 2 * 44100];
+        equalizer.modify_stream(&mut dc);
+        assert!((dc[dc.len() - 1] - 0.25).abs() < 1e-3);
+
+        let mut equalizer = Equalizer::new(&[band], 44100);
+        let mut nyquist: Vec<f64> = (0..2 * 44100)
+            .map(|i| if (i / 2) % 2 == 0 { 0.25 } else { -0.25 })
+            .collect();
+        equalizer.modify_stream(&mut nyquist);
+
+        let expected = 0.25 / f64::powf(10.0, 6.0 / 20.0);
+        assert!((nyquist[nyquist.len() - 1].abs() - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn boosts_do_not_clip() {
+        let bands = [
+            EqualizerBand::from_str("peak:1000:6:2").unwrap(),
+            EqualizerBand::from_str("peak:1200:6:2").unwrap(),
+            EqualizerBand::from_str("lowpass:8000:0:4").unwrap(),
+        ];
+
+        for &frequency in &[100.0, 1000.0, 1100.0, 1200.0, 8000.0] {
+            let mut equalizer = Equalizer::new(&bands, 44100);
+            let w = 2.0 * PI * frequency / 44100.0;
+            let mut data: Vec<f64> = (0..2 * 44100)
+                .map(|i| f64::sin((i / 2) as f64 * w))
+                .collect();
+            equalizer.modify_stream(&mut data);
+
+            let peak = data[44100..]
+                .iter()
+                .fold(0.0, |peak: f64, x| peak.max(x.abs()));
+            assert!(peak <= 1.0 + 1e-3, "{} Hz peaks at {}", frequency, peak);
+        }
+    }
+}
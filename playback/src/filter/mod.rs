@@ -0,0 +1,37 @@
+use crate::mixer::AudioFilter;
+
+pub mod equalizer;
+use self::equalizer::{Equalizer, EqualizerBand};
+
+// The user-configurable DSP stages, applied in order before the volume of the mixer.
+// The equalizer always comes first, further stages can be appended at runtime.
+pub struct FilterChain {
+    equalizer: Equalizer,
+    stages: Vec<Box<dyn AudioFilter + Send>>,
+}
+
+impl FilterChain {
+    pub fn new(bands: &[EqualizerBand], sample_rate: u32) -> Self {
+        Self {
+            equalizer: Equalizer::new(bands, sample_rate),
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn set_equalizer(&mut self, bands: &[EqualizerBand]) {
+        self.equalizer.set_bands(bands);
+    }
+
+    pub fn push(&mut self, stage: Box<dyn AudioFilter + Send>) {
+        self.stages.push(stage);
+    }
+}
+
+impl AudioFilter for FilterChain {
+    fn modify_stream(&mut self, data: &mut [f64]) {
+        self.equalizer.modify_stream(data);
+        for stage in self.stages.iter_mut() {
+            stage.modify_stream(data);
+        }
+    }
+}
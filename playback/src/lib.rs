@@ -10,8 +10,13 @@ pub mod config;
 pub mod convert;
 pub mod decoder;
 pub mod dither;
+pub mod filter;
+pub mod local_files;
 pub mod mixer;
+mod output;
 pub mod player;
+pub mod resampler;
+pub mod time_stretch;
 
 pub const SAMPLE_RATE: u32 = 44100;
 pub const NUM_CHANNELS: u8 = 2;
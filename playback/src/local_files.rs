@@ -0,0 +1,547 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+#[cfg(not(feature = "symphonia-decoder"))]
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[cfg(not(feature = "symphonia-decoder"))]
+use byteorder::{ByteOrder, LittleEndian};
+#[cfg(not(feature = "symphonia-decoder"))]
+use lewton::inside_ogg::OggStreamReader;
+use percent_encoding::percent_decode_str;
+#[cfg(feature = "symphonia-decoder")]
+use symphonia::core::formats::FormatOptions;
+#[cfg(feature = "symphonia-decoder")]
+use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+#[cfg(feature = "symphonia-decoder")]
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
+#[cfg(feature = "symphonia-decoder")]
+use symphonia::core::probe::Hint;
+
+// what symphonia can decode, and what people have their music in
+#[cfg(feature = "symphonia-decoder")]
+const EXTENSIONS: &[&str] = &["flac", "m4a", "mp3", "oga", "ogg"];
+// lewton only decodes Ogg Vorbis
+#[cfg(not(feature = "symphonia-decoder"))]
+const EXTENSIONS: &[&str] = &["oga", "ogg"];
+
+// Durations in URIs are in whole seconds, and tags can be off by a bit as well.
+const DURATION_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// The parts of a `spotify:local:{artist}:{album}:{title}:{duration}` URI, which is what
+/// playlists refer to files on the computer of a user with. Each part is URL encoded, with spaces
+/// as `+`, and all but the title may be empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalUri {
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+    pub duration: Option<Duration>,
+}
+
+fn decode_part(part: &str) -> String {
+    percent_decode_str(&part.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+impl LocalUri {
+    pub fn parse(uri: &str) -> Option<Self> {
+        let parts: Vec<&str> = uri.strip_prefix("spotify:local:")?.split(':').collect();
+        match parts[..] {
+            [artist, album, title, duration] if !title.is_empty() => Some(Self {
+                artist: decode_part(artist),
+                album: decode_part(album),
+                title: decode_part(title),
+                duration: duration.parse().ok().map(Duration::from_secs),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// ReplayGain values from the tags of a file, in the form of Spotify's normalisation data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain_db: f32,
+    pub track_peak: f32,
+    pub album_gain_db: f32,
+    pub album_peak: f32,
+}
+
+/// A music file with what its tags say about it.
+#[derive(Debug, Clone)]
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub artists: Vec<String>,
+    pub album: String,
+    // the name of the file if there is no title tag
+    pub title: String,
+    pub duration: Option<Duration>,
+    pub replay_gain: Option<ReplayGain>,
+}
+
+// Tags are compared regardless of case and spacing.
+fn normalise(tag: &str) -> String {
+    tag.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// e.g. "-6.48 dB"
+fn parse_gain(value: &str) -> Option<f32> {
+    value
+        .trim()
+        .trim_end_matches(|c: char| c.is_alphabetic())
+        .trim()
+        .parse()
+        .ok()
+}
+
+// The tags that local files are matched by, and that their ReplayGain is read from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TagKey {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    TrackGain,
+    TrackPeak,
+    AlbumGain,
+    AlbumPeak,
+}
+
+type Tags = Vec<(TagKey, String)>;
+
+// The tags and the duration of a music file, or `None` if it can't be read.
+#[cfg(feature = "symphonia-decoder")]
+fn read_tags(path: &Path, file: File) -> Option<(Tags, Option<Duration>)> {
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(file), MediaSourceStreamOptions::default());
+    let mut probed = match symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(probed) => probed,
+        Err(e) => {
+            debug!("Skipping {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    // Tags can come before the actual format (e.g. ID3v2 in front of MP3) or within it.
+    let mut tags: Vec<Tag> = Vec::new();
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            tags.extend_from_slice(revision.tags());
+        }
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.extend_from_slice(revision.tags());
+    }
+
+    let tags = tags
+        .iter()
+        .filter_map(|tag| {
+            let key = match tag.std_key? {
+                StandardTagKey::Artist => TagKey::Artist,
+                StandardTagKey::AlbumArtist => TagKey::AlbumArtist,
+                StandardTagKey::Album => TagKey::Album,
+                StandardTagKey::TrackTitle => TagKey::Title,
+                StandardTagKey::ReplayGainTrackGain => TagKey::TrackGain,
+                StandardTagKey::ReplayGainTrackPeak => TagKey::TrackPeak,
+                StandardTagKey::ReplayGainAlbumGain => TagKey::AlbumGain,
+                StandardTagKey::ReplayGainAlbumPeak => TagKey::AlbumPeak,
+                _ => return None,
+            };
+            Some((key, tag.value.to_string()))
+        })
+        .collect();
+
+    let duration = probed.format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        let frames = params.n_frames?;
+        match (params.time_base, params.sample_rate) {
+            (Some(time_base), _) => {
+                let time = time_base.calc_time(frames);
+                Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+            }
+            (None, Some(sample_rate)) => {
+                Some(Duration::from_secs_f64(frames as f64 / sample_rate as f64))
+            }
+            (None, None) => None,
+        }
+    });
+
+    Some((tags, duration))
+}
+
+// The tags and the duration of an Ogg Vorbis file, or `None` if it can't be read.
+#[cfg(not(feature = "symphonia-decoder"))]
+fn read_tags(path: &Path, mut file: File) -> Option<(Tags, Option<Duration>)> {
+    let (comments, sample_rate) = match OggStreamReader::new(&mut file) {
+        Ok(reader) => (
+            reader.comment_hdr.comment_list,
+            reader.ident_hdr.audio_sample_rate,
+        ),
+        Err(e) => {
+            debug!("Skipping {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let tags = comments
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let key = match key.to_uppercase().as_ref() {
+                "ARTIST" => TagKey::Artist,
+                "ALBUMARTIST" | "ALBUM ARTIST" => TagKey::AlbumArtist,
+                "ALBUM" => TagKey::Album,
+                "TITLE" => TagKey::Title,
+                "REPLAYGAIN_TRACK_GAIN" => TagKey::TrackGain,
+                "REPLAYGAIN_TRACK_PEAK" => TagKey::TrackPeak,
+                "REPLAYGAIN_ALBUM_GAIN" => TagKey::AlbumGain,
+                "REPLAYGAIN_ALBUM_PEAK" => TagKey::AlbumPeak,
+                _ => return None,
+            };
+            Some((key, value))
+        })
+        .collect();
+
+    let duration = match last_granule_position(&mut file) {
+        Some(samples) if sample_rate > 0 => {
+            Some(Duration::from_secs_f64(samples as f64 / sample_rate as f64))
+        }
+        _ => None,
+    };
+
+    Some((tags, duration))
+}
+
+// The granule position of the last Ogg page, which is the length of a Vorbis stream in samples per
+// channel. The last page starts within the largest size that a page can have from the end.
+#[cfg(not(feature = "symphonia-decoder"))]
+fn last_granule_position(file: &mut File) -> Option<u64> {
+    const OGG_PAGE_MAX_SIZE: u64 = 27 + 255 + 255 * 255;
+
+    let len = file.seek(SeekFrom::End(0)).ok()?;
+    file.seek(SeekFrom::Start(len.saturating_sub(OGG_PAGE_MAX_SIZE)))
+        .ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+
+    let page = tail.windows(4).rposition(|window| window == b"OggS")?;
+    tail.get(page + 6..page + 14).map(LittleEndian::read_u64)
+}
+
+impl LocalFile {
+    fn read(path: &Path) -> Option<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Unable to open {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let (tags, duration) = read_tags(path, file)?;
+
+        let values = |key: TagKey| {
+            tags.iter()
+                .filter(move |tag| tag.0 == key)
+                .map(|tag| tag.1.clone())
+                .filter(|value| !value.trim().is_empty())
+        };
+
+        let mut artists: Vec<String> = values(TagKey::Artist).collect();
+        if artists.is_empty() {
+            artists.extend(values(TagKey::AlbumArtist));
+        }
+        let album = values(TagKey::Album).next().unwrap_or_default();
+        let title = values(TagKey::Title)
+            .next()
+            .or_else(|| Some(path.file_stem()?.to_string_lossy().into_owned()))?;
+
+        let gain = |key| values(key).next().as_deref().and_then(parse_gain);
+        let replay_gain = gain(TagKey::TrackGain).map(|track_gain_db| {
+            let track_peak = gain(TagKey::TrackPeak).unwrap_or(1.0);
+            ReplayGain {
+                track_gain_db,
+                track_peak,
+                album_gain_db: gain(TagKey::AlbumGain).unwrap_or(track_gain_db),
+                album_peak: gain(TagKey::AlbumPeak).unwrap_or(track_peak),
+            }
+        });
+
+        Some(Self {
+            path: path.to_owned(),
+            artists,
+            album,
+            title,
+            duration,
+            replay_gain,
+        })
+    }
+
+    // How well the file fits the URI of a local file with the same title, if at all.
+    fn score(&self, uri: &LocalUri) -> Option<u32> {
+        let artist = normalise(&uri.artist);
+        let album = normalise(&uri.album);
+
+        let artist_matches = !artist.is_empty()
+            && (self.artists.iter().any(|a| normalise(a) == artist)
+                || normalise(&self.artists.join(", ")) == artist);
+        let album_matches = !album.is_empty() && normalise(&self.album) == album;
+        let duration_matches = match (self.duration, uri.duration) {
+            (Some(a), Some(b)) => (if a > b { a - b } else { b - a }) <= DURATION_TOLERANCE,
+            _ => false,
+        };
+
+        let score = 4 * artist_matches as u32 + 2 * album_matches as u32 + duration_matches as u32;
+        // Without an artist or album, there is nothing but the title to go by.
+        if score > 0 || (artist.is_empty() && album.is_empty()) {
+            Some(score)
+        } else {
+            None
+        }
+    }
+}
+
+/// The music files in a directory, indexed by their tags so that the files of local URIs can be
+/// found, much like the desktop client does with the folders that it is told about.
+#[derive(Debug, Default)]
+pub struct LocalFiles {
+    files: Vec<LocalFile>,
+    // the indices of the files by normalised title
+    titles: HashMap<String, Vec<usize>>,
+}
+
+impl LocalFiles {
+    /// Reads the tags of all music files in `dir` and its subdirectories. Files that are added
+    /// later aren't found until the directory is indexed again.
+    pub fn index(dir: &Path) -> Self {
+        let start = Instant::now();
+
+        let mut paths = Vec::new();
+        let mut visited = HashSet::new();
+        find_files(dir, &mut paths, &mut visited);
+
+        let local_files = Self::new(paths.iter().filter_map(|path| LocalFile::read(path)));
+        info!(
+            "Indexed {} local files in {} in {:.1} s",
+            local_files.len(),
+            dir.display(),
+            start.elapsed().as_secs_f32()
+        );
+        local_files
+    }
+
+    fn new<I: IntoIterator<Item = LocalFile>>(files: I) -> Self {
+        let mut local_files = Self::default();
+        for file in files {
+            local_files
+                .titles
+                .entry(normalise(&file.title))
+                .or_default()
+                .push(local_files.files.len());
+            local_files.files.push(file);
+        }
+        local_files
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Finds the file that a `spotify:local` URI refers to. Its title has to match, and then
+    /// the file that matches the artist, album and duration best is taken.
+    pub fn resolve(&self, uri: &str) -> Option<&LocalFile> {
+        let uri = LocalUri::parse(uri)?;
+        let candidates = self.titles.get(&normalise(&uri.title))?;
+
+        let mut best: Option<(u32, &LocalFile)> = None;
+        for file in candidates.iter().map(|&index| &self.files[index]) {
+            if let Some(score) = file.score(&uri) {
+                match best {
+                    Some((best_score, _)) if best_score >= score => (),
+                    _ => best = Some((score, file)),
+                }
+            }
+        }
+        best.map(|(_, file)| file)
+    }
+}
+
+fn is_music_file(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+fn find_files(dir: &Path, paths: &mut Vec<PathBuf>, visited: &mut HashSet<PathBuf>) {
+    // Symbolic links could lead back to where they come from.
+    match dir.canonicalize() {
+        Ok(canonical) => {
+            if !visited.insert(canonical) {
+                return;
+            }
+        }
+        Err(e) => {
+            warn!("Unable to read {}: {}", dir.display(), e);
+            return;
+        }
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Unable to read {}: {}", dir.display(), e);
+            return;
+        }
+    };
+
+    let mut entries: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            find_files(&path, paths, visited);
+        } else if is_music_file(&path) {
+            paths.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::tests::{vorbis_stream_with_comments, PACKET_FRAMES};
+
+    fn local_file(artist: &str, album: &str, title: &str, duration: u64) -> LocalFile {
+        LocalFile {
+            path: PathBuf::from(format!("{} - {}.flac", artist, title)),
+            artists: vec![artist.to_string()],
+            album: album.to_string(),
+            title: title.to_string(),
+            duration: Some(Duration::from_secs(duration)),
+            replay_gain: None,
+        }
+    }
+
+    #[test]
+    fn parse_uri() {
+        assert_eq!(
+            LocalUri::parse("spotify:local:Simon+%26+Garfunkel::The+Boxer%3A+Live:305"),
+            Some(LocalUri {
+                artist: "Simon & Garfunkel".to_string(),
+                album: "".to_string(),
+                title: "The Boxer: Live".to_string(),
+                duration: Some(Duration::from_secs(305)),
+            })
+        );
+        assert_eq!(
+            LocalUri::parse("spotify:local:::Title:"),
+            Some(LocalUri {
+                artist: "".to_string(),
+                album: "".to_string(),
+                title: "Title".to_string(),
+                duration: None,
+            })
+        );
+        assert_eq!(LocalUri::parse("spotify:local:Artist:Album::100"), None);
+        assert_eq!(LocalUri::parse("spotify:local:Artist:Title:100"), None);
+        assert_eq!(
+            LocalUri::parse("spotify:track:5sWHDYs0csV6RS48xBl0tH"),
+            None
+        );
+    }
+
+    #[test]
+    fn resolve() {
+        let local_files = LocalFiles::new(vec![
+            local_file("Daft Punk", "Discovery", "One More Time", 320),
+            local_file("Daft Punk", "Alive 2007", "One More Time", 400),
+            local_file("Someone Else", "Covers", "One More Time", 200),
+            local_file("Daft Punk", "Discovery", "Aerodynamic", 212),
+        ]);
+
+        let album = |uri| local_files.resolve(uri).map(|file| file.album.as_str());
+
+        assert_eq!(
+            album("spotify:local:Daft+Punk:Alive+2007:One+More+Time:400"),
+            Some("Alive 2007")
+        );
+        assert_eq!(
+            album("spotify:local:daft+punk:discovery:one+more++time:"),
+            Some("Discovery")
+        );
+        // the duration tips the scale when the album is unknown
+        assert_eq!(
+            album("spotify:local:Daft+Punk::One+More+Time:399"),
+            Some("Alive 2007")
+        );
+        assert_eq!(
+            album("spotify:local:Someone+Else::One+More+Time:"),
+            Some("Covers")
+        );
+        assert_eq!(album("spotify:local:::Aerodynamic:"), Some("Discovery"));
+        assert!(local_files
+            .resolve("spotify:local:Nobody:Nothing:One+More+Time:1")
+            .is_none());
+        assert!(local_files
+            .resolve("spotify:local:Daft+Punk:Discovery:Digital+Love:301")
+            .is_none());
+    }
+
+    #[test]
+    fn read_ogg() {
+        let path = std::env::temp_dir().join(format!("librespot-local-{}.ogg", std::process::id()));
+        // a second and a bit, as the first packet decodes to nothing
+        let packets = 2 + 44100 / PACKET_FRAMES as usize;
+        let comments = [
+            "ARTIST=Daft Punk",
+            "album=Discovery",
+            "TITLE=One More Time",
+            "REPLAYGAIN_TRACK_GAIN=-6.48 dB",
+        ];
+        fs::write(&path, vorbis_stream_with_comments(packets, &comments)).unwrap();
+        let local_file = LocalFile::read(&path);
+        fs::remove_file(&path).unwrap();
+
+        let local_file = local_file.unwrap();
+        assert_eq!(local_file.artists, ["Daft Punk"]);
+        assert_eq!(local_file.album, "Discovery");
+        assert_eq!(local_file.title, "One More Time");
+        let duration = local_file.duration.unwrap();
+        assert!(
+            duration >= Duration::from_secs(1) && duration < Duration::from_millis(1010),
+            "{:?}",
+            duration
+        );
+        let replay_gain = local_file.replay_gain.unwrap();
+        assert_eq!(replay_gain.track_gain_db, -6.48);
+        assert_eq!(replay_gain.album_gain_db, -6.48);
+    }
+
+    #[test]
+    fn gain() {
+        assert_eq!(parse_gain("-6.48 dB"), Some(-6.48));
+        assert_eq!(parse_gain("+1.5dB"), Some(1.5));
+        assert_eq!(parse_gain("0.988553"), Some(0.988553));
+        assert_eq!(parse_gain("loud"), None);
+    }
+}
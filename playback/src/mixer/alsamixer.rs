@@ -113,9 +113,10 @@ impl Mixer for AlsaMixer {
         }
 
         // For hardware controls with a small range (24 dB or less),
-        // force using the dB API with a linear mapping.
+        // force using the dB API with a linear mapping. A table is
+        // left as it is, it was made for the hardware.
         let mut use_linear_in_db = false;
-        if !is_softvol && db_range <= 24.0 {
+        if !is_softvol && db_range <= 24.0 && !matches!(config.volume_ctrl, VolumeCtrl::Table(_)) {
             use_linear_in_db = true;
             config.volume_ctrl = VolumeCtrl::Linear;
         }
@@ -0,0 +1,384 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{MappedCtrl, VolumeCtrl};
+use super::{Mixer, MixerConfig};
+
+// How long a socket is given to take a request and answer it.
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How long volume() waits for a query to be answered before it goes with the volume it knows.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(250);
+
+// Where the requests go. Both take the same requests, `set VOLUME` and `get`, as arguments that
+// are appended to the command or as lines that are written to the socket. The volume is the
+// mapped one, from 0.0 to 1.0, and `get` is answered with one on stdout or with a line.
+enum ExternalTarget {
+    Command(Vec<String>),
+    Socket(ExternalSocket),
+}
+
+impl ExternalTarget {
+    fn parse(target: &str) -> Self {
+        if let Some(address) = target.strip_prefix("tcp:") {
+            return Self::Socket(ExternalSocket::Tcp(address.to_string()));
+        }
+
+        if let Some(path) = target.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Self::Socket(ExternalSocket::Unix(path.to_string()));
+            #[cfg(not(unix))]
+            panic!("Unix sockets are not supported on this platform: {}", path);
+        }
+
+        let command: Vec<_> = target.split_whitespace().map(String::from).collect();
+        if command.is_empty() {
+            panic!("No command or socket for the external mixer");
+        }
+        Self::Command(command)
+    }
+}
+
+enum ExternalSocket {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+impl ExternalSocket {
+    fn connect(&self) -> io::Result<Box<dyn ExternalConnection>> {
+        match self {
+            Self::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_read_timeout(Some(SOCKET_TIMEOUT))?;
+                stream.set_write_timeout(Some(SOCKET_TIMEOUT))?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Self::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(SOCKET_TIMEOUT))?;
+                stream.set_write_timeout(Some(SOCKET_TIMEOUT))?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+trait ExternalConnection: Read + Write + Send {}
+
+impl<T: Read + Write + Send> ExternalConnection for T {}
+
+// Makes the requests, on the thread of the mixer only.
+struct ExternalClient {
+    target: ExternalTarget,
+    // kept open between requests
+    connection: Option<BufReader<Box<dyn ExternalConnection>>>,
+}
+
+impl ExternalClient {
+    // Returns the answer to the request if it is a query.
+    fn request(&mut self, args: &[&str], query: bool) -> io::Result<String> {
+        match self.target {
+            ExternalTarget::Command(ref command) => Self::request_command(command, args),
+            ExternalTarget::Socket(ref socket) => {
+                Self::request_socket(socket, &mut self.connection, args, query)
+            }
+        }
+    }
+
+    fn request_command(command: &[String], args: &[&str]) -> io::Result<String> {
+        let output = Command::new(&command[0])
+            .args(&command[1..])
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{:?} returned {}", command, output.status),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn request_socket(
+        socket: &ExternalSocket,
+        connection: &mut Option<BufReader<Box<dyn ExternalConnection>>>,
+        args: &[&str],
+        query: bool,
+    ) -> io::Result<String> {
+        // A connection that was kept open may have been closed by the other end in the
+        // meantime, which only shows when it is used. Such a request is tried once more.
+        let mut retry = connection.is_some();
+        loop {
+            let mut stream = match connection.take() {
+                Some(stream) => stream,
+                None => BufReader::new(socket.connect()?),
+            };
+
+            let mut answer = String::new();
+            let result = writeln!(stream.get_mut(), "{}", args.join(" "))
+                .and_then(|_| stream.get_mut().flush())
+                .and_then(|_| {
+                    if query && stream.read_line(&mut answer)? == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    Ok(())
+                });
+
+            match result {
+                Ok(()) => {
+                    *connection = Some(stream);
+                    return Ok(answer);
+                }
+                Err(e) if !retry => return Err(e),
+                Err(_) => retry = false,
+            }
+        }
+    }
+
+    fn query_volume(&mut self) -> io::Result<f64> {
+        let answer = self.request(&["get"], true)?;
+        answer.trim().parse::<f64>().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid volume: {:?}", answer.trim()),
+            )
+        })
+    }
+}
+
+struct ExternalPending {
+    // the volume that is still to be set, along with what it maps to
+    volume: Option<(u16, f64)>,
+    // the volume that the hardware is known to be at
+    applied: Option<u16>,
+    // whether the volume is to be queried
+    query: bool,
+    // counts the queries that have been answered (or failed), for callers to wait for theirs
+    queries_done: u64,
+    shutdown: bool,
+}
+
+struct ExternalShared {
+    pending: Mutex<ExternalPending>,
+    changed: Condvar,
+    // the latest volume that was set or queried
+    volume: AtomicU16,
+}
+
+// Controls a volume that librespot can't get to otherwise, e.g. of an amplifier that is
+// controlled over a serial port, by infrared or HTTP, through a command or a local socket that
+// the user provides. The volume is mapped through the volume control like it is for softvol.
+//
+// The requests are made on a thread of its own, so that a slow command or socket doesn't hold up
+// the caller. Only the latest volume is set when they come in faster than that, and queries that
+// take too long leave the caller with the volume that was known before.
+pub struct ExternalMixer {
+    shared: Arc<ExternalShared>,
+    volume_ctrl: VolumeCtrl,
+    query: bool,
+}
+
+impl Mixer for ExternalMixer {
+    fn open(config: MixerConfig) -> Self {
+        info!(
+            "Mixing with external mixer {} and volume control: {:?}",
+            config.external, config.volume_ctrl
+        );
+
+        let shared = Arc::new(ExternalShared {
+            pending: Mutex::new(ExternalPending {
+                volume: None,
+                applied: None,
+                query: false,
+                queries_done: 0,
+                shutdown: false,
+            }),
+            changed: Condvar::new(),
+            volume: AtomicU16::new(VolumeCtrl::MAX_VOLUME / 2),
+        });
+
+        let client = ExternalClient {
+            target: ExternalTarget::parse(&config.external),
+            connection: None,
+        };
+        let thread_shared = shared.clone();
+        let volume_ctrl = config.volume_ctrl.clone();
+        thread::spawn(move || run_external(&thread_shared, client, &volume_ctrl));
+
+        Self {
+            shared,
+            volume_ctrl: config.volume_ctrl,
+            query: config.external_query,
+        }
+    }
+
+    fn volume(&self) -> u16 {
+        if self.query {
+            let mut pending = self.shared.pending.lock().unwrap();
+            let queries_done = pending.queries_done;
+            pending.query = true;
+            self.shared.changed.notify_all();
+
+            let deadline = Instant::now() + QUERY_TIMEOUT;
+            while pending.queries_done == queries_done {
+                let now = Instant::now();
+                if now >= deadline {
+                    debug!("The external mixer didn't answer in time, going with the last volume");
+                    break;
+                }
+                pending = self
+                    .shared
+                    .changed
+                    .wait_timeout(pending, deadline - now)
+                    .unwrap()
+                    .0;
+            }
+        }
+
+        self.shared.volume.load(Ordering::Relaxed)
+    }
+
+    fn set_volume(&self, volume: u16) {
+        self.shared.volume.store(volume, Ordering::Relaxed);
+
+        let mut pending = self.shared.pending.lock().unwrap();
+        // e.g. when a volume that was just queried is passed back
+        if pending.volume.is_none() && pending.applied == Some(volume) {
+            return;
+        }
+        pending.volume = Some((volume, self.volume_ctrl.to_mapped(volume)));
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Drop for ExternalMixer {
+    fn drop(&mut self) {
+        // The volume that is still pending is set before the thread ends.
+        self.shared.pending.lock().unwrap().shutdown = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl ExternalMixer {
+    pub const NAME: &'static str = "external";
+}
+
+fn run_external(shared: &ExternalShared, mut client: ExternalClient, volume_ctrl: &VolumeCtrl) {
+    loop {
+        let mut pending = shared.pending.lock().unwrap();
+        while pending.volume.is_none() && !pending.query && !pending.shutdown {
+            pending = shared.changed.wait(pending).unwrap();
+        }
+
+        // A volume that is to be set goes first, so that a query tells what it came to.
+        if let Some((volume, mapped_volume)) = pending.volume.take() {
+            pending.applied = None;
+            drop(pending);
+
+            match client.request(&["set", &format!("{:.4}", mapped_volume)], false) {
+                Ok(_) => {
+                    let mut pending = shared.pending.lock().unwrap();
+                    if pending.volume.is_none() {
+                        pending.applied = Some(volume);
+                    }
+                }
+                Err(e) => warn!("Unable to set the volume of the external mixer: {}", e),
+            }
+            continue;
+        }
+
+        if pending.query {
+            pending.query = false;
+            drop(pending);
+
+            let result = client.query_volume();
+            let mut pending = shared.pending.lock().unwrap();
+            match result {
+                // A volume that was set in the meantime is newer.
+                Ok(mapped_volume) if pending.volume.is_none() => {
+                    let volume = volume_ctrl.from_mapped(mapped_volume.max(0.0).min(1.0));
+                    shared.volume.store(volume, Ordering::Relaxed);
+                    pending.applied = Some(volume);
+                }
+                Ok(_) => (),
+                Err(e) => warn!("Unable to query the volume of the external mixer: {}", e),
+            }
+            pending.queries_done += 1;
+            shared.changed.notify_all();
+            continue;
+        }
+
+        return;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mixer = ExternalMixer::open(MixerConfig {
+            volume_ctrl: VolumeCtrl::Linear,
+            external: format!("tcp:{}", listener.local_addr().unwrap()),
+            external_query: true,
+            ..Default::default()
+        });
+
+        mixer.set_volume(VolumeCtrl::MAX_VOLUME / 4);
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "set 0.2500\n");
+        while mixer.shared.pending.lock().unwrap().applied.is_none() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let server = thread::spawn(move || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "get\n");
+            reader.get_mut().write_all(b"0.75\n").unwrap();
+            reader
+        });
+        assert_eq!(mixer.volume(), 49151);
+        let _reader = server.join().unwrap();
+
+        // The volume that was queried isn't set again.
+        mixer.set_volume(49151);
+        assert!(mixer.shared.pending.lock().unwrap().volume.is_none());
+    }
+
+    #[test]
+    fn unanswered_query() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mixer = ExternalMixer::open(MixerConfig {
+            volume_ctrl: VolumeCtrl::Linear,
+            external: format!("tcp:{}", listener.local_addr().unwrap()),
+            external_query: true,
+            ..Default::default()
+        });
+
+        // The socket takes the query but never answers it, which the caller doesn't wait for.
+        let start = Instant::now();
+        assert_eq!(mixer.volume(), VolumeCtrl::MAX_VOLUME / 2);
+        assert!(start.elapsed() < SOCKET_TIMEOUT);
+        let _stream = listener.accept().unwrap();
+    }
+}
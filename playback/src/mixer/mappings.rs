@@ -1,5 +1,12 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use thiserror::Error;
+
 use super::VolumeCtrl;
-use crate::player::db_to_ratio;
+use crate::player::{db_to_ratio, ratio_to_db};
 
 pub trait MappedCtrl {
     fn to_mapped(&self, volume: u16) -> f64;
@@ -17,8 +24,9 @@ impl MappedCtrl for VolumeCtrl {
         // reach zero).
         if volume == 0 {
             return 0.0;
-        } else if volume == Self::MAX_VOLUME {
+        } else if volume == Self::MAX_VOLUME && !matches!(self, Self::Table(_)) {
             // And limit in case of rounding errors (as is the case for log).
+            // A table may well not go all the way up.
             return 1.0;
         }
 
@@ -29,6 +37,7 @@ impl MappedCtrl for VolumeCtrl {
                     CubicMapping::linear_to_mapped(normalized_volume, db_range)
                 }
                 Self::Log(db_range) => LogMapping::linear_to_mapped(normalized_volume, db_range),
+                Self::Table(ref table) => table.linear_to_mapped(normalized_volume),
                 _ => normalized_volume,
             }
         } else {
@@ -63,6 +72,7 @@ impl MappedCtrl for VolumeCtrl {
             match *self {
                 Self::Cubic(db_range) => CubicMapping::mapped_to_linear(mapped_volume, db_range),
                 Self::Log(db_range) => LogMapping::mapped_to_linear(mapped_volume, db_range),
+                Self::Table(ref table) => table.mapped_to_linear(mapped_volume),
                 _ => mapped_volume,
             }
         } else {
@@ -82,6 +92,7 @@ impl MappedCtrl for VolumeCtrl {
             Self::Fixed => 0.0,
             Self::Linear => Self::DEFAULT_DB_RANGE, // arbitrary, could be anything > 0
             Self::Log(db_range) | Self::Cubic(db_range) => db_range,
+            Self::Table(ref table) => table.db_range(),
         }
     }
 
@@ -95,7 +106,7 @@ impl MappedCtrl for VolumeCtrl {
     }
 
     fn range_ok(&self) -> bool {
-        self.db_range() > 0.0 || matches!(self, Self::Fixed | Self::Linear)
+        self.db_range() > 0.0 || matches!(self, Self::Fixed | Self::Linear | Self::Table(_))
     }
 }
 
@@ -161,3 +172,162 @@ impl CubicMapping {
         f64::powf(10.0, -1.0 * db_range / 60.0)
     }
 }
+
+#[derive(Debug, Error)]
+pub enum VolumeTableError {
+    #[error("Unable to read the volume table: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid point on line {line} of the volume table: {content:?}, expected a volume in % and a gain in dB")]
+    InvalidPoint { line: usize, content: String },
+    #[error("The volume table needs at least two points")]
+    TooFewPoints,
+    #[error("The volumes of the volume table must go up within 0 - 100 %, and the gains must not go down nor above 0 dB")]
+    NotMonotonic,
+}
+
+// A curve that follows a table of points, for hardware whose response none of the mappings
+// above match. Each point gives the gain in dB (0 dB or below) at a volume in %. The gain is
+// interpolated linearly in dB between the points, and stays at the first and last one below
+// and above them.
+//
+// A table is read from a file with a point per line, the volume and the gain separated by
+// whitespace or a comma. Empty lines and those that start with # are skipped, e.g.:
+//
+// # volume %, dB
+// 0, -80
+// 50, -30
+// 100, 0
+#[derive(Clone, Debug, PartialEq)]
+pub struct VolumeTable {
+    // (volume in %, gain in dB)
+    points: Vec<(f64, f64)>,
+}
+
+impl VolumeTable {
+    pub fn new(points: Vec<(f64, f64)>) -> Result<Self, VolumeTableError> {
+        if points.len() < 2 {
+            return Err(VolumeTableError::TooFewPoints);
+        }
+
+        let valid = points
+            .iter()
+            .all(|&(volume, db)| (0.0..=100.0).contains(&volume) && db.is_finite() && db <= 0.0)
+            && points
+                .windows(2)
+                .all(|w| w[0].0 < w[1].0 && w[0].1 <= w[1].1);
+        if !valid {
+            return Err(VolumeTableError::NotMonotonic);
+        }
+
+        Ok(Self { points })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, VolumeTableError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn linear_to_mapped(&self, normalized_volume: f64) -> f64 {
+        db_to_ratio(interpolate(&self.points, normalized_volume * 100.0))
+    }
+
+    pub fn mapped_to_linear(&self, mapped_volume: f64) -> f64 {
+        let inverse: Vec<_> = self
+            .points
+            .iter()
+            .map(|&(volume, db)| (db, volume))
+            .collect();
+        interpolate(&inverse, ratio_to_db(mapped_volume)) / 100.0
+    }
+
+    pub fn db_range(&self) -> f64 {
+        self.points[self.points.len() - 1].1 - self.points[0].1
+    }
+}
+
+impl FromStr for VolumeTable {
+    type Err = VolumeTableError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut points = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let content = line.trim();
+            if content.is_empty() || content.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || VolumeTableError::InvalidPoint {
+                line: index + 1,
+                content: content.to_string(),
+            };
+            let values: Vec<_> = content
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<f64>().map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?;
+            match values[..] {
+                [volume, db] => points.push((volume, db)),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Self::new(points)
+    }
+}
+
+// The value at x of the curve through the points, which must go up by x. Where the curve is
+// flat in x, the lowest value is taken.
+fn interpolate(points: &[(f64, f64)], x: f64) -> f64 {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    if x <= first.0 {
+        return first.1;
+    } else if x >= last.0 {
+        return last.1;
+    }
+
+    let index = points
+        .iter()
+        .position(|&(point_x, _)| point_x >= x)
+        .unwrap_or(points.len() - 1);
+    let ((x0, y0), (x1, y1)) = (points[index - 1], points[index]);
+    if x1 - x0 <= f64::EPSILON {
+        y0
+    } else {
+        y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_table() {
+        let table: VolumeTable = "# volume %, dB\n0, -80\n\n50 -20\n75, -20\n90, -6"
+            .parse()
+            .unwrap();
+        assert_eq!(table.db_range(), 74.0);
+
+        let volume_ctrl = VolumeCtrl::Table(table);
+        let half = VolumeCtrl::MAX_VOLUME / 2;
+        assert!((ratio_to_db(volume_ctrl.to_mapped(half / 2)) - -50.0).abs() < 0.01);
+        // It doesn't go up to 0 dB at full volume.
+        assert!((ratio_to_db(volume_ctrl.to_mapped(VolumeCtrl::MAX_VOLUME)) - -6.0).abs() < 1e-9);
+
+        // back and forth, with the flat part of the curve unmapped to where it starts
+        for &volume in &[1000, half / 2, 55000] {
+            let volume_back = volume_ctrl.from_mapped(volume_ctrl.to_mapped(volume));
+            assert!((volume_back as i32 - volume as i32).abs() <= 1);
+        }
+        let flat = VolumeCtrl::MAX_VOLUME / 100 * 60;
+        assert_eq!(volume_ctrl.from_mapped(volume_ctrl.to_mapped(flat)), half);
+
+        assert!(matches!(
+            "0 -60\n50 -70\n100 0".parse::<VolumeTable>(),
+            Err(VolumeTableError::NotMonotonic)
+        ));
+        assert!(matches!(
+            "0 -60\n50\n100 0".parse::<VolumeTable>(),
+            Err(VolumeTableError::InvalidPoint { line: 2, .. })
+        ));
+    }
+}
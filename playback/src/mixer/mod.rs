@@ -1,4 +1,7 @@
+use std::time::Duration;
+
 use crate::config::VolumeCtrl;
+use crate::SAMPLE_RATE;
 
 pub mod mappings;
 use self::mappings::MappedCtrl;
@@ -17,12 +20,15 @@ pub trait Mixer: Send {
 }
 
 pub trait AudioFilter {
-    fn modify_stream(&self, data: &mut [f64]);
+    fn modify_stream(&mut self, data: &mut [f64]);
 }
 
 pub mod softmixer;
 use self::softmixer::SoftMixer;
 
+pub mod external;
+use self::external::ExternalMixer;
+
 #[cfg(feature = "alsa-backend")]
 pub mod alsamixer;
 #[cfg(feature = "alsa-backend")]
@@ -34,6 +40,14 @@ pub struct MixerConfig {
     pub control: String,
     pub index: u32,
     pub volume_ctrl: VolumeCtrl,
+    // How long the softvol mixer takes to glide from one volume to the next, at the sample rate
+    // of the output.
+    pub volume_ramp: Duration,
+    pub sample_rate: u32,
+    // For the external mixer: the command that is run, or the socket that is written to with
+    // `tcp:HOST:PORT` or `unix:PATH`, and whether the volume is queried from it.
+    pub external: String,
+    pub external_query: bool,
 }
 
 impl Default for MixerConfig {
@@ -43,6 +57,10 @@ impl Default for MixerConfig {
             control: String::from("PCM"),
             index: 0,
             volume_ctrl: VolumeCtrl::default(),
+            volume_ramp: Duration::from_millis(50),
+            sample_rate: SAMPLE_RATE,
+            external: String::new(),
+            external_query: false,
         }
     }
 }
@@ -55,6 +73,7 @@ fn mk_sink<M: Mixer + 'static>(config: MixerConfig) -> Box<dyn Mixer> {
 
 pub const MIXERS: &[(&str, MixerFn)] = &[
     (SoftMixer::NAME, mk_sink::<SoftMixer>), // default goes first
+    (ExternalMixer::NAME, mk_sink::<ExternalMixer>),
     #[cfg(feature = "alsa-backend")]
     (AlsaMixer::NAME, mk_sink::<AlsaMixer>),
 ];
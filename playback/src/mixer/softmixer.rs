@@ -4,6 +4,7 @@ use std::sync::Arc;
 use super::AudioFilter;
 use super::{MappedCtrl, VolumeCtrl};
 use super::{Mixer, MixerConfig};
+use crate::NUM_CHANNELS;
 
 #[derive(Clone)]
 pub struct SoftMixer {
@@ -11,16 +12,22 @@ pub struct SoftMixer {
     // It's much faster than a Mutex<f64>.
     volume: Arc<AtomicU64>,
     volume_ctrl: VolumeCtrl,
+    ramp_frames: u64,
 }
 
 impl Mixer for SoftMixer {
     fn open(config: MixerConfig) -> Self {
         let volume_ctrl = config.volume_ctrl;
-        info!("Mixing with softvol and volume control: {:?}", volume_ctrl);
+        info!(
+            "Mixing with softvol and volume control: {:?}, ramp: {} ms",
+            volume_ctrl,
+            config.volume_ramp.as_millis()
+        );
 
         Self {
             volume: Arc::new(AtomicU64::new(f64::to_bits(0.5))),
             volume_ctrl,
+            ramp_frames: (config.volume_ramp.as_secs_f64() * config.sample_rate as f64) as u64,
         }
     }
 
@@ -36,8 +43,13 @@ impl Mixer for SoftMixer {
     }
 
     fn get_audio_filter(&self) -> Option<Box<dyn AudioFilter + Send>> {
+        let volume = f64::from_bits(self.volume.load(Ordering::Relaxed));
         Some(Box::new(SoftVolumeApplier {
             volume: self.volume.clone(),
+            ramp_frames: self.ramp_frames,
+            current: volume,
+            target: volume,
+            step: 0.0,
         }))
     }
 }
@@ -46,17 +58,84 @@ impl SoftMixer {
     pub const NAME: &'static str = "softvol";
 }
 
+// Glides to a new volume over the ramp rather than jumping to it, which would be heard as
+// zipper noise while a volume knob is turned. The ramp is linear in the mapped volume, i.e. in
+// the factor the samples are scaled by, so it follows the curve of the volume control.
 struct SoftVolumeApplier {
     volume: Arc<AtomicU64>,
+    ramp_frames: u64,
+    // the factor of the last frame
+    current: f64,
+    // the volume that is ramped to, and how much the factor changes per frame on the way
+    target: f64,
+    step: f64,
 }
 
 impl AudioFilter for SoftVolumeApplier {
-    fn modify_stream(&self, data: &mut [f64]) {
+    fn modify_stream(&mut self, data: &mut [f64]) {
         let volume = f64::from_bits(self.volume.load(Ordering::Relaxed));
-        if volume < 1.0 {
-            for x in data.iter_mut() {
-                *x *= volume;
+        if volume != self.target {
+            // A change during a ramp starts a new one from where that one got to.
+            self.target = volume;
+            self.step = (volume - self.current) / self.ramp_frames.max(1) as f64;
+        }
+
+        if self.current == self.target {
+            if volume < 1.0 {
+                for x in data.iter_mut() {
+                    *x *= volume;
+                }
+            }
+            return;
+        }
+
+        for frame in data.chunks_mut(NUM_CHANNELS as usize) {
+            if self.current != self.target {
+                self.current += self.step;
+                if (self.step > 0.0 && self.current > self.target)
+                    || (self.step < 0.0 && self.current < self.target)
+                {
+                    self.current = self.target;
+                }
+            }
+            for x in frame.iter_mut() {
+                *x *= self.current;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn volume_ramp() {
+        let mixer = SoftMixer::open(MixerConfig {
+            volume_ctrl: VolumeCtrl::Linear,
+            volume_ramp: Duration::from_millis(10),
+            sample_rate: 1000,
+            ..Default::default()
+        });
+        mixer.set_volume(0);
+        let mut filter = mixer.get_audio_filter().unwrap();
+
+        mixer.set_volume(VolumeCtrl::MAX_VOLUME);
+        let mut data = vec![1.0; 15 * NUM_CHANNELS as usize];
+        filter.modify_stream(&mut data);
+
+        // Ten frames to get there, in steps of the same size, on all channels alike.
+        let frames: Vec<_> = data.chunks(NUM_CHANNELS as usize).collect();
+        for (i, frame) in frames.iter().enumerate() {
+            let expected = f64::min((i + 1) as f64 / 10.0, 1.0);
+            assert!(frame.iter().all(|x| (x - expected).abs() < 1e-9));
+        }
+
+        // Turning it down again starts from full volume.
+        mixer.set_volume(VolumeCtrl::MAX_VOLUME / 2);
+        let mut data = vec![1.0; NUM_CHANNELS as usize];
+        filter.modify_stream(&mut data);
+        assert!(data[0] < 1.0 && data[0] > 0.9);
+    }
+}
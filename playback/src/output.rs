@@ -0,0 +1,516 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use crate::audio_backend::{Sink, SinkError, SinkResult, StreamProperties};
+use crate::convert::Converter;
+use crate::decoder::AudioPacket;
+use crate::dither::DithererBuilder;
+use crate::mixer::AudioFilter;
+use crate::NUM_CHANNELS;
+
+pub type SinkFactory = Box<dyn FnOnce() -> Box<dyn Sink> + Send>;
+
+// Where the audio of a packet starts in the track it was decoded from, so that decoding can
+// pick up there again when the packet is dropped before it was played.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputPosition {
+    pub play_request_id: u64,
+    pub position_pcm: u64,
+}
+
+enum OutputItem {
+    Audio {
+        packet: AudioPacket,
+        // at the output sample rate, zero for passthrough data
+        frames: u64,
+        position: Option<OutputPosition>,
+    },
+    // Passed on in order with the audio, so that it changes when the audio gets to it.
+    Properties(StreamProperties),
+}
+
+enum OutputRequest {
+    Start,
+    // The audio in the queue is played before the sink is stopped.
+    Stop,
+    SetSink { factory: SinkFactory, start: bool },
+}
+
+struct OutputState {
+    queue: VecDeque<OutputItem>,
+    queued_frames: u64,
+    queued_packets: usize,
+    request: Option<OutputRequest>,
+    reply: Option<SinkResult<()>>,
+    // whether the sink is started and takes the audio from the queue
+    running: bool,
+    // Set when a write fails, and cleared when the sink is started again. Until then the queue
+    // is left as it is.
+    failed: bool,
+    // the error of that write, for the next write of the player to return
+    error: Option<SinkError>,
+    latency: Option<Duration>,
+    shutdown: bool,
+    // the output thread panicked, e.g. in the sink
+    gone: bool,
+}
+
+impl OutputState {
+    // The next item for the output thread to pass on to the sink, if there is one that can be.
+    fn pop_item(&mut self) -> Option<OutputItem> {
+        match self.queue.front()? {
+            OutputItem::Audio { .. } if !self.running || self.failed => return None,
+            OutputItem::Audio { frames, .. } => {
+                self.queued_frames -= frames;
+                self.queued_packets -= 1;
+            }
+            OutputItem::Properties(_) => (),
+        }
+        self.queue.pop_front()
+    }
+
+    fn take_request(&mut self) -> Option<OutputRequest> {
+        match self.request {
+            // Play out what is left first.
+            Some(OutputRequest::Stop)
+                if self.running && !self.failed && self.queued_packets > 0 =>
+            {
+                None
+            }
+            _ => self.request.take(),
+        }
+    }
+}
+
+struct OutputShared {
+    state: Mutex<OutputState>,
+    changed: Condvar,
+}
+
+impl OutputShared {
+    fn lock(&self) -> MutexGuard<'_, OutputState> {
+        // Panic safety: the sink and the audio filter, which are outside of our control, are only
+        // called with the lock released, see run_output().
+        self.state.lock().unwrap()
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, OutputState>) -> MutexGuard<'a, OutputState> {
+        self.changed.wait(state).unwrap()
+    }
+}
+
+// Lets the player know when the output thread is gone, so that it doesn't wait for it forever.
+struct OutputThreadGuard(Arc<OutputShared>);
+
+impl Drop for OutputThreadGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if let Ok(mut state) = self.0.state.lock() {
+                state.gone = true;
+            }
+            self.0.changed.notify_all();
+        }
+    }
+}
+
+// Decouples the sink from decoding. The player writes decoded audio into a queue of bounded
+// length, which a thread of its own passes on to the sink, so that decoding that falls behind
+// for a moment (e.g. while waiting for the download) isn't heard right away. The sink is
+// created, used and dropped on that thread only.
+pub struct PlayerOutput {
+    shared: Arc<OutputShared>,
+    sample_rate: u32,
+    depth_frames: u64,
+    thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl PlayerOutput {
+    // The volume of the mixer is applied as the audio is passed on to the sink rather than when
+    // it is written to the queue, so that changes to it are heard right away.
+    pub fn new<F>(
+        sink_builder: F,
+        audio_filter: Option<Box<dyn AudioFilter + Send>>,
+        ditherer: Option<DithererBuilder>,
+        sample_rate: u32,
+        depth: Duration,
+    ) -> Self
+    where
+        F: FnOnce() -> Box<dyn Sink> + Send + 'static,
+    {
+        let shared = Arc::new(OutputShared {
+            state: Mutex::new(OutputState {
+                queue: VecDeque::new(),
+                queued_frames: 0,
+                queued_packets: 0,
+                request: None,
+                reply: None,
+                running: false,
+                failed: false,
+                error: None,
+                latency: None,
+                shutdown: false,
+                gone: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let thread_shared = shared.clone();
+        let thread_handle = thread::spawn(move || {
+            let guard = OutputThreadGuard(thread_shared);
+            run_output(&guard.0, sink_builder(), audio_filter, ditherer);
+        });
+
+        Self {
+            shared,
+            sample_rate,
+            depth_frames: (depth.as_secs_f64() * sample_rate as f64) as u64,
+            thread_handle: Some(thread_handle),
+        }
+    }
+
+    fn request(&self, request: OutputRequest) -> SinkResult<()> {
+        let mut state = self.shared.lock();
+        state.request = Some(request);
+        self.shared.changed.notify_all();
+
+        loop {
+            if let Some(reply) = state.reply.take() {
+                return reply;
+            }
+            if state.gone {
+                return Err(Self::gone());
+            }
+            state = self.shared.wait(state);
+        }
+    }
+
+    fn gone() -> SinkError {
+        SinkError::NotConnected("The output thread is gone".to_string())
+    }
+
+    pub fn start(&self) -> SinkResult<()> {
+        self.request(OutputRequest::Start)
+    }
+
+    // Blocks until the audio in the queue has been played.
+    pub fn stop(&self) -> SinkResult<()> {
+        self.request(OutputRequest::Stop)
+    }
+
+    // Stops the sink if it is running, replaces it and starts the new one if asked to. The
+    // audio in the queue goes to the new sink.
+    pub fn set_sink(&self, factory: SinkFactory, start: bool) -> SinkResult<()> {
+        self.request(OutputRequest::SetSink { factory, start })
+    }
+
+    pub fn set_stream_properties(&self, properties: &StreamProperties) {
+        let mut state = self.shared.lock();
+        state
+            .queue
+            .push_back(OutputItem::Properties(properties.clone()));
+        self.shared.changed.notify_all();
+    }
+
+    // Blocks while the queue is full. Returns the error of a write to the sink that failed since
+    // the last call, the packet is dropped then.
+    pub fn write(&self, packet: AudioPacket, position: Option<OutputPosition>) -> SinkResult<()> {
+        let frames = match packet {
+            AudioPacket::Samples(ref samples) => (samples.len() / NUM_CHANNELS as usize) as u64,
+            // There is no telling how long passthrough data plays for, so it is queued one
+            // packet at a time.
+            AudioPacket::OggData(_) => 0,
+        };
+
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(error) = state.error.take() {
+                return Err(error);
+            }
+            if state.gone {
+                return Err(Self::gone());
+            }
+
+            let room = state.queued_packets == 0
+                || (frames > 0 && state.queued_frames + frames <= self.depth_frames);
+            // Nothing is taken from the queue then, don't wait for it.
+            if room || !state.running || state.failed {
+                break;
+            }
+            state = self.shared.wait(state);
+        }
+
+        state.queued_frames += frames;
+        state.queued_packets += 1;
+        state.queue.push_back(OutputItem::Audio {
+            packet,
+            frames,
+            position,
+        });
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    // Drops the audio in the queue. Returns where the first of it that was decoded for the
+    // latest play request starts, if any was.
+    pub fn flush(&self) -> Option<OutputPosition> {
+        let mut state = self.shared.lock();
+        let mut first: Option<OutputPosition> = None;
+        state.queue.retain(|item| match item {
+            OutputItem::Audio { position, .. } => {
+                match (first, position) {
+                    (Some(first_position), Some(position))
+                        if first_position.play_request_id == position.play_request_id => {}
+                    (_, Some(position)) => first = Some(*position),
+                    (_, None) => (),
+                }
+                false
+            }
+            OutputItem::Properties(_) => true,
+        });
+        state.queued_frames = 0;
+        state.queued_packets = 0;
+        self.shared.changed.notify_all();
+        first
+    }
+
+    // How long it takes until audio that is written now is heard, including the queue.
+    pub fn latency(&self) -> Option<Duration> {
+        let state = self.shared.lock();
+        let queued = Duration::from_secs_f64(state.queued_frames as f64 / self.sample_rate as f64);
+        match state.latency {
+            Some(latency) => Some(latency + queued),
+            None if state.queued_frames > 0 => Some(queued),
+            None => None,
+        }
+    }
+}
+
+impl Drop for PlayerOutput {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.changed.notify_all();
+        if let Some(handle) = self.thread_handle.take() {
+            if let Err(e) = handle.join() {
+                error!("Player output thread Error: {:?}", e);
+            }
+        }
+    }
+}
+
+fn run_output(
+    shared: &OutputShared,
+    mut sink: Box<dyn Sink>,
+    mut audio_filter: Option<Box<dyn AudioFilter + Send>>,
+    ditherer: Option<DithererBuilder>,
+) {
+    let mut converter = Converter::new(ditherer);
+    // for sinks that replace this one
+    let mut properties = StreamProperties::default();
+
+    let mut state = shared.lock();
+    while !state.shutdown {
+        if let Some(request) = state.take_request() {
+            let was_running = state.running;
+            drop(state);
+
+            let (result, running) = match request {
+                OutputRequest::Start => {
+                    let result = sink.start();
+                    let running = result.is_ok();
+                    (result, running)
+                }
+                OutputRequest::Stop => (sink.stop(), false),
+                OutputRequest::SetSink { factory, start } => {
+                    if was_running {
+                        if let Err(e) = sink.stop() {
+                            warn!("Unable to stop the old sink cleanly: {}", e);
+                        }
+                    }
+                    sink = factory();
+                    sink.set_stream_properties(&properties);
+                    if start {
+                        let result = sink.start();
+                        let running = result.is_ok();
+                        (result, running)
+                    } else {
+                        (Ok(()), false)
+                    }
+                }
+            };
+            let latency = sink.latency();
+
+            state = shared.lock();
+            state.running = running;
+            if running {
+                state.failed = false;
+            }
+            state.latency = latency;
+            state.reply = Some(result);
+            shared.changed.notify_all();
+            continue;
+        }
+
+        let item = match state.pop_item() {
+            Some(item) => item,
+            None => {
+                state = shared.wait(state);
+                continue;
+            }
+        };
+        // There is room in the queue again.
+        shared.changed.notify_all();
+        drop(state);
+
+        let result = match item {
+            OutputItem::Audio { mut packet, .. } => {
+                if let (Some(audio_filter), AudioPacket::Samples(ref mut data)) =
+                    (audio_filter.as_mut(), &mut packet)
+                {
+                    audio_filter.modify_stream(data);
+                }
+                sink.write(packet, &mut converter)
+            }
+            OutputItem::Properties(new_properties) => {
+                sink.set_stream_properties(&new_properties);
+                properties = new_properties;
+                Ok(())
+            }
+        };
+        let latency = sink.latency();
+
+        state = shared.lock();
+        if let Err(e) = result {
+            state.failed = true;
+            state.error = Some(e);
+        }
+        state.latency = latency;
+        shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_backend::NullSink;
+    use std::time::Instant;
+
+    // Takes 10 ms for every write.
+    struct SlowSink {
+        written: Arc<Mutex<Vec<f64>>>,
+    }
+
+    impl Sink for SlowSink {
+        fn write(&mut self, packet: AudioPacket, _: &mut Converter) -> SinkResult<()> {
+            thread::sleep(Duration::from_millis(10));
+            self.written
+                .lock()
+                .unwrap()
+                .push(packet.samples().unwrap()[0]);
+            Ok(())
+        }
+    }
+
+    fn packet(value: f64) -> AudioPacket {
+        AudioPacket::Samples(vec![value; 441 * NUM_CHANNELS as usize])
+    }
+
+    fn position(play_request_id: u64, position_pcm: u64) -> Option<OutputPosition> {
+        Some(OutputPosition {
+            play_request_id,
+            position_pcm,
+        })
+    }
+
+    #[test]
+    fn queue_flush_and_drain() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sink_written = written.clone();
+        // room for five packets of 10 ms
+        let output = PlayerOutput::new(
+            move || {
+                Box::new(SlowSink {
+                    written: sink_written,
+                }) as Box<dyn Sink>
+            },
+            None,
+            None,
+            44100,
+            Duration::from_millis(50),
+        );
+
+        output.start().unwrap();
+        let start = Instant::now();
+        for i in 0..20 {
+            output
+                .write(packet(i as f64), position(1, i * 441))
+                .unwrap();
+        }
+        // Writing is held up by the sink once the queue is full.
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(output.latency().unwrap() >= Duration::from_millis(10));
+
+        // What is dropped is decoded again from where it starts.
+        let flushed = output.flush().unwrap();
+        assert_eq!(flushed.play_request_id, 1);
+        let played = written.lock().unwrap().len() as u64;
+        assert!(flushed.position_pcm >= played * 441);
+        assert!(flushed.position_pcm <= (played + 2) * 441);
+
+        // Stopping plays out what is queued.
+        for i in 0..3 {
+            output
+                .write(packet(100.0 + i as f64), position(2, i * 441))
+                .unwrap();
+        }
+        output.stop().unwrap();
+        assert_eq!(written.lock().unwrap().last(), Some(&102.0));
+        assert_eq!(output.flush(), None);
+    }
+
+    #[test]
+    fn set_sink_hands_over() {
+        let old_sink = NullSink::new(44100);
+        let old_stats = old_sink.stats();
+        let new_sink = NullSink::new(44100);
+        let new_stats = new_sink.stats();
+
+        let output = PlayerOutput::new(
+            move || Box::new(old_sink) as Box<dyn Sink>,
+            None,
+            None,
+            44100,
+            Duration::from_millis(50),
+        );
+
+        output.start().unwrap();
+        for i in 0..3 {
+            output
+                .write(packet(i as f64), position(1, i * 441))
+                .unwrap();
+        }
+        output
+            .set_sink(Box::new(move || Box::new(new_sink) as Box<dyn Sink>), true)
+            .unwrap();
+        for i in 3..6 {
+            output
+                .write(packet(i as f64), position(1, i * 441))
+                .unwrap();
+        }
+        output.stop().unwrap();
+
+        let old_stats = old_stats.lock().unwrap().clone();
+        let new_stats = new_stats.lock().unwrap().clone();
+        assert_eq!((old_stats.starts, old_stats.stops), (1, 1));
+        assert_eq!((new_stats.starts, new_stats.stops), (1, 1));
+
+        // What was still queued goes to the new sink, and nothing is lost on the way.
+        assert!(new_stats.packets_written >= 3);
+        assert_eq!(old_stats.packets_written + new_stats.packets_written, 6);
+        assert_eq!(
+            old_stats.samples_written + new_stats.samples_written,
+            6 * 441 * NUM_CHANNELS as u64
+        );
+    }
+}
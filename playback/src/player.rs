@@ -1,35 +1,64 @@
 use std::cmp::max;
+use std::collections::HashMap;
+use std::fs::File;
 use std::future::Future;
 use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::RangeInclusive;
 use std::pin::Pin;
-use std::process::exit;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
-use std::{mem, thread};
+use std::{iter, mem, thread};
 
 use byteorder::{LittleEndian, ReadBytesExt};
 use futures_util::stream::futures_unordered::FuturesUnordered;
-use futures_util::{future, StreamExt, TryFutureExt};
-use tokio::sync::{mpsc, oneshot};
+use futures_util::{future, ready, StreamExt, TryFutureExt};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Sleep;
 
-use crate::audio::{AudioDecrypt, AudioFile, StreamLoaderController};
+use crate::audio::{AudioDecrypt, AudioFile, DownloadProgress, HttpFile, StreamLoaderController};
 use crate::audio::{
     READ_AHEAD_BEFORE_PLAYBACK, READ_AHEAD_BEFORE_PLAYBACK_ROUNDTRIPS, READ_AHEAD_DURING_PLAYBACK,
     READ_AHEAD_DURING_PLAYBACK_ROUNDTRIPS,
 };
-use crate::audio_backend::Sink;
-use crate::config::{Bitrate, NormalisationMethod, NormalisationType, PlayerConfig};
-use crate::convert::Converter;
+use crate::audio_backend::{Sink, SinkBuilder, SinkError, StreamProperties};
+use crate::config::{
+    AudioCodec, AudioFormat, Bitrate, EqualizerBand, FadeCurve, NormalisationMethod,
+    NormalisationType, PlayerConfig, SleepTimer,
+};
 use crate::core::session::Session;
-use crate::core::spotify_id::SpotifyId;
+use crate::core::spotify_id::{SpotifyAudioType, SpotifyId};
 use crate::core::util::SeqGenerator;
-use crate::decoder::{AudioDecoder, AudioPacket, DecoderError, PassthroughDecoder, VorbisDecoder};
+#[cfg(feature = "symphonia-decoder")]
+use crate::decoder::SymphoniaDecoder;
+#[cfg(not(feature = "symphonia-decoder"))]
+use crate::decoder::VorbisDecoder;
+use crate::decoder::{self, AudioDecoder, AudioPacket, PassthroughDecoder};
+use crate::filter::FilterChain;
+use crate::local_files::{LocalFiles, LocalUri, ReplayGain};
 use crate::metadata::{AudioItem, FileFormat};
 use crate::mixer::AudioFilter;
+use crate::output::{OutputPosition, PlayerOutput};
+use crate::resampler::Resampler;
+use crate::time_stretch::TimeStretcher;
 
-use crate::{MS_PER_PAGE, NUM_CHANNELS, PAGES_PER_MS, SAMPLES_PER_SECOND};
+use crate::{MS_PER_PAGE, NUM_CHANNELS, PAGES_PER_MS, SAMPLES_PER_SECOND, SAMPLE_RATE};
 
 const PRELOAD_NEXT_TRACK_BEFORE_END_DURATION_MS: u32 = 30000;
+// How often the progress of a download is reported while it goes on.
+const DOWNLOAD_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+// The most that a decoder reads at once to get to its next packet: a whole Ogg page, which is a
+// header of 27 bytes followed by a table of up to 255 segments of up to 255 bytes.
+const OGG_PAGE_MAX_SIZE: usize = 27 + 255 + 255 * 255;
+// With `Bitrate::Auto`, the download has to be this many times faster than a bitrate needs for
+// it to be stepped up to, so that there is some to spare for seeking and preloading.
+const AUTO_BITRATE_HEADROOM: f64 = 2.0;
+// How long playback has to go without stalling before the bitrate is stepped up again.
+const AUTO_BITRATE_STEP_UP_DELAY: Duration = Duration::from_secs(120);
+// Round trips that take longer than this are taken for a congested link, which isn't given
+// more to download.
+const AUTO_BITRATE_MAX_PING_TIME: Duration = Duration::from_millis(500);
 pub const DB_VOLTAGE_RATIO: f64 = 20.0;
 
 pub struct Player {
@@ -50,16 +79,26 @@ pub type SinkEventCallback = Box<dyn Fn(SinkStatus) + Send>;
 struct PlayerInternal {
     session: Session,
     config: PlayerConfig,
+    // filled in by a thread of its own, as reading the tags of a collection takes a while
+    local_files: watch::Receiver<Option<Arc<LocalFiles>>>,
     commands: mpsc::UnboundedReceiver<PlayerCommand>,
 
     state: PlayerState,
     preload: PlayerPreload,
-    sink: Box<dyn Sink>,
+    output: PlayerOutput,
     sink_status: SinkStatus,
     sink_event_callback: Option<SinkEventCallback>,
-    audio_filter: Option<Box<dyn AudioFilter + Send>>,
+    stream_properties: StreamProperties,
+    // the album of the track that is playing, see is_other_album()
+    album_id: Option<SpotifyId>,
+    fallback_sink: Option<(SinkBuilder, Option<String>, AudioFormat)>,
+    on_fallback_sink: bool,
+    sink_recovery: Option<PlayerSinkRecovery>,
+    speed: f64,
+    time_stretcher: Option<TimeStretcher>,
+    resampler: Option<Resampler>,
+    filter_chain: FilterChain,
     event_senders: Vec<mpsc::UnboundedSender<PlayerEvent>>,
-    converter: Converter,
 
     limiter_active: bool,
     limiter_attack_counter: u32,
@@ -69,17 +108,41 @@ struct PlayerInternal {
     limiter_strength: f64,
 
     auto_normalise_as_album: bool,
+
+    crossfade: Option<PlayerCrossfade>,
+    crossfade_pending: Option<u64>,
+
+    fade: Option<PlayerFade>,
+
+    // the play request that download progress was last reported for, along with when and what
+    download_progress_reported: Option<(u64, Instant, DownloadProgress)>,
+
+    sleep_timer: Option<PlayerSleepTimer>,
+    // whether the track that is loaded follows on from one that played to its end
+    follows_ended_track: bool,
+
+    // with `Bitrate::Auto`
+    auto_bitrate: Option<PlayerAutoBitrate>,
+}
+
+// A failed sink that waits to be restarted.
+struct PlayerSinkRecovery {
+    retry: u32,
+    backoff: Duration,
+    timer: Pin<Box<Sleep>>,
 }
 
 enum PlayerCommand {
     Load {
         track_id: SpotifyId,
+        local_uri: Option<String>,
         play_request_id: u64,
         play: bool,
         position_ms: u32,
     },
     Preload {
         track_id: SpotifyId,
+        local_uri: Option<String>,
     },
     Play,
     Pause,
@@ -89,6 +152,20 @@ enum PlayerCommand {
     SetSinkEventCallback(Option<SinkEventCallback>),
     EmitVolumeSetEvent(u16),
     SetAutoNormaliseAsAlbum(bool),
+    SetEqualizer(Vec<EqualizerBand>),
+    AddAudioFilter(Box<dyn AudioFilter + Send>),
+    SetSpeed(f64),
+    SetSink {
+        sink_builder: SinkBuilder,
+        device: Option<String>,
+        format: AudioFormat,
+    },
+    SetFallbackSink {
+        sink_builder: SinkBuilder,
+        device: Option<String>,
+        format: AudioFormat,
+    },
+    SetSleepTimer(Option<SleepTimer>),
 }
 
 #[derive(Debug, Clone)]
@@ -117,6 +194,20 @@ pub enum PlayerEvent {
         track_id: SpotifyId,
         position_ms: u32,
     },
+    // The metadata of a track that was loaded, issued before it starts playing or is paused. It is
+    // not issued again when the track that is loaded already is loaded anew, e.g. to repeat it.
+    TrackChanged {
+        play_request_id: u64,
+        audio_item: Box<AudioItem>,
+    },
+    // The format of the file that a track that was loaded is played from, as picked for the
+    // bitrate of the config, or for the link with `Bitrate::Auto`. Not issued for tracks that
+    // aren't played from one of Spotify's files.
+    FormatSelected {
+        play_request_id: u64,
+        track_id: SpotifyId,
+        format: FileFormat,
+    },
     // The player is preloading a track.
     Preloading {
         track_id: SpotifyId,
@@ -159,10 +250,74 @@ pub enum PlayerEvent {
         play_request_id: u64,
         track_id: SpotifyId,
     },
+    // Playback stalls until more of the track has been downloaded. Episodes that are streamed from
+    // outside of Spotify are read as they arrive, without a download to tell about, so neither
+    // this nor the events below are issued for them.
+    Buffering {
+        play_request_id: u64,
+        track_id: SpotifyId,
+        position_ms: u32,
+    },
+    // Playback goes on after it stalled for buffering.
+    BufferingFinished {
+        play_request_id: u64,
+        track_id: SpotifyId,
+    },
+    // How far the download of the playing track has come. This is issued periodically while the
+    // track is being downloaded, including while buffering, and once more when it is complete.
+    DownloadProgress {
+        play_request_id: u64,
+        track_id: SpotifyId,
+        progress: DownloadProgress,
+    },
+    // The sleep timer was set while it wasn't.
+    SleepTimerArmed {
+        timer: SleepTimer,
+    },
+    // The sleep timer was set anew while it was already, or cancelled.
+    SleepTimerChanged {
+        timer: Option<SleepTimer>,
+    },
+    // The sleep timer paused playback, or would have if anything was playing. It isn't set
+    // anymore after this.
+    SleepTimerFired {
+        timer: SleepTimer,
+    },
+    // The playback speed was changed, see `Player::set_speed`. Not issued for speeds that are
+    // ignored.
+    SpeedChanged {
+        speed: f64,
+    },
     // The mixer volume was set to a new level.
     VolumeSet {
         volume: u16,
     },
+    // The audio output failed. The player tries to recover it, and pauses if it can't.
+    OutputLost {
+        error: String,
+    },
+    // The audio output works again after it was lost, possibly on the fallback sink.
+    OutputReconnected {
+        fallback: bool,
+    },
+    // Something went wrong that the player could cope with, by skipping or stopping a track.
+    Error {
+        error: PlayerError,
+    },
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum PlayerError {
+    #[error("Unable to decode track <{track_id:?}>, skipping it: {error}")]
+    Decoder { track_id: SpotifyId, error: String },
+    #[error("Unable to seek to {position_ms} ms in track <{track_id:?}>: {error}")]
+    Seek {
+        track_id: SpotifyId,
+        position_ms: u32,
+        error: String,
+    },
+    #[error("Invalid player state in {0}, stopping playback")]
+    InvalidState(&'static str),
 }
 
 impl PlayerEvent {
@@ -192,8 +347,32 @@ impl PlayerEvent {
             }
             | Stopped {
                 play_request_id, ..
+            }
+            | Buffering {
+                play_request_id, ..
+            }
+            | BufferingFinished {
+                play_request_id, ..
+            }
+            | DownloadProgress {
+                play_request_id, ..
+            }
+            | TrackChanged {
+                play_request_id, ..
+            }
+            | FormatSelected {
+                play_request_id, ..
             } => Some(*play_request_id),
-            Changed { .. } | Preloading { .. } | VolumeSet { .. } => None,
+            Changed { .. }
+            | Preloading { .. }
+            | VolumeSet { .. }
+            | SpeedChanged { .. }
+            | SleepTimerArmed { .. }
+            | SleepTimerChanged { .. }
+            | SleepTimerFired { .. }
+            | OutputLost { .. }
+            | OutputReconnected { .. }
+            | Error { .. } => None,
         }
     }
 }
@@ -216,6 +395,28 @@ pub struct NormalisationData {
     album_peak: f32,
 }
 
+impl Default for NormalisationData {
+    fn default() -> Self {
+        Self {
+            track_gain_db: 0.0,
+            track_peak: 1.0,
+            album_gain_db: 0.0,
+            album_peak: 1.0,
+        }
+    }
+}
+
+impl From<ReplayGain> for NormalisationData {
+    fn from(replay_gain: ReplayGain) -> Self {
+        Self {
+            track_gain_db: replay_gain.track_gain_db,
+            track_peak: replay_gain.track_peak,
+            album_gain_db: replay_gain.album_gain_db,
+            album_peak: replay_gain.album_peak,
+        }
+    }
+}
+
 impl NormalisationData {
     fn parse_from_file<T: Read + Seek>(mut file: T) -> io::Result<NormalisationData> {
         const SPOTIFY_NORMALIZATION_HEADER_START_OFFSET: u64 = 144;
@@ -279,6 +480,8 @@ impl NormalisationData {
 }
 
 impl Player {
+    pub const SPEED_RANGE: RangeInclusive<f64> = 0.5..=3.0;
+
     pub fn new<F>(
         config: PlayerConfig,
         session: Session,
@@ -291,6 +494,20 @@ impl Player {
         let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
         let (event_sender, event_receiver) = mpsc::unbounded_channel();
 
+        if config.sample_rate != SAMPLE_RATE && !config.passthrough {
+            debug!(
+                "Resampling from {} Hz to {} Hz",
+                SAMPLE_RATE, config.sample_rate
+            );
+        }
+
+        debug!("Output Buffer: {:?}", config.output_buffer);
+
+        if config.crossfade > Duration::default() {
+            debug!("Crossfade: {:?}", config.crossfade);
+            debug!("Crossfade Curve: {:?}", config.crossfade_curve);
+        }
+
         if config.normalisation {
             debug!("Normalisation Type: {:?}", config.normalisation_type);
             debug!(
@@ -313,21 +530,54 @@ impl Player {
         let handle = thread::spawn(move || {
             debug!("new Player[{}]", session.session_id());
 
-            let converter = Converter::new(config.ditherer);
+            let output = PlayerOutput::new(
+                sink_builder,
+                audio_filter,
+                config.ditherer,
+                config.sample_rate,
+                config.output_buffer,
+            );
+            // Passthrough hands the encoded stream to the sink as is, so there is
+            // nothing to resample.
+            let resampler = if config.sample_rate != SAMPLE_RATE && !config.passthrough {
+                Some(Resampler::new(SAMPLE_RATE, config.sample_rate))
+            } else {
+                None
+            };
+            let filter_chain = FilterChain::new(&config.equalizer, config.sample_rate);
+            let auto_bitrate = if config.bitrate == Bitrate::Auto {
+                Some(PlayerAutoBitrate::new())
+            } else {
+                None
+            };
+            let (local_files_tx, local_files) = watch::channel(None);
+            if let Some(dir) = config.local_files_dir.clone() {
+                thread::spawn(move || {
+                    let _ = local_files_tx.send(Some(Arc::new(LocalFiles::index(&dir))));
+                });
+            }
 
             let internal = PlayerInternal {
                 session,
                 config,
+                local_files,
                 commands: cmd_rx,
 
                 state: PlayerState::Stopped,
                 preload: PlayerPreload::None,
-                sink: sink_builder(),
+                output,
                 sink_status: SinkStatus::Closed,
                 sink_event_callback: None,
-                audio_filter,
+                stream_properties: StreamProperties::default(),
+                album_id: None,
+                fallback_sink: None,
+                on_fallback_sink: false,
+                sink_recovery: None,
+                speed: 1.0,
+                time_stretcher: None,
+                resampler,
+                filter_chain,
                 event_senders: [event_sender].to_vec(),
-                converter,
 
                 limiter_active: false,
                 limiter_attack_counter: 0,
@@ -337,11 +587,28 @@ impl Player {
                 limiter_strength: 0.0,
 
                 auto_normalise_as_album: false,
+
+                crossfade: None,
+                crossfade_pending: None,
+
+                fade: None,
+
+                download_progress_reported: None,
+
+                sleep_timer: None,
+                follows_ended_track: false,
+
+                auto_bitrate,
             };
 
             // While PlayerInternal is written as a future, it still contains blocking code.
-            // It must be run by using block_on() in a dedicated thread.
-            futures_executor::block_on(internal);
+            // It must be run by using block_on() in a dedicated thread. The runtime is only
+            // there for its timers.
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .expect("Failed to create the player runtime");
+            runtime.block_on(internal);
             debug!("PlayerInternal thread finished.");
         });
 
@@ -364,9 +631,32 @@ impl Player {
     }
 
     pub fn load(&mut self, track_id: SpotifyId, start_playing: bool, position_ms: u32) -> u64 {
+        self.load_uri(track_id, None, start_playing, position_ms)
+    }
+
+    // Plays a `spotify:local` URI from the local files directory. The URI can't be told from
+    // the `SpotifyId` of a local file, so it is passed along with it.
+    pub fn load_local(
+        &mut self,
+        track_id: SpotifyId,
+        uri: String,
+        start_playing: bool,
+        position_ms: u32,
+    ) -> u64 {
+        self.load_uri(track_id, Some(uri), start_playing, position_ms)
+    }
+
+    fn load_uri(
+        &mut self,
+        track_id: SpotifyId,
+        local_uri: Option<String>,
+        start_playing: bool,
+        position_ms: u32,
+    ) -> u64 {
         let play_request_id = self.play_request_id_generator.get();
         self.command(PlayerCommand::Load {
             track_id,
+            local_uri,
             play_request_id,
             play: start_playing,
             position_ms,
@@ -376,7 +666,17 @@ impl Player {
     }
 
     pub fn preload(&self, track_id: SpotifyId) {
-        self.command(PlayerCommand::Preload { track_id });
+        self.command(PlayerCommand::Preload {
+            track_id,
+            local_uri: None,
+        });
+    }
+
+    pub fn preload_local(&self, track_id: SpotifyId, uri: String) {
+        self.command(PlayerCommand::Preload {
+            track_id,
+            local_uri: Some(uri),
+        });
     }
 
     pub fn play(&self) {
@@ -424,6 +724,52 @@ impl Player {
     pub fn set_auto_normalise_as_album(&self, setting: bool) {
         self.command(PlayerCommand::SetAutoNormaliseAsAlbum(setting));
     }
+
+    pub fn set_equalizer(&self, bands: Vec<EqualizerBand>) {
+        self.command(PlayerCommand::SetEqualizer(bands));
+    }
+
+    // Changes the tempo without changing the pitch, e.g. to listen to podcasts faster.
+    // Positions in player events and seeks remain in media time.
+    pub fn set_speed(&self, speed: f64) {
+        self.command(PlayerCommand::SetSpeed(speed));
+    }
+
+    // Switches to another output, e.g. from headphones to speakers, and carries on where the
+    // old one left off.
+    pub fn set_sink(&self, sink_builder: SinkBuilder, device: Option<String>, format: AudioFormat) {
+        self.command(PlayerCommand::SetSink {
+            sink_builder,
+            device,
+            format,
+        });
+    }
+
+    // The sink to switch to when the current one fails and can't be restarted.
+    pub fn set_fallback_sink(
+        &self,
+        sink_builder: SinkBuilder,
+        device: Option<String>,
+        format: AudioFormat,
+    ) {
+        self.command(PlayerCommand::SetFallbackSink {
+            sink_builder,
+            device,
+            format,
+        });
+    }
+
+    // Pauses playback after some time or at the end of what is playing, after fading it out for
+    // `sleep_timer_fade` of the config. Setting it again replaces it, and `None` cancels it.
+    pub fn set_sleep_timer(&self, timer: Option<SleepTimer>) {
+        self.command(PlayerCommand::SetSleepTimer(timer));
+    }
+
+    // Appends a stage to the filter chain. Stages run after the equalizer, in the order they
+    // were added, and before the volume of the mixer is applied.
+    pub fn add_audio_filter(&self, audio_filter: Box<dyn AudioFilter + Send>) {
+        self.command(PlayerCommand::AddAudioFilter(audio_filter));
+    }
 }
 
 impl Drop for Player {
@@ -440,6 +786,10 @@ impl Drop for Player {
 }
 
 struct PlayerLoadedTrackData {
+    // None when the track is loaded again from the current state, whose metadata is still known
+    audio_item: Option<Box<AudioItem>>,
+    // the file it is played from, if it is one of Spotify's and was loaded just now
+    format: Option<FileFormat>,
     decoder: Decoder,
     normalisation_data: NormalisationData,
     stream_loader_controller: StreamLoaderController,
@@ -448,6 +798,252 @@ struct PlayerLoadedTrackData {
     stream_position_pcm: u64,
 }
 
+// The tail of a track that is faded out while the next track is faded in.
+struct PlayerCrossfade {
+    decoder: Decoder,
+    normalisation_factor: f64,
+    buffer: Vec<f64>,
+    buffer_position: usize,
+    elapsed_frames: u64,
+    duration_frames: u64,
+}
+
+impl PlayerCrossfade {
+    fn next_sample(&mut self) -> Option<f64> {
+        while self.buffer_position >= self.buffer.len() {
+            match self.decoder.next_packet() {
+                Ok(Some(AudioPacket::Samples(samples))) => {
+                    self.buffer = samples;
+                    self.buffer_position = 0;
+                }
+                Ok(Some(AudioPacket::OggData(_))) | Ok(None) => return None,
+                Err(e) => {
+                    warn!("Ending crossfade early, unable to decode samples: {}", e);
+                    return None;
+                }
+            }
+        }
+
+        let sample = self.buffer[self.buffer_position];
+        self.buffer_position += 1;
+        Some(sample * self.normalisation_factor)
+    }
+
+    fn mix(&mut self, data: &mut [f64], curve: FadeCurve) {
+        for frame in data.chunks_mut(NUM_CHANNELS as usize) {
+            if self.is_finished() {
+                return;
+            }
+
+            let progress = self.elapsed_frames as f64 / self.duration_frames as f64;
+            let fade_in_gain = curve.fade_in_gain(progress);
+            let fade_out_gain = curve.fade_out_gain(progress);
+
+            for sample in frame.iter_mut() {
+                match self.next_sample() {
+                    Some(tail_sample) => {
+                        *sample = *sample * fade_in_gain + tail_sample * fade_out_gain
+                    }
+                    None => {
+                        // The outgoing track ended before the fade did.
+                        self.elapsed_frames = self.duration_frames;
+                        return;
+                    }
+                }
+            }
+
+            self.elapsed_frames += 1;
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.elapsed_frames >= self.duration_frames
+    }
+}
+
+// A short gain ramp at the output, so that audio that starts or stops in the middle of a track
+// doesn't click.
+struct PlayerFade {
+    fading_in: bool,
+    elapsed_frames: u64,
+    duration_frames: u64,
+}
+
+impl PlayerFade {
+    // Ramps in the opposite direction from where the fade is now, if any, so that a fade that is
+    // interrupted carries on from the same gain.
+    fn new(fading_in: bool, duration_frames: u64, interrupted: Option<&PlayerFade>) -> Self {
+        let elapsed_frames = match interrupted {
+            Some(fade) if fade.fading_in != fading_in && !fade.is_finished() => duration_frames
+                .saturating_sub(fade.elapsed_frames * duration_frames / fade.duration_frames),
+            _ => 0,
+        };
+
+        Self {
+            fading_in,
+            elapsed_frames,
+            duration_frames,
+        }
+    }
+
+    fn apply(&mut self, data: &mut [f64]) {
+        // The shape of such a short ramp is hardly audible, as long as it is smooth.
+        let curve = FadeCurve::SCurve;
+
+        for frame in data.chunks_mut(NUM_CHANNELS as usize) {
+            let progress = self.elapsed_frames as f64 / self.duration_frames as f64;
+            let gain = if self.fading_in {
+                curve.fade_in_gain(progress)
+            } else {
+                curve.fade_out_gain(progress)
+            };
+
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+
+            self.elapsed_frames = self.elapsed_frames.saturating_add(1);
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.elapsed_frames >= self.duration_frames
+    }
+}
+
+struct PlayerSleepTimer {
+    timer: SleepTimer,
+    // when a timer that runs for some time is up
+    deadline: Option<Instant>,
+    // wakes the player up at the deadline, in case nothing else does then
+    wake: Option<Pin<Box<Sleep>>>,
+    // the tail before playback is paused
+    fade: Option<PlayerFade>,
+}
+
+impl PlayerSleepTimer {
+    fn new(timer: SleepTimer) -> Self {
+        let deadline = match timer {
+            SleepTimer::After(duration) => Some(Instant::now() + duration),
+            SleepTimer::EndOfTrack | SleepTimer::EndOfAlbum => None,
+        };
+        Self {
+            timer,
+            deadline,
+            wake: deadline.map(|deadline| Box::pin(tokio::time::sleep_until(deadline.into()))),
+            fade: None,
+        }
+    }
+
+    // Ready once, when the deadline has come.
+    fn poll_deadline(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        match self.wake {
+            Some(ref mut wake) => {
+                ready!(wake.as_mut().poll(cx));
+                self.wake = None;
+                Poll::Ready(())
+            }
+            None => Poll::Pending,
+        }
+    }
+
+    // Whether the timer pauses playback as a track starts, given whether that track follows one
+    // that played to its end, and whether it is from another album.
+    fn fires_before_track(&self, follows_ended_track: bool, other_album: bool) -> bool {
+        match self.timer {
+            SleepTimer::After(_) => self
+                .deadline
+                .map_or(false, |deadline| Instant::now() >= deadline),
+            SleepTimer::EndOfTrack => follows_ended_track,
+            SleepTimer::EndOfAlbum => follows_ended_track && other_album,
+        }
+    }
+}
+
+// Picks the bitrate of the tracks that are loaded with `Bitrate::Auto`, from the download rate of
+// the session and from how playback went with the bitrate before. It is stepped down right away
+// when playback stalls, and up one step at a time when the link has kept up for a while.
+struct PlayerAutoBitrate {
+    bitrate: Bitrate,
+    last_underrun: Option<Instant>,
+    // how long it has to go without stalls before it steps up again
+    step_up_delay: Duration,
+}
+
+impl PlayerAutoBitrate {
+    const BITRATES: [Bitrate; 3] = [Bitrate::Bitrate96, Bitrate::Bitrate160, Bitrate::Bitrate320];
+
+    fn new() -> Self {
+        Self {
+            bitrate: Bitrate::default(),
+            last_underrun: None,
+            step_up_delay: AUTO_BITRATE_STEP_UP_DELAY,
+        }
+    }
+
+    fn index(&self) -> usize {
+        Self::BITRATES
+            .iter()
+            .position(|&bitrate| bitrate == self.bitrate)
+            .unwrap_or_default()
+    }
+
+    // what a bitrate takes to download, as far as the Vorbis files go
+    fn bytes_per_second(bitrate: Bitrate) -> usize {
+        match bitrate {
+            Bitrate::Bitrate96 => 12 * 1024,
+            Bitrate::Bitrate160 | Bitrate::Auto => 20 * 1024,
+            Bitrate::Bitrate320 => 40 * 1024,
+        }
+    }
+
+    fn underrun(&mut self) {
+        self.last_underrun = Some(Instant::now());
+        if let Some(index) = self.index().checked_sub(1) {
+            self.bitrate = Self::BITRATES[index];
+            info!(
+                "Playback stalled, loading the next tracks with {:?}",
+                self.bitrate
+            );
+        }
+    }
+
+    // The bitrate for the next track, from the download rate (bytes per second) and the ping time
+    // of the download of the current track, as far as they are known.
+    fn select(&mut self, download_rate: Option<usize>, ping_time: Option<Duration>) -> Bitrate {
+        let download_rate = match download_rate {
+            Some(download_rate) => download_rate,
+            None => return self.bitrate,
+        };
+
+        let index = self.index();
+        let previous = self.bitrate;
+        if download_rate < Self::bytes_per_second(self.bitrate) && index > 0 {
+            // The link doesn't keep up, even though playback hasn't stalled (yet).
+            self.bitrate = Self::BITRATES[index - 1];
+        } else if let Some(&next) = Self::BITRATES.get(index + 1) {
+            let fast_enough =
+                download_rate as f64 >= Self::bytes_per_second(next) as f64 * AUTO_BITRATE_HEADROOM;
+            let recovered = !matches!(self.last_underrun,
+                Some(last_underrun) if last_underrun.elapsed() < self.step_up_delay);
+            let responsive = !matches!(ping_time,
+                Some(ping_time) if ping_time > AUTO_BITRATE_MAX_PING_TIME);
+            if fast_enough && recovered && responsive {
+                self.bitrate = next;
+            }
+        }
+
+        if self.bitrate != previous {
+            info!(
+                "Download rate is {} kB/s, loading the next tracks with {:?}",
+                download_rate / 1024,
+                self.bitrate
+            );
+        }
+        self.bitrate
+    }
+}
+
 enum PlayerPreload {
     None,
     Loading {
@@ -511,7 +1107,7 @@ impl PlayerState {
             Playing { .. } => true,
             Invalid => {
                 error!("PlayerState is_playing: invalid state");
-                exit(1);
+                false
             }
         }
     }
@@ -527,6 +1123,17 @@ impl PlayerState {
         matches!(self, Loading { .. })
     }
 
+    fn track_id(&self) -> Option<SpotifyId> {
+        use self::PlayerState::*;
+        match *self {
+            Stopped | Invalid => None,
+            Loading { track_id, .. }
+            | Paused { track_id, .. }
+            | Playing { track_id, .. }
+            | EndOfTrack { track_id, .. } => Some(track_id),
+        }
+    }
+
     fn decoder(&mut self) -> Option<&mut Decoder> {
         use self::PlayerState::*;
         match *self {
@@ -539,7 +1146,7 @@ impl PlayerState {
             } => Some(decoder),
             Invalid => {
                 error!("PlayerState decoder: invalid state");
-                exit(1);
+                None
             }
         }
     }
@@ -558,12 +1165,12 @@ impl PlayerState {
             } => Some(stream_loader_controller),
             Invalid => {
                 error!("PlayerState stream_loader_controller: invalid state");
-                exit(1);
+                None
             }
         }
     }
 
-    fn playing_to_end_of_track(&mut self) {
+    fn playing_to_end_of_track(&mut self) -> Result<(), PlayerError> {
         use self::PlayerState::*;
         match mem::replace(self, Invalid) {
             Playing {
@@ -581,6 +1188,8 @@ impl PlayerState {
                     track_id,
                     play_request_id,
                     loaded_track: PlayerLoadedTrackData {
+                        audio_item: None,
+                        format: None,
                         decoder,
                         normalisation_data,
                         stream_loader_controller,
@@ -590,14 +1199,15 @@ impl PlayerState {
                     },
                 };
             }
-            _ => {
-                error!("Called playing_to_end_of_track in non-playing state.");
-                exit(1);
+            state => {
+                *self = state;
+                return Err(PlayerError::InvalidState("playing_to_end_of_track"));
             }
         }
+        Ok(())
     }
 
-    fn paused_to_playing(&mut self) {
+    fn paused_to_playing(&mut self) -> Result<(), PlayerError> {
         use self::PlayerState::*;
         match ::std::mem::replace(self, Invalid) {
             Paused {
@@ -626,14 +1236,15 @@ impl PlayerState {
                     suggested_to_preload_next_track,
                 };
             }
-            _ => {
-                error!("PlayerState paused_to_playing: invalid state");
-                exit(1);
+            state => {
+                *self = state;
+                return Err(PlayerError::InvalidState("paused_to_playing"));
             }
         }
+        Ok(())
     }
 
-    fn playing_to_paused(&mut self) {
+    fn playing_to_paused(&mut self) -> Result<(), PlayerError> {
         use self::PlayerState::*;
         match ::std::mem::replace(self, Invalid) {
             Playing {
@@ -662,20 +1273,110 @@ impl PlayerState {
                     suggested_to_preload_next_track,
                 };
             }
-            _ => {
-                error!("PlayerState playing_to_paused: invalid state");
-                exit(1);
+            state => {
+                *self = state;
+                return Err(PlayerError::InvalidState("playing_to_paused"));
             }
         }
+        Ok(())
     }
 }
 
 struct PlayerTrackLoader {
     session: Session,
     config: PlayerConfig,
+    local_files: watch::Receiver<Option<Arc<LocalFiles>>>,
 }
 
 impl PlayerTrackLoader {
+    // The formats that a track is looked for in, in order: by codec with the preferred one first,
+    // and then by how close their bitrate is to the one that is set. Formats that there is no
+    // decoder for are left to the caller to skip.
+    fn file_formats(config: &PlayerConfig) -> Vec<FileFormat> {
+        // (Most) podcasts seem to support only 96 bit Vorbis, so fall back to it
+        let vorbis_formats = match config.bitrate {
+            Bitrate::Bitrate96 => [
+                FileFormat::OGG_VORBIS_96,
+                FileFormat::OGG_VORBIS_160,
+                FileFormat::OGG_VORBIS_320,
+            ],
+            // `Auto` has been resolved to one of the others by the player.
+            Bitrate::Bitrate160 | Bitrate::Auto => [
+                FileFormat::OGG_VORBIS_160,
+                FileFormat::OGG_VORBIS_96,
+                FileFormat::OGG_VORBIS_320,
+            ],
+            Bitrate::Bitrate320 => [
+                FileFormat::OGG_VORBIS_320,
+                FileFormat::OGG_VORBIS_160,
+                FileFormat::OGG_VORBIS_96,
+            ],
+        };
+
+        let (mp3_formats, aac_formats) = match config.bitrate {
+            Bitrate::Bitrate96 => (
+                [
+                    FileFormat::MP3_96,
+                    FileFormat::MP3_160,
+                    FileFormat::MP3_256,
+                    FileFormat::MP3_320,
+                ],
+                [
+                    FileFormat::MP4_128,
+                    FileFormat::AAC_160,
+                    FileFormat::AAC_320,
+                ],
+            ),
+            Bitrate::Bitrate160 | Bitrate::Auto => (
+                [
+                    FileFormat::MP3_160,
+                    FileFormat::MP3_96,
+                    FileFormat::MP3_256,
+                    FileFormat::MP3_320,
+                ],
+                [
+                    FileFormat::AAC_160,
+                    FileFormat::MP4_128,
+                    FileFormat::AAC_320,
+                ],
+            ),
+            Bitrate::Bitrate320 => (
+                [
+                    FileFormat::MP3_320,
+                    FileFormat::MP3_256,
+                    FileFormat::MP3_160,
+                    FileFormat::MP3_96,
+                ],
+                [
+                    FileFormat::AAC_320,
+                    FileFormat::AAC_160,
+                    FileFormat::MP4_128,
+                ],
+            ),
+        };
+
+        // The preferred codec goes first, then the others in the order of the default.
+        let preferred_codec = config.preferred_codec;
+        let codecs = iter::once(preferred_codec).chain(
+            [AudioCodec::Vorbis, AudioCodec::Mp3, AudioCodec::Aac]
+                .iter()
+                .copied()
+                .filter(|&codec| codec != preferred_codec),
+        );
+
+        codecs
+            .flat_map(|codec| match codec {
+                AudioCodec::Vorbis => &vorbis_formats[..],
+                // Some podcasts only come as MP3 or AAC, which can be decoded but not passed
+                // through.
+                _ if config.passthrough => &[],
+                AudioCodec::Mp3 => &mp3_formats[..],
+                AudioCodec::Aac => &aac_formats[..],
+            })
+            .copied()
+            .collect()
+    }
+
     async fn find_available_alternative(&self, audio: AudioItem) -> Option<AudioItem> {
         if audio.available {
             Some(audio)
@@ -717,8 +1418,19 @@ impl PlayerTrackLoader {
     async fn load_track(
         &self,
         spotify_id: SpotifyId,
+        local_uri: Option<String>,
         position_ms: u32,
     ) -> Option<PlayerLoadedTrackData> {
+        if spotify_id.audio_type == SpotifyAudioType::Local {
+            return match local_uri {
+                Some(uri) => self.load_local_track(spotify_id, uri, position_ms).await,
+                None => {
+                    warn!("<{}> is a local file without its URI", spotify_id.to_uri());
+                    None
+                }
+            };
+        }
+
         let audio = match AudioItem::get_audio_item(&self.session, spotify_id).await {
             Ok(audio) => audio,
             Err(e) => {
@@ -747,40 +1459,30 @@ impl PlayerTrackLoader {
         }
         let duration_ms = audio.duration as u32;
 
-        // (Most) podcasts seem to support only 96 bit Vorbis, so fall back to it
-        let formats = match self.config.bitrate {
-            Bitrate::Bitrate96 => [
-                FileFormat::OGG_VORBIS_96,
-                FileFormat::OGG_VORBIS_160,
-                FileFormat::OGG_VORBIS_320,
-            ],
-            Bitrate::Bitrate160 => [
-                FileFormat::OGG_VORBIS_160,
-                FileFormat::OGG_VORBIS_96,
-                FileFormat::OGG_VORBIS_320,
-            ],
-            Bitrate::Bitrate320 => [
-                FileFormat::OGG_VORBIS_320,
-                FileFormat::OGG_VORBIS_160,
-                FileFormat::OGG_VORBIS_96,
-            ],
-        };
+        let entry = Self::file_formats(&self.config)
+            .into_iter()
+            .find_map(|format| {
+                let &file_id = audio.files.get(&format)?;
+                let (decoder_name, decoder_builder) = decoder::find(format)?;
+                Some((format, file_id, decoder_name, decoder_builder))
+            });
 
-        let entry = formats.iter().find_map(|format| {
-            if let Some(&file_id) = audio.files.get(format) {
-                Some((*format, file_id))
-            } else {
-                None
+        let (format, file_id, decoder_name, decoder_builder) = match (entry, &audio.external_url) {
+            (Some(t), _) => t,
+            (None, Some(url)) => {
+                return self
+                    .load_external_track(&audio, url, position_ms, duration_ms)
+                    .await
             }
-        });
-
-        let (format, file_id) = match entry {
-            Some(t) => t,
-            None => {
+            (None, None) => {
                 warn!("<{}> is not available in any supported format", audio.name);
                 return None;
             }
         };
+        let is_vorbis = matches!(
+            format,
+            FileFormat::OGG_VORBIS_96 | FileFormat::OGG_VORBIS_160 | FileFormat::OGG_VORBIS_320
+        );
 
         let bytes_per_second = self.stream_data_rate(format);
         let play_from_beginning = position_ms == 0;
@@ -822,33 +1524,30 @@ impl PlayerTrackLoader {
                 }
             };
 
-            let mut decrypted_file = AudioDecrypt::new(key, encrypted_file);
+            let mut decrypted_file = AudioDecrypt::new(Some(key), encrypted_file);
 
-            let normalisation_data = match NormalisationData::parse_from_file(&mut decrypted_file) {
-                Ok(data) => data,
-                Err(_) => {
-                    warn!("Unable to extract normalisation data, using default value.");
-                    NormalisationData {
-                        track_gain_db: 0.0,
-                        track_peak: 1.0,
-                        album_gain_db: 0.0,
-                        album_peak: 1.0,
-                    }
-                }
+            // Only Spotify's Ogg files have a header with normalisation data in front.
+            let (normalisation_data, offset) = if is_vorbis {
+                let normalisation_data =
+                    match NormalisationData::parse_from_file(&mut decrypted_file) {
+                        Ok(data) => data,
+                        Err(_) => {
+                            warn!("Unable to extract normalisation data, using default value.");
+                            NormalisationData::default()
+                        }
+                    };
+                (normalisation_data, 0xa7)
+            } else {
+                (NormalisationData::default(), 0)
             };
 
-            let audio_file = Subfile::new(decrypted_file, 0xa7);
+            let audio_file = Subfile::new(decrypted_file, offset);
 
             let result = if self.config.passthrough {
-                match PassthroughDecoder::new(audio_file) {
-                    Ok(result) => Ok(Box::new(result) as Decoder),
-                    Err(e) => Err(DecoderError::PassthroughDecoder(e.to_string())),
-                }
+                PassthroughDecoder::new(audio_file).map(|decoder| Box::new(decoder) as Decoder)
             } else {
-                match VorbisDecoder::new(audio_file) {
-                    Ok(result) => Ok(Box::new(result) as Decoder),
-                    Err(e) => Err(DecoderError::LewtonDecoder(e.to_string())),
-                }
+                debug!("Decoding {:?} with {}", format, decoder_name);
+                decoder_builder(Box::new(audio_file), format)
             };
 
             let mut decoder = match result {
@@ -881,11 +1580,12 @@ impl PlayerTrackLoader {
                 }
             };
 
-            let position_pcm = PlayerInternal::position_ms_to_pcm(position_ms);
+            let mut position_pcm = PlayerInternal::position_ms_to_pcm(position_ms);
 
             if position_pcm != 0 {
-                if let Err(e) = decoder.seek(position_pcm) {
-                    error!("PlayerTrackLoader load_track: {}", e);
+                match decoder.seek(position_pcm) {
+                    Ok(actual_position_pcm) => position_pcm = actual_position_pcm,
+                    Err(e) => error!("PlayerTrackLoader load_track: {}", e),
                 }
                 stream_loader_controller.set_stream_mode();
             }
@@ -893,6 +1593,8 @@ impl PlayerTrackLoader {
             info!("<{}> ({} ms) loaded", audio.name, audio.duration);
 
             return Some(PlayerLoadedTrackData {
+                audio_item: Some(Box::new(audio.clone())),
+                format: Some(format),
                 decoder,
                 normalisation_data,
                 stream_loader_controller,
@@ -902,6 +1604,241 @@ impl PlayerTrackLoader {
             });
         }
     }
+
+    // Waits for the local files directory to be indexed, if there is one.
+    async fn local_files(&self) -> Option<Arc<LocalFiles>> {
+        let mut local_files = self.local_files.clone();
+        loop {
+            let indexed = local_files.borrow().clone();
+            if indexed.is_some() {
+                return indexed;
+            }
+            if local_files.changed().await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    // The format of a file that isn't hosted by Spotify, going by what the server says it is or
+    // else by its name. It only serves as a hint to the decoder, which looks at the data itself.
+    fn external_file_format(content_type: Option<&str>, url: &str) -> FileFormat {
+        let content_type = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|content_type| content_type.trim().to_lowercase());
+        let extension = url
+            .split(&['?', '#'][..])
+            .next()
+            .and_then(|path| path.rsplit('.').next())
+            .map(|extension| extension.to_lowercase());
+
+        match (content_type.as_deref(), extension.as_deref()) {
+            (Some("audio/ogg"), _)
+            | (Some("audio/vorbis"), _)
+            | (Some("application/ogg"), _)
+            | (_, Some("ogg"))
+            | (_, Some("oga")) => FileFormat::OGG_VORBIS_160,
+            (Some("audio/aac"), _) | (Some("audio/aacp"), _) | (_, Some("aac")) => {
+                FileFormat::AAC_160
+            }
+            (Some("audio/mp4"), _)
+            | (Some("audio/x-m4a"), _)
+            | (Some("audio/m4a"), _)
+            | (_, Some("m4a"))
+            | (_, Some("mp4")) => FileFormat::MP4_128,
+            _ => FileFormat::MP3_160,
+        }
+    }
+
+    // Streams an episode from where it is hosted outside of Spotify, e.g. when it only exists as
+    // the enclosure of an RSS feed.
+    async fn load_external_track(
+        &self,
+        audio: &AudioItem,
+        url: &str,
+        position_ms: u32,
+        duration_ms: u32,
+    ) -> Option<PlayerLoadedTrackData> {
+        let audio_file = match HttpFile::open(self.session.http_client().clone(), url).await {
+            Ok(audio_file) => audio_file,
+            Err(e) => {
+                error!("Unable to open <{}>: {}", url, e);
+                return None;
+            }
+        };
+
+        let format = Self::external_file_format(audio_file.content_type(), url);
+        let bytes_per_second = self.stream_data_rate(format);
+        // This controls no download, so playback of the episode doesn't report buffering or
+        // download progress, the decoder just blocks until the data has arrived.
+        let stream_loader_controller = audio_file.get_stream_loader_controller();
+
+        let result = if self.config.passthrough {
+            PassthroughDecoder::new(audio_file).map(|decoder| Box::new(decoder) as Decoder)
+        } else {
+            match decoder::find(format) {
+                Some((decoder_name, decoder_builder)) => {
+                    debug!("Decoding {:?} with {}", format, decoder_name);
+                    decoder_builder(Box::new(audio_file), format)
+                }
+                None => {
+                    warn!("<{}> is not available in any supported format", audio.name);
+                    return None;
+                }
+            }
+        };
+
+        let mut decoder = match result {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                error!("Unable to read audio file: {}", e);
+                return None;
+            }
+        };
+
+        let mut position_pcm = PlayerInternal::position_ms_to_pcm(position_ms);
+        if position_pcm != 0 {
+            match decoder.seek(position_pcm) {
+                Ok(actual_position_pcm) => position_pcm = actual_position_pcm,
+                Err(e) => error!("PlayerTrackLoader load_external_track: {}", e),
+            }
+        }
+        info!(
+            "<{}> ({} ms) loaded from <{}>",
+            audio.name, audio.duration, url
+        );
+
+        Some(PlayerLoadedTrackData {
+            audio_item: Some(Box::new(audio.clone())),
+            format: None,
+            decoder,
+            normalisation_data: NormalisationData::default(),
+            stream_loader_controller,
+            bytes_per_second,
+            duration_ms,
+            stream_position_pcm: position_pcm,
+        })
+    }
+
+    // Plays the file of a `spotify:local` URI from the local files directory, like the desktop
+    // client does for the local files in a playlist.
+    async fn load_local_track(
+        &self,
+        spotify_id: SpotifyId,
+        uri: String,
+        position_ms: u32,
+    ) -> Option<PlayerLoadedTrackData> {
+        let local_files = self.local_files().await;
+        let local_file = match local_files
+            .as_ref()
+            .and_then(|local_files| local_files.resolve(&uri))
+        {
+            Some(local_file) => local_file,
+            None => {
+                warn!("<{}> is not among the local files", uri);
+                return None;
+            }
+        };
+
+        info!("Loading <{}> from {}", uri, local_file.path.display());
+
+        let audio_file = match File::open(&local_file.path) {
+            Ok(file) => AudioFile::Cached(file),
+            Err(e) => {
+                error!("Unable to open {}: {}", local_file.path.display(), e);
+                return None;
+            }
+        };
+        let stream_loader_controller = audio_file.get_stream_loader_controller();
+
+        let extension = local_file
+            .path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let result = if self.config.passthrough {
+            if extension == "ogg" || extension == "oga" {
+                PassthroughDecoder::new(audio_file).map(|decoder| Box::new(decoder) as Decoder)
+            } else {
+                warn!(
+                    "<{}> can't be passed through, it is not an Ogg file",
+                    local_file.path.display()
+                );
+                return None;
+            }
+        } else {
+            #[cfg(feature = "symphonia-decoder")]
+            let result = SymphoniaDecoder::with_extension(audio_file, &extension);
+            // Only Ogg files are indexed without symphonia.
+            #[cfg(not(feature = "symphonia-decoder"))]
+            let result = VorbisDecoder::new(audio_file);
+            result.map(|decoder| Box::new(decoder) as Decoder)
+        };
+
+        let mut decoder = match result {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                error!("Unable to read audio file: {}", e);
+                return None;
+            }
+        };
+
+        let mut position_pcm = PlayerInternal::position_ms_to_pcm(position_ms);
+        if position_pcm != 0 {
+            match decoder.seek(position_pcm) {
+                Ok(actual_position_pcm) => position_pcm = actual_position_pcm,
+                Err(e) => error!("PlayerTrackLoader load_local_track: {}", e),
+            }
+        }
+
+        // The URI tells the duration in whole seconds if the file doesn't.
+        let duration = local_file
+            .duration
+            .or_else(|| LocalUri::parse(&uri)?.duration)
+            .unwrap_or_default();
+        let duration_ms = duration.as_millis() as u32;
+        let bytes_per_second = match duration.as_secs() {
+            0 => self.stream_data_rate(FileFormat::OGG_VORBIS_320),
+            seconds => stream_loader_controller.len() / seconds as usize,
+        };
+
+        let audio = AudioItem {
+            id: spotify_id,
+            uri,
+            files: HashMap::new(),
+            name: local_file.title.clone(),
+            artists: local_file.artists.clone(),
+            album: local_file.album.clone(),
+            album_id: None,
+            duration: duration_ms as i32,
+            available: true,
+            alternatives: None,
+            covers: Vec::new(),
+            is_podcast: false,
+            external_url: None,
+        };
+        info!(
+            "<{}> ({} ms) loaded from {}",
+            audio.name,
+            duration_ms,
+            local_file.path.display()
+        );
+
+        Some(PlayerLoadedTrackData {
+            audio_item: Some(Box::new(audio)),
+            format: None,
+            decoder,
+            normalisation_data: local_file
+                .replay_gain
+                .map(NormalisationData::from)
+                .unwrap_or_default(),
+            stream_loader_controller,
+            bytes_per_second,
+            duration_ms,
+            stream_position_pcm: position_pcm,
+        })
+    }
 }
 
 impl Future for PlayerInternal {
@@ -929,6 +1866,18 @@ impl Future for PlayerInternal {
                 self.handle_command(cmd);
             }
 
+            if let Some(ref mut sink_recovery) = self.sink_recovery {
+                if sink_recovery.timer.as_mut().poll(cx).is_ready() {
+                    all_futures_completed_or_not_ready = false;
+                    self.retry_sink();
+                }
+            }
+
+            // When the time is up, handle_sleep_timer() below takes it from there.
+            if let Some(ref mut sleep_timer) = self.sleep_timer {
+                let _ = sleep_timer.poll_deadline(cx);
+            }
+
             // Handle loading of a new track to play
             if let PlayerState::Loading {
                 ref mut loader,
@@ -946,8 +1895,7 @@ impl Future for PlayerInternal {
                             start_playback,
                         );
                         if let PlayerState::Loading { .. } = self.state {
-                            error!("The state wasn't changed by start_playback()");
-                            exit(1);
+                            self.handle_player_error(PlayerError::InvalidState("start_playback"));
                         }
                     }
                     Poll::Ready(Err(e)) => {
@@ -1000,9 +1948,24 @@ impl Future for PlayerInternal {
                 }
             }
 
-            if self.state.is_playing() {
-                self.ensure_sink_running();
+            if self.state.is_playing() && !self.ensure_sink_running() {
+                self.handle_pause();
+            }
+
+            if self.state.is_playing() && self.sink_recovery.is_none() {
+                // Reading on would otherwise block in the decoder until the data has arrived. It
+                // reads a page at a time, which may take more than the next few bytes.
+                if self.wait_for_data(OGG_PAGE_MAX_SIZE) {
+                    if let Some(ref mut auto_bitrate) = self.auto_bitrate {
+                        auto_bitrate.underrun();
+                    }
+                }
+            }
 
+            // Playback holds still while the sink waits to be restarted.
+            if self.state.is_playing() && self.sink_recovery.is_none() {
+                let speed = self.speed;
+                let latency_ms = self.sink_latency_ms();
                 if let PlayerState::Playing {
                     track_id,
                     play_request_id,
@@ -1022,8 +1985,11 @@ impl Future for PlayerInternal {
                                         Ok(samples) => {
                                             *stream_position_pcm +=
                                                 (samples.len() / NUM_CHANNELS as usize) as u64;
+                                            // What is heard lags behind what is decoded by
+                                            // the audio still buffered in the sink.
                                             let stream_position_millis =
-                                                Self::position_pcm_to_ms(*stream_position_pcm);
+                                                Self::position_pcm_to_ms(*stream_position_pcm)
+                                                    .saturating_sub(latency_ms);
 
                                             let notify_about_position =
                                                 match *reported_nominal_start_time {
@@ -1031,8 +1997,12 @@ impl Future for PlayerInternal {
                                                     Some(reported_nominal_start_time) => {
                                                         // only notify if we're behind. If we're ahead it's probably due to a buffer of the backend and we're actually in time.
                                                         let lag = (Instant::now()
-                                                            - reported_nominal_start_time)
+                                                            .saturating_duration_since(
+                                                                reported_nominal_start_time,
+                                                            )
                                                             .as_millis()
+                                                            as f64
+                                                            * speed)
                                                             as i64
                                                             - stream_position_millis as i64;
                                                         lag > Duration::from_secs(1).as_millis()
@@ -1042,8 +2012,10 @@ impl Future for PlayerInternal {
                                             if notify_about_position {
                                                 *reported_nominal_start_time = Some(
                                                     Instant::now()
-                                                        - Duration::from_millis(
-                                                            stream_position_millis as u64,
+                                                        - Duration::from_secs_f64(
+                                                            stream_position_millis as f64
+                                                                / 1000.0
+                                                                / speed,
                                                         ),
                                                 );
                                                 self.send_event(PlayerEvent::Playing {
@@ -1055,11 +2027,11 @@ impl Future for PlayerInternal {
                                             }
                                         }
                                         Err(e) => {
-                                            warn!("Skipping to next track, unable to decode samples for track <{:?}>: {:?}", track_id, e);
-                                            self.send_event(PlayerEvent::EndOfTrack {
+                                            self.handle_player_error(PlayerError::Decoder {
                                                 track_id,
-                                                play_request_id,
-                                            })
+                                                error: e.to_string(),
+                                            });
+                                            self.handle_end_of_track();
                                         }
                                     }
                                 }
@@ -1071,16 +2043,15 @@ impl Future for PlayerInternal {
                             self.handle_packet(packet, normalisation_factor);
                         }
                         Err(e) => {
-                            warn!("Skipping to next track, unable to get next packet for track <{:?}>: {:?}", track_id, e);
-                            self.send_event(PlayerEvent::EndOfTrack {
+                            self.handle_player_error(PlayerError::Decoder {
                                 track_id,
-                                play_request_id,
-                            })
+                                error: e.to_string(),
+                            });
+                            self.handle_end_of_track();
                         }
                     }
                 } else {
-                    error!("PlayerInternal poll: Invalid PlayerState");
-                    exit(1);
+                    self.handle_player_error(PlayerError::InvalidState("poll"));
                 };
             }
 
@@ -1116,11 +2087,18 @@ impl Future for PlayerInternal {
                 }
             }
 
+            self.report_download_progress(false);
+
+            self.handle_crossfade_trigger();
+            self.handle_sleep_timer();
+
             if self.session.is_invalid() {
                 return Poll::Ready(());
             }
 
-            if (!self.state.is_playing()) && all_futures_completed_or_not_ready {
+            if (!self.state.is_playing() || self.sink_recovery.is_some())
+                && all_futures_completed_or_not_ready
+            {
                 return Poll::Pending;
             }
         }
@@ -1136,27 +2114,218 @@ impl PlayerInternal {
         (position_ms as f64 * PAGES_PER_MS) as u64
     }
 
-    fn ensure_sink_running(&mut self) {
+    fn handle_crossfade_trigger(&mut self) {
+        if self.config.crossfade == Duration::default()
+            || !self.config.gapless
+            || self.config.passthrough
+        {
+            return;
+        }
+
+        if let PlayerState::Playing {
+            track_id,
+            play_request_id,
+            duration_ms,
+            stream_position_pcm,
+            ..
+        } = self.state
+        {
+            if self.crossfade_pending == Some(play_request_id) {
+                return;
+            }
+
+            // The track fades out on its own before the sleep timer pauses playback.
+            if self.sleep_timer_ends_with_track() {
+                return;
+            }
+
+            // Only crossfade into a track that is ready to play right away.
+            let next_track_ready = matches!(
+                self.preload,
+                PlayerPreload::Ready { track_id: next_track_id, .. } if next_track_id != track_id
+            );
+
+            let remaining_ms =
+                duration_ms as i64 - Self::position_pcm_to_ms(stream_position_pcm) as i64;
+
+            if next_track_ready && remaining_ms <= self.config.crossfade.as_millis() as i64 {
+                // Let Spirc advance to the next track early. The remainder of this track
+                // is faded out when the next track is loaded.
+                debug!("Starting crossfade with {} ms remaining", remaining_ms);
+                self.crossfade_pending = Some(play_request_id);
+                self.send_event(PlayerEvent::EndOfTrack {
+                    track_id,
+                    play_request_id,
+                });
+            }
+        }
+    }
+
+    fn start_crossfade(&mut self, play_request_id: u64) {
+        if let PlayerState::Playing {
+            play_request_id: current_play_request_id,
+            ..
+        } = self.state
+        {
+            if current_play_request_id != play_request_id {
+                return;
+            }
+
+            if let PlayerState::Playing {
+                decoder,
+                normalisation_factor,
+                duration_ms,
+                stream_position_pcm,
+                ..
+            } = mem::replace(&mut self.state, PlayerState::Stopped)
+            {
+                let remaining_pcm =
+                    Self::position_ms_to_pcm(duration_ms).saturating_sub(stream_position_pcm);
+                let crossfade_pcm =
+                    Self::position_ms_to_pcm(self.config.crossfade.as_millis() as u32);
+
+                self.crossfade = Some(PlayerCrossfade {
+                    decoder,
+                    normalisation_factor,
+                    buffer: Vec::new(),
+                    buffer_position: 0,
+                    elapsed_frames: 0,
+                    duration_frames: remaining_pcm.min(crossfade_pcm),
+                });
+            }
+        }
+    }
+
+    // Returns false if the sink could not be started, not even by recovering it.
+    fn ensure_sink_running(&mut self) -> bool {
         if self.sink_status != SinkStatus::Running {
             trace!("== Starting sink ==");
             if let Some(callback) = &mut self.sink_event_callback {
                 callback(SinkStatus::Running);
             }
-            match self.sink.start() {
-                Ok(()) => self.sink_status = SinkStatus::Running,
-                Err(e) => {
-                    error!("{}", e);
-                    exit(1);
+            if let Err(e) = self.output.start() {
+                if !self.recover_sink(e) {
+                    self.close_failed_sink();
+                    return false;
                 }
             }
+            self.sink_status = SinkStatus::Running;
+        }
+        true
+    }
+
+    // Tries to get the sink going again after it failed, first by restarting it a bounded number
+    // of times with a doubling backoff, then by switching to the fallback sink if there is one.
+    // The restarts are left to `poll`, so that commands are still handled while playback waits
+    // for the sink. Returns false if there is nothing left to try.
+    fn recover_sink(&mut self, error: SinkError) -> bool {
+        error!("{}", error);
+        if self.sink_recovery.is_some() {
+            // A restart is already on its way.
+            return true;
+        }
+
+        self.send_event(PlayerEvent::OutputLost {
+            error: error.to_string(),
+        });
+
+        // Whatever is left of the failed sink may still hold on to the device.
+        let _ = self.output.stop();
+
+        if self.config.sink_retries > 0 {
+            self.schedule_sink_retry(1, self.config.sink_retry_backoff);
+            true
+        } else {
+            self.switch_to_fallback_sink()
+        }
+    }
+
+    fn schedule_sink_retry(&mut self, retry: u32, backoff: Duration) {
+        warn!(
+            "Restarting the sink in {:?} (retry {} of {})",
+            backoff, retry, self.config.sink_retries
+        );
+        self.sink_recovery = Some(PlayerSinkRecovery {
+            retry,
+            backoff,
+            timer: Box::pin(tokio::time::sleep(backoff)),
+        });
+    }
+
+    fn retry_sink(&mut self) {
+        let sink_recovery = match self.sink_recovery.take() {
+            Some(sink_recovery) => sink_recovery,
+            None => return,
+        };
+
+        match self.output.start() {
+            Ok(()) => {
+                info!("The sink is working again");
+                self.handle_sink_reconnected(self.on_fallback_sink);
+            }
+            Err(e) => {
+                warn!("{}", e);
+                if sink_recovery.retry < self.config.sink_retries {
+                    self.schedule_sink_retry(sink_recovery.retry + 1, sink_recovery.backoff * 2);
+                } else if !self.switch_to_fallback_sink() {
+                    self.close_failed_sink();
+                    self.handle_pause();
+                }
+            }
+        }
+    }
+
+    fn switch_to_fallback_sink(&mut self) -> bool {
+        if let Some((sink_builder, device, format)) = self.fallback_sink.take() {
+            warn!("Switching to the fallback sink");
+            let sample_rate = self.config.sample_rate;
+            let result = self.output.set_sink(
+                Box::new(move || sink_builder(device, format, sample_rate)),
+                true,
+            );
+            self.on_fallback_sink = true;
+
+            match result {
+                Ok(()) => {
+                    self.handle_sink_reconnected(true);
+                    return true;
+                }
+                Err(e) => error!("{}", e),
+            }
+        }
+
+        false
+    }
+
+    fn handle_sink_reconnected(&mut self, fallback: bool) {
+        self.send_event(PlayerEvent::OutputReconnected { fallback });
+
+        // Playback held still while the sink was gone, report the position again.
+        if let PlayerState::Playing {
+            ref mut reported_nominal_start_time,
+            ..
+        } = self.state
+        {
+            *reported_nominal_start_time = None;
+        }
+    }
+
+    fn close_failed_sink(&mut self) {
+        error!("Unable to recover the sink, playback is paused");
+        self.sink_status = SinkStatus::Closed;
+        if let Some(callback) = &mut self.sink_event_callback {
+            callback(SinkStatus::Closed);
         }
     }
 
     fn ensure_sink_stopped(&mut self, temporarily: bool) {
+        // The sink is started afresh when playback goes on.
+        self.sink_recovery = None;
+
         match self.sink_status {
             SinkStatus::Running => {
                 trace!("== Stopping sink ==");
-                match self.sink.stop() {
+                match self.output.stop() {
                     Ok(()) => {
                         self.sink_status = if temporarily {
                             SinkStatus::TemporarilyClosed
@@ -1168,8 +2337,13 @@ impl PlayerInternal {
                         }
                     }
                     Err(e) => {
-                        error!("{}", e);
-                        exit(1);
+                        // There is nothing left to stop, the next start will tell whether
+                        // the sink is still usable.
+                        warn!("{}", e);
+                        self.sink_status = SinkStatus::Closed;
+                        if let Some(callback) = &mut self.sink_event_callback {
+                            callback(SinkStatus::Closed);
+                        }
                     }
                 }
             }
@@ -1185,6 +2359,261 @@ impl PlayerInternal {
         }
     }
 
+    fn fade_frames(&self) -> Option<u64> {
+        if self.config.fade == Duration::default() || self.config.passthrough {
+            return None;
+        }
+        Some(
+            (self.config.fade.as_secs_f64() * self.config.sample_rate as f64)
+                .round()
+                .max(1.0) as u64,
+        )
+    }
+
+    // Ramps up the audio that is played from here on.
+    fn fade_in(&mut self) {
+        if let Some(duration_frames) = self.fade_frames() {
+            self.fade = Some(PlayerFade::new(true, duration_frames, self.fade.as_ref()));
+        }
+    }
+
+    // Plays on for the duration of the fade while ramping the audio down, so that playback can
+    // stop where it ends without a click.
+    fn fade_out(&mut self) {
+        let duration_frames = match self.fade_frames() {
+            Some(duration_frames)
+                if self.state.is_playing() && self.sink_status == SinkStatus::Running =>
+            {
+                duration_frames
+            }
+            _ => return,
+        };
+        self.fade = Some(PlayerFade::new(false, duration_frames, self.fade.as_ref()));
+
+        while matches!(self.fade, Some(ref fade) if !fade.is_finished()) {
+            let (packet, normalisation_factor) = match self.state {
+                PlayerState::Playing {
+                    ref mut decoder,
+                    ref mut stream_position_pcm,
+                    normalisation_factor,
+                    ..
+                } => match decoder.next_packet() {
+                    Ok(Some(packet)) => {
+                        if let Ok(samples) = packet.samples() {
+                            *stream_position_pcm += (samples.len() / NUM_CHANNELS as usize) as u64;
+                        }
+                        (packet, normalisation_factor)
+                    }
+                    // The end of the track, or what went wrong, is dealt with when playback
+                    // carries on.
+                    _ => break,
+                },
+                // The sink failed and paused playback.
+                _ => break,
+            };
+            self.handle_packet(Some(packet), normalisation_factor);
+        }
+
+        self.fade = None;
+    }
+
+    // Drops the audio that waits in the output to be played, so that pausing, seeking or stopping
+    // is heard right away. Decoding goes back to where the first of it starts, so that playback
+    // carries on from what was heard.
+    fn flush_output(&mut self) {
+        // There is at most a packet of passthrough data in the output, and no position for it.
+        if self.config.passthrough {
+            return;
+        }
+
+        let flushed = self.output.flush();
+
+        if let (
+            Some(flushed),
+            PlayerState::Playing {
+                play_request_id,
+                ref mut decoder,
+                ref mut stream_position_pcm,
+                ref stream_loader_controller,
+                ..
+            },
+        ) = (flushed, &mut self.state)
+        {
+            if flushed.play_request_id != *play_request_id {
+                return;
+            }
+
+            // While crossfading, the dropped audio has the end of the track before mixed in,
+            // which can't be decoded again in step with this one. The rest of it is dropped too,
+            // and this track goes on by itself from where the dropped audio started.
+            if self.crossfade.take().is_some() {
+                debug!("Dropping the rest of the crossfade along with the buffered audio");
+            }
+
+            stream_loader_controller.set_random_access_mode();
+            match decoder.seek(flushed.position_pcm) {
+                Ok(actual_position_pcm) => *stream_position_pcm = actual_position_pcm,
+                Err(e) => warn!("Unable to decode the dropped audio again: {}", e),
+            }
+            stream_loader_controller.set_stream_mode();
+
+            if let Some(ref mut time_stretcher) = self.time_stretcher {
+                time_stretcher.reset();
+            }
+            if let Some(ref mut resampler) = self.resampler {
+                resampler.reset();
+            }
+        }
+    }
+
+    fn handle_command_set_sleep_timer(&mut self, timer: Option<SleepTimer>) {
+        let previous = mem::replace(&mut self.sleep_timer, timer.map(PlayerSleepTimer::new));
+
+        // A tail that is cut short fades back in as it went.
+        if let Some(fade) = previous
+            .as_ref()
+            .and_then(|previous| previous.fade.as_ref())
+        {
+            if self.state.is_playing() {
+                self.fade = Some(PlayerFade::new(true, fade.duration_frames, Some(fade)));
+            }
+        }
+
+        match (previous, timer) {
+            (None, Some(timer)) => {
+                info!("Sleep timer set: {:?}", timer);
+                self.send_event(PlayerEvent::SleepTimerArmed { timer });
+            }
+            (Some(_), timer) => {
+                info!("Sleep timer changed: {:?}", timer);
+                self.send_event(PlayerEvent::SleepTimerChanged { timer });
+            }
+            (None, None) => (),
+        }
+    }
+
+    // Whether the sleep timer pauses playback once the track that is playing has ended.
+    fn sleep_timer_ends_with_track(&self) -> bool {
+        match self.sleep_timer {
+            Some(PlayerSleepTimer {
+                timer: SleepTimer::EndOfTrack,
+                ..
+            }) => true,
+            // This is only known once the next track has been preloaded.
+            Some(PlayerSleepTimer {
+                timer: SleepTimer::EndOfAlbum,
+                ..
+            }) => matches!(
+                self.preload,
+                PlayerPreload::Ready { ref loaded_track, .. }
+                    if loaded_track.audio_item.as_ref().map_or(false, |audio_item| {
+                        self.is_other_album(audio_item)
+                    })
+            ),
+            _ => false,
+        }
+    }
+
+    // Whether a track is from another album than the one that is playing. Albums of the same name
+    // are told apart by their ids, but local files only have the name to go by.
+    fn is_other_album(&self, audio_item: &AudioItem) -> bool {
+        match (audio_item.album_id, self.album_id) {
+            (Some(album_id), Some(playing_album_id)) => album_id != playing_album_id,
+            (None, None) => audio_item.album != self.stream_properties.album,
+            _ => true,
+        }
+    }
+
+    // Fades out the tail before the sleep timer pauses playback, and pauses it when the time is
+    // up. Timers that wait for the end of a track fire when the next one starts instead, see
+    // start_playback().
+    fn handle_sleep_timer(&mut self) {
+        let (deadline, tail_finished) = match self.sleep_timer {
+            Some(ref sleep_timer) => (
+                sleep_timer.deadline,
+                matches!(sleep_timer.fade, Some(ref fade) if fade.is_finished()),
+            ),
+            None => return,
+        };
+
+        if let Some(deadline) = deadline {
+            if tail_finished || Instant::now() >= deadline {
+                // Between tracks, the next one is loaded paused instead.
+                if !matches!(
+                    self.state,
+                    PlayerState::Loading { .. } | PlayerState::EndOfTrack { .. }
+                ) {
+                    self.end_sleep_timer();
+                }
+                return;
+            }
+        }
+
+        // how long it is until the timer pauses playback, as far as it does so while playing
+        let remaining = match self.state {
+            PlayerState::Playing {
+                duration_ms,
+                stream_position_pcm,
+                ..
+            } => match deadline {
+                Some(deadline) => Some(deadline.saturating_duration_since(Instant::now())),
+                None if self.sleep_timer_ends_with_track() => Some(
+                    Duration::from_millis(
+                        duration_ms.saturating_sub(Self::position_pcm_to_ms(stream_position_pcm))
+                            as u64,
+                    )
+                    .div_f64(self.speed),
+                ),
+                None => None,
+            },
+            _ => None,
+        };
+
+        let tail = self.config.sleep_timer_fade;
+        let sample_rate = self.config.sample_rate as f64;
+        let playing = self.state.is_playing();
+        if let Some(ref mut sleep_timer) = self.sleep_timer {
+            match remaining {
+                Some(remaining) if remaining < tail && !self.config.passthrough => {
+                    if sleep_timer.fade.is_none() {
+                        debug!("Fading out for the sleep timer");
+                        let duration_frames = (tail.as_secs_f64() * sample_rate) as u64;
+                        let remaining_frames = (remaining.as_secs_f64() * sample_rate) as u64;
+                        sleep_timer.fade = Some(PlayerFade {
+                            fading_in: false,
+                            elapsed_frames: duration_frames.saturating_sub(remaining_frames),
+                            duration_frames,
+                        });
+                    }
+                }
+                // The tail is picked up again where it is when playback is resumed, and fades
+                // back in if there is no tail anymore (e.g. after seeking back).
+                _ => {
+                    if let Some(fade) = sleep_timer.fade.take() {
+                        if playing {
+                            self.fade =
+                                Some(PlayerFade::new(true, fade.duration_frames, Some(&fade)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Tells that the sleep timer fired, pauses playback if it is playing, and disarms the timer.
+    fn end_sleep_timer(&mut self) {
+        if let Some(ref sleep_timer) = self.sleep_timer {
+            info!("Sleep timer fired");
+            let timer = sleep_timer.timer;
+            self.send_event(PlayerEvent::SleepTimerFired { timer });
+            // The tail is still applied to what plays until then.
+            if self.state.is_playing() {
+                self.handle_pause();
+            }
+            self.sleep_timer = None;
+        }
+    }
+
     fn handle_player_stop(&mut self) {
         match self.state {
             PlayerState::Playing {
@@ -1207,6 +2636,24 @@ impl PlayerInternal {
                 play_request_id,
                 ..
             } => {
+                // There is nothing left to play for a timer that waits for the end of it.
+                if matches!(self.state, PlayerState::EndOfTrack { .. })
+                    && matches!(self.sleep_timer, Some(ref sleep_timer) if sleep_timer.deadline.is_none())
+                {
+                    self.end_sleep_timer();
+                }
+                if self.state.is_playing() {
+                    self.flush_output();
+                }
+                self.fade_out();
+                self.crossfade = None;
+                self.crossfade_pending = None;
+                if let Some(ref mut time_stretcher) = self.time_stretcher {
+                    time_stretcher.reset();
+                }
+                if let Some(ref mut resampler) = self.resampler {
+                    resampler.reset();
+                }
                 self.ensure_sink_stopped(false);
                 self.send_event(PlayerEvent::Stopped {
                     track_id,
@@ -1216,8 +2663,7 @@ impl PlayerInternal {
             }
             PlayerState::Stopped => (),
             PlayerState::Invalid => {
-                error!("PlayerInternal handle_player_stop: invalid state");
-                exit(1);
+                self.handle_player_error(PlayerError::InvalidState("handle_player_stop"))
             }
         }
     }
@@ -1231,22 +2677,37 @@ impl PlayerInternal {
             ..
         } = self.state
         {
-            self.state.paused_to_playing();
+            if let Err(e) = self.state.paused_to_playing() {
+                self.handle_player_error(e);
+                return;
+            }
+            self.fade_in();
 
-            let position_ms = Self::position_pcm_to_ms(stream_position_pcm);
+            let position_ms = self.heard_position_ms(Self::position_pcm_to_ms(stream_position_pcm));
             self.send_event(PlayerEvent::Playing {
                 track_id,
                 play_request_id,
                 position_ms,
                 duration_ms,
             });
-            self.ensure_sink_running();
+            if !self.ensure_sink_running() {
+                self.handle_pause();
+            }
         } else {
             warn!("Player::play called from invalid state");
         }
     }
 
     fn handle_pause(&mut self) {
+        // The sink may fail while the fade plays, and pause playback on its own.
+        if self.state.is_playing() {
+            self.flush_output();
+            self.fade_out();
+            if !self.state.is_playing() {
+                return;
+            }
+        }
+
         if let PlayerState::Playing {
             track_id,
             play_request_id,
@@ -1255,10 +2716,13 @@ impl PlayerInternal {
             ..
         } = self.state
         {
-            self.state.playing_to_paused();
+            if let Err(e) = self.state.playing_to_paused() {
+                self.handle_player_error(e);
+                return;
+            }
 
             self.ensure_sink_stopped(false);
-            let position_ms = Self::position_pcm_to_ms(stream_position_pcm);
+            let position_ms = self.heard_position_ms(Self::position_pcm_to_ms(stream_position_pcm));
             self.send_event(PlayerEvent::Paused {
                 track_id,
                 play_request_id,
@@ -1274,6 +2738,23 @@ impl PlayerInternal {
         match packet {
             Some(mut packet) => {
                 if !packet.is_empty() {
+                    // The position has already moved on past the packet.
+                    let position = match (&self.state, &packet) {
+                        (
+                            PlayerState::Playing {
+                                play_request_id,
+                                stream_position_pcm,
+                                ..
+                            },
+                            AudioPacket::Samples(data),
+                        ) => Some(OutputPosition {
+                            play_request_id: *play_request_id,
+                            position_pcm: stream_position_pcm
+                                .saturating_sub((data.len() / NUM_CHANNELS as usize) as u64),
+                        }),
+                        _ => None,
+                    };
+
                     if let AudioPacket::Samples(ref mut data) = packet {
                         if self.config.normalisation
                             && !(f64::abs(normalisation_factor - 1.0) <= f64::EPSILON
@@ -1379,36 +2860,120 @@ impl PlayerInternal {
                             }
                         }
 
-                        if let Some(ref editor) = self.audio_filter {
-                            editor.modify_stream(data)
+                        if let Some(ref mut crossfade) = self.crossfade {
+                            crossfade.mix(data, self.config.crossfade_curve);
+                            if crossfade.is_finished() {
+                                debug!("Crossfade finished");
+                                self.crossfade = None;
+                            }
+                        }
+
+                        if let Some(ref mut time_stretcher) = self.time_stretcher {
+                            *data = time_stretcher.process(data);
                         }
+
+                        self.process_tempo_adjusted(data);
                     }
 
-                    if let Err(e) = self.sink.write(packet, &mut self.converter) {
-                        error!("{}", e);
-                        exit(1);
+                    // The packet that failed is dropped, playback carries on with the next one.
+                    if let Err(e) = self.output.write(packet, position) {
+                        if !self.recover_sink(e) {
+                            self.close_failed_sink();
+                            self.handle_pause();
+                        }
                     }
                 }
             }
 
-            None => {
-                self.state.playing_to_end_of_track();
-                if let PlayerState::EndOfTrack {
+            None => self.handle_end_of_track(),
+        }
+    }
+
+    // The stages that come after the tempo has been adjusted, up to the output.
+    fn process_tempo_adjusted(&mut self, data: &mut Vec<f64>) {
+        // Everything from here on runs at the output sample rate.
+        if let Some(ref mut resampler) = self.resampler {
+            *data = resampler.resample(data);
+        }
+
+        self.filter_chain.modify_stream(data);
+
+        // Last, so that the ramp goes from silence to whatever the gain of normalisation makes
+        // of the audio, or back. The volume of the mixer is applied by the output.
+        if let Some(ref mut fade) = self.fade {
+            fade.apply(data);
+            if fade.fading_in && fade.is_finished() {
+                self.fade = None;
+            }
+        }
+
+        if let Some(ref mut fade) = self
+            .sleep_timer
+            .as_mut()
+            .and_then(|timer| timer.fade.as_mut())
+        {
+            fade.apply(data);
+        }
+    }
+
+    fn handle_end_of_track(&mut self) {
+        if let Err(e) = self.state.playing_to_end_of_track() {
+            self.handle_player_error(e);
+            return;
+        }
+
+        if let PlayerState::EndOfTrack {
+            track_id,
+            play_request_id,
+            ..
+        } = self.state
+        {
+            // Spirc was already told when the crossfade was triggered.
+            if self.crossfade_pending.take() != Some(play_request_id) {
+                self.send_event(PlayerEvent::EndOfTrack {
+                    track_id,
+                    play_request_id,
+                })
+            }
+        }
+    }
+
+    // Reports an error to the event listeners instead of taking the process down with it. An
+    // invalid state leaves nothing that could be played, so playback is stopped.
+    fn handle_player_error(&mut self, error: PlayerError) {
+        error!("{}", error);
+
+        if let PlayerError::InvalidState(_) = error {
+            self.ensure_sink_stopped(false);
+            match mem::replace(&mut self.state, PlayerState::Stopped) {
+                PlayerState::Loading {
+                    track_id,
+                    play_request_id,
+                    ..
+                }
+                | PlayerState::Playing {
+                    track_id,
+                    play_request_id,
+                    ..
+                }
+                | PlayerState::Paused {
                     track_id,
                     play_request_id,
                     ..
-                } = self.state
-                {
-                    self.send_event(PlayerEvent::EndOfTrack {
-                        track_id,
-                        play_request_id,
-                    })
-                } else {
-                    error!("PlayerInternal handle_packet: Invalid PlayerState");
-                    exit(1);
                 }
+                | PlayerState::EndOfTrack {
+                    track_id,
+                    play_request_id,
+                    ..
+                } => self.send_event(PlayerEvent::Stopped {
+                    track_id,
+                    play_request_id,
+                }),
+                PlayerState::Stopped | PlayerState::Invalid => (),
             }
         }
+
+        self.send_event(PlayerEvent::Error { error });
     }
 
     fn reset_limiter(&mut self) {
@@ -1427,7 +2992,8 @@ impl PlayerInternal {
         loaded_track: PlayerLoadedTrackData,
         start_playback: bool,
     ) {
-        let position_ms = Self::position_pcm_to_ms(loaded_track.stream_position_pcm);
+        let position_ms =
+            self.heard_position_ms(Self::position_pcm_to_ms(loaded_track.stream_position_pcm));
 
         let mut config = self.config.clone();
         if config.normalisation_type == NormalisationType::Auto {
@@ -1440,8 +3006,51 @@ impl PlayerInternal {
         let normalisation_factor =
             NormalisationData::get_factor(&config, loaded_track.normalisation_data);
 
-        if start_playback {
-            self.ensure_sink_running();
+        // The sleep timer pauses playback when a track starts rather than when the last one
+        // ends, so that it carries on with the next track when it is resumed.
+        let follows_ended_track = mem::take(&mut self.follows_ended_track);
+        let other_album = loaded_track
+            .audio_item
+            .as_ref()
+            .map_or(false, |audio_item| self.is_other_album(audio_item));
+        let sleep_timer_fired = self.sleep_timer.as_ref().map_or(false, |sleep_timer| {
+            sleep_timer.fires_before_track(follows_ended_track, other_album)
+        });
+        if sleep_timer_fired {
+            self.crossfade = None;
+            self.end_sleep_timer();
+        }
+        let start_playback = start_playback && !sleep_timer_fired;
+
+        if let Some(audio_item) = &loaded_track.audio_item {
+            self.album_id = audio_item.album_id;
+            self.stream_properties = StreamProperties {
+                uri: audio_item.uri.clone(),
+                title: audio_item.name.clone(),
+                artists: audio_item.artists.clone(),
+                album: audio_item.album.clone(),
+                duration_ms: loaded_track.duration_ms,
+                ..Default::default()
+            };
+            self.send_event(PlayerEvent::TrackChanged {
+                play_request_id,
+                audio_item: audio_item.clone(),
+            });
+        }
+
+        if let Some(format) = loaded_track.format {
+            self.send_event(PlayerEvent::FormatSelected {
+                play_request_id,
+                track_id,
+                format,
+            });
+        }
+
+        // Without a working sink, the track is loaded paused.
+        if start_playback && self.ensure_sink_running() {
+            if loaded_track.stream_position_pcm > 0 {
+                self.fade_in();
+            }
 
             self.send_event(PlayerEvent::Playing {
                 track_id,
@@ -1460,9 +3069,7 @@ impl PlayerInternal {
                 duration_ms: loaded_track.duration_ms,
                 bytes_per_second: loaded_track.bytes_per_second,
                 stream_position_pcm: loaded_track.stream_position_pcm,
-                reported_nominal_start_time: Some(
-                    Instant::now() - Duration::from_millis(position_ms as u64),
-                ),
+                reported_nominal_start_time: Some(self.nominal_start_time(position_ms)),
                 suggested_to_preload_next_track: false,
             };
         } else {
@@ -1493,13 +3100,34 @@ impl PlayerInternal {
     fn handle_command_load(
         &mut self,
         track_id: SpotifyId,
+        local_uri: Option<String>,
         play_request_id: u64,
         play: bool,
         position_ms: u32,
     ) {
+        // A crossfade can only continue into the track that was preloaded for it.
+        self.crossfade = None;
+        let crossfade_pending = self.crossfade_pending.take();
+
+        self.follows_ended_track =
+            crossfade_pending.is_some() || matches!(self.state, PlayerState::EndOfTrack { .. });
+
+        // Only the end of a track that played to it is still to be heard.
+        if !self.follows_ended_track {
+            self.output.flush();
+        }
+
         if !self.config.gapless {
             self.ensure_sink_stopped(play);
         }
+
+        // The filter tail of the last track only belongs in front of the next one if it joins
+        // on to it without a gap.
+        if !self.follows_ended_track || !self.config.gapless {
+            if let Some(ref mut resampler) = self.resampler {
+                resampler.reset();
+            }
+        }
         // emit the correct player event
         match self.state {
             PlayerState::Playing {
@@ -1527,8 +3155,8 @@ impl PlayerInternal {
                 position_ms,
             }),
             PlayerState::Invalid { .. } => {
-                error!("PlayerInternal handle_command_load: invalid state");
-                exit(1);
+                self.handle_player_error(PlayerError::InvalidState("handle_command_load"));
+                return;
             }
         }
 
@@ -1546,8 +3174,8 @@ impl PlayerInternal {
                 let mut loaded_track = match mem::replace(&mut self.state, PlayerState::Invalid) {
                     PlayerState::EndOfTrack { loaded_track, .. } => loaded_track,
                     _ => {
-                        error!("PlayerInternal handle_command_load: Invalid PlayerState");
-                        exit(1);
+                        self.handle_player_error(PlayerError::InvalidState("handle_command_load"));
+                        return;
                     }
                 };
 
@@ -1557,18 +3185,21 @@ impl PlayerInternal {
                     loaded_track
                         .stream_loader_controller
                         .set_random_access_mode();
-                    if let Err(e) = loaded_track.decoder.seek(position_pcm) {
-                        // This may be blocking.
-                        error!("PlayerInternal handle_command_load: {}", e);
-                    }
+                    // This may be blocking.
+                    loaded_track.stream_position_pcm = match loaded_track.decoder.seek(position_pcm)
+                    {
+                        Ok(actual_position_pcm) => actual_position_pcm,
+                        Err(e) => {
+                            error!("PlayerInternal handle_command_load: {}", e);
+                            position_pcm
+                        }
+                    };
                     loaded_track.stream_loader_controller.set_stream_mode();
-                    loaded_track.stream_position_pcm = position_pcm;
                 }
                 self.preload = PlayerPreload::None;
                 self.start_playback(track_id, play_request_id, loaded_track, play);
                 if let PlayerState::Invalid = self.state {
-                    error!("start_playback() hasn't set a valid player state.");
-                    exit(1);
+                    self.handle_player_error(PlayerError::InvalidState("start_playback"));
                 }
                 return;
             }
@@ -1596,12 +3227,15 @@ impl PlayerInternal {
 
                 if position_pcm != *stream_position_pcm {
                     stream_loader_controller.set_random_access_mode();
-                    if let Err(e) = decoder.seek(position_pcm) {
-                        // This may be blocking.
-                        error!("PlayerInternal handle_command_load: {}", e);
-                    }
+                    // This may be blocking.
+                    *stream_position_pcm = match decoder.seek(position_pcm) {
+                        Ok(actual_position_pcm) => actual_position_pcm,
+                        Err(e) => {
+                            error!("PlayerInternal handle_command_load: {}", e);
+                            position_pcm
+                        }
+                    };
                     stream_loader_controller.set_stream_mode();
-                    *stream_position_pcm = position_pcm;
                 }
 
                 // Move the info from the current state into a PlayerLoadedTrackData so we can use
@@ -1628,6 +3262,8 @@ impl PlayerInternal {
                 } = old_state
                 {
                     let loaded_track = PlayerLoadedTrackData {
+                        audio_item: None,
+                        format: None,
                         decoder,
                         normalisation_data,
                         stream_loader_controller,
@@ -1640,14 +3276,13 @@ impl PlayerInternal {
                     self.start_playback(track_id, play_request_id, loaded_track, play);
 
                     if let PlayerState::Invalid = self.state {
-                        error!("start_playback() hasn't set a valid player state.");
-                        exit(1);
+                        self.handle_player_error(PlayerError::InvalidState("start_playback"));
                     }
 
                     return;
                 } else {
-                    error!("PlayerInternal handle_command_load: Invalid PlayerState");
-                    exit(1);
+                    self.handle_player_error(PlayerError::InvalidState("handle_command_load"));
+                    return;
                 }
             }
         }
@@ -1671,17 +3306,25 @@ impl PlayerInternal {
                         loaded_track
                             .stream_loader_controller
                             .set_random_access_mode();
-                        if let Err(e) = loaded_track.decoder.seek(position_pcm) {
-                            // This may be blocking
-                            error!("PlayerInternal handle_command_load: {}", e);
-                        }
+                        // This may be blocking
+                        loaded_track.stream_position_pcm =
+                            match loaded_track.decoder.seek(position_pcm) {
+                                Ok(actual_position_pcm) => actual_position_pcm,
+                                Err(e) => {
+                                    error!("PlayerInternal handle_command_load: {}", e);
+                                    position_pcm
+                                }
+                            };
                         loaded_track.stream_loader_controller.set_stream_mode();
                     }
+                    if let (true, Some(crossfade_play_request_id)) = (play, crossfade_pending) {
+                        self.start_crossfade(crossfade_play_request_id);
+                    }
                     self.start_playback(track_id, play_request_id, *loaded_track, play);
                     return;
                 } else {
-                    error!("PlayerInternal handle_command_load: Invalid PlayerState");
-                    exit(1);
+                    self.handle_player_error(PlayerError::InvalidState("handle_command_load"));
+                    return;
                 }
             }
         }
@@ -1720,7 +3363,8 @@ impl PlayerInternal {
         self.preload = PlayerPreload::None;
 
         // If we don't have a loader yet, create one from scratch.
-        let loader = loader.unwrap_or_else(|| Box::pin(self.load_track(track_id, position_ms)));
+        let loader =
+            loader.unwrap_or_else(|| Box::pin(self.load_track(track_id, local_uri, position_ms)));
 
         // Set ourselves to a loading state.
         self.state = PlayerState::Loading {
@@ -1731,7 +3375,7 @@ impl PlayerInternal {
         };
     }
 
-    fn handle_command_preload(&mut self, track_id: SpotifyId) {
+    fn handle_command_preload(&mut self, track_id: SpotifyId, local_uri: Option<String>) {
         debug!("Preloading track");
         let mut preload_track = true;
         // check whether the track is already loaded somewhere or being loaded.
@@ -1774,7 +3418,7 @@ impl PlayerInternal {
 
         // schedule the preload of the current track if desired.
         if preload_track {
-            let loader = self.load_track(track_id, 0);
+            let loader = self.load_track(track_id, local_uri, 0);
             self.preload = PlayerPreload::Loading {
                 track_id,
                 loader: Box::pin(loader),
@@ -1783,14 +3427,36 @@ impl PlayerInternal {
     }
 
     fn handle_command_seek(&mut self, position_ms: u32) {
+        // Fade out here, and back in at the new position.
+        self.flush_output();
+        self.fade_out();
+        self.crossfade = None;
+        if let Some(ref mut time_stretcher) = self.time_stretcher {
+            time_stretcher.reset();
+        }
+        if let Some(ref mut resampler) = self.resampler {
+            resampler.reset();
+        }
+
         if let Some(stream_loader_controller) = self.state.stream_loader_controller() {
             stream_loader_controller.set_random_access_mode();
         }
+        // What is reported is where the decoder actually ended up.
+        let mut position_ms = position_ms;
         if let Some(decoder) = self.state.decoder() {
             let position_pcm = Self::position_ms_to_pcm(position_ms);
 
             match decoder.seek(position_pcm) {
-                Ok(_) => {
+                Ok(actual_position_pcm) => {
+                    if actual_position_pcm != position_pcm {
+                        debug!(
+                            "Seek to {} ms ended up at {} ms",
+                            position_ms,
+                            Self::position_pcm_to_ms(actual_position_pcm)
+                        );
+                    }
+                    position_ms = Self::position_pcm_to_ms(actual_position_pcm);
+
                     if let PlayerState::Playing {
                         ref mut stream_position_pcm,
                         ..
@@ -1800,10 +3466,29 @@ impl PlayerInternal {
                         ..
                     } = self.state
                     {
-                        *stream_position_pcm = position_pcm;
+                        *stream_position_pcm = actual_position_pcm;
+                    }
+                }
+                Err(e) => {
+                    if let Some(track_id) = self.state.track_id() {
+                        self.handle_player_error(PlayerError::Seek {
+                            track_id,
+                            position_ms,
+                            error: e.to_string(),
+                        });
+                    }
+                    if let PlayerState::Playing {
+                        stream_position_pcm,
+                        ..
+                    }
+                    | PlayerState::Paused {
+                        stream_position_pcm,
+                        ..
+                    } = self.state
+                    {
+                        position_ms = Self::position_pcm_to_ms(stream_position_pcm);
                     }
                 }
-                Err(e) => error!("PlayerInternal handle_command_seek: {}", e),
             }
         } else {
             warn!("Player::seek called from invalid state");
@@ -1817,6 +3502,12 @@ impl PlayerInternal {
         // ensure we have a bit of a buffer of downloaded data
         self.preload_data_before_playback();
 
+        if self.state.is_playing() {
+            self.fade_in();
+        }
+
+        let position_ms = self.heard_position_ms(position_ms);
+        let nominal_start_time = self.nominal_start_time(position_ms);
         if let PlayerState::Playing {
             track_id,
             play_request_id,
@@ -1825,8 +3516,7 @@ impl PlayerInternal {
             ..
         } = self.state
         {
-            *reported_nominal_start_time =
-                Some(Instant::now() - Duration::from_millis(position_ms as u64));
+            *reported_nominal_start_time = Some(nominal_start_time);
             self.send_event(PlayerEvent::Playing {
                 track_id,
                 play_request_id,
@@ -1855,12 +3545,16 @@ impl PlayerInternal {
         match cmd {
             PlayerCommand::Load {
                 track_id,
+                local_uri,
                 play_request_id,
                 play,
                 position_ms,
-            } => self.handle_command_load(track_id, play_request_id, play, position_ms),
+            } => self.handle_command_load(track_id, local_uri, play_request_id, play, position_ms),
 
-            PlayerCommand::Preload { track_id } => self.handle_command_preload(track_id),
+            PlayerCommand::Preload {
+                track_id,
+                local_uri,
+            } => self.handle_command_preload(track_id, local_uri),
 
             PlayerCommand::Seek(position_ms) => self.handle_command_seek(position_ms),
 
@@ -1881,10 +3575,145 @@ impl PlayerInternal {
             PlayerCommand::SetAutoNormaliseAsAlbum(setting) => {
                 self.auto_normalise_as_album = setting
             }
+
+            PlayerCommand::SetEqualizer(bands) => self.filter_chain.set_equalizer(&bands),
+
+            PlayerCommand::AddAudioFilter(audio_filter) => self.filter_chain.push(audio_filter),
+
+            PlayerCommand::SetSpeed(speed) => self.handle_command_set_speed(speed),
+
+            PlayerCommand::SetSink {
+                sink_builder,
+                device,
+                format,
+            } => self.handle_command_set_sink(sink_builder, device, format),
+
+            PlayerCommand::SetFallbackSink {
+                sink_builder,
+                device,
+                format,
+            } => self.fallback_sink = Some((sink_builder, device, format)),
+
+            PlayerCommand::SetSleepTimer(timer) => self.handle_command_set_sleep_timer(timer),
+        }
+    }
+
+    fn handle_command_set_sink(
+        &mut self,
+        sink_builder: SinkBuilder,
+        device: Option<String>,
+        format: AudioFormat,
+    ) {
+        // The old sink hands over to the new one without closing as far as the sink event
+        // callback is concerned, so that playback carries on where it is.
+        debug!("Switching sink to {:?} with format {:?}", device, format);
+        let sample_rate = self.config.sample_rate;
+        let result = self.output.set_sink(
+            Box::new(move || sink_builder(device, format, sample_rate)),
+            self.sink_status == SinkStatus::Running,
+        );
+        self.on_fallback_sink = false;
+
+        match result {
+            Ok(()) => {
+                // The new sink takes over from one that was waiting to be restarted.
+                if self.sink_recovery.take().is_some() {
+                    self.handle_sink_reconnected(false);
+                }
+            }
+            Err(e) => {
+                if !self.recover_sink(e) {
+                    self.close_failed_sink();
+                    self.handle_pause();
+                }
+            }
+        }
+
+        // The audio that was buffered in the old sink is gone, report the position again.
+        if let PlayerState::Playing {
+            ref mut reported_nominal_start_time,
+            ..
+        } = self.state
+        {
+            *reported_nominal_start_time = None;
+        }
+    }
+
+    fn handle_command_set_speed(&mut self, speed: f64) {
+        if self.config.passthrough {
+            warn!("Player::set_speed has no effect in passthrough mode");
+            return;
+        }
+
+        if !Player::SPEED_RANGE.contains(&speed) {
+            warn!(
+                "Ignoring playback speed {}, valid speeds are {} to {}",
+                speed,
+                Player::SPEED_RANGE.start(),
+                Player::SPEED_RANGE.end()
+            );
+            return;
+        }
+
+        debug!("Playback speed: {}", speed);
+        self.speed = speed;
+
+        if (speed - 1.0).abs() <= f64::EPSILON {
+            // What the time stretcher holds on to is played out before the decoded audio
+            // passes straight on, so that none of it is skipped.
+            if let Some(mut time_stretcher) = self.time_stretcher.take() {
+                let mut data = time_stretcher.finish();
+                if self.state.is_playing() && !data.is_empty() {
+                    self.process_tempo_adjusted(&mut data);
+                    if let Err(e) = self.output.write(AudioPacket::Samples(data), None) {
+                        if !self.recover_sink(e) {
+                            self.close_failed_sink();
+                            self.handle_pause();
+                        }
+                    }
+                }
+            }
+        } else if let Some(ref mut time_stretcher) = self.time_stretcher {
+            time_stretcher.set_speed(speed);
+        } else {
+            self.time_stretcher = Some(TimeStretcher::new(SAMPLE_RATE, speed));
+        }
+
+        // Positions are extrapolated from the time playback started, which depends on the
+        // speed. Report the position again so that listeners can catch up.
+        self.send_event(PlayerEvent::SpeedChanged { speed });
+        if let PlayerState::Playing {
+            ref mut reported_nominal_start_time,
+            ..
+        } = self.state
+        {
+            *reported_nominal_start_time = None;
+        }
+    }
+
+    // The audio that was written to the output but not heard yet, in media time.
+    fn sink_latency_ms(&self) -> u32 {
+        if self.config.passthrough {
+            return 0;
         }
+        self.output.latency().map_or(0, |latency| {
+            (latency.as_secs_f64() * 1000.0 * self.speed) as u32
+        })
+    }
+
+    fn heard_position_ms(&self, position_ms: u32) -> u32 {
+        position_ms.saturating_sub(self.sink_latency_ms())
+    }
+
+    // The instant at which playback would have started to reach `position_ms` (in media time)
+    // by now at the current speed.
+    fn nominal_start_time(&self, position_ms: u32) -> Instant {
+        Instant::now() - Duration::from_secs_f64(position_ms as f64 / 1000.0 / self.speed)
     }
 
     fn send_event(&mut self, event: PlayerEvent) {
+        self.update_stream_properties(&event);
+
         let mut index = 0;
         while index < self.event_senders.len() {
             match self.event_senders[index].send(event.clone()) {
@@ -1896,9 +3725,46 @@ impl PlayerInternal {
         }
     }
 
+    // Keeps sinks that pass on what is playing up to date with the events that change it.
+    fn update_stream_properties(&mut self, event: &PlayerEvent) {
+        let properties = &mut self.stream_properties;
+        match *event {
+            PlayerEvent::Changed { new_track_id, .. } => {
+                // The metadata follows once the new track is loaded.
+                *properties = StreamProperties {
+                    uri: new_track_id.to_uri(),
+                    ..Default::default()
+                };
+            }
+            PlayerEvent::Playing {
+                position_ms,
+                duration_ms,
+                ..
+            } => {
+                properties.position_ms = position_ms;
+                properties.duration_ms = duration_ms;
+                properties.playing = true;
+            }
+            PlayerEvent::Paused {
+                position_ms,
+                duration_ms,
+                ..
+            } => {
+                properties.position_ms = position_ms;
+                properties.duration_ms = duration_ms;
+                properties.playing = false;
+            }
+            PlayerEvent::Stopped { .. } => *properties = StreamProperties::default(),
+            _ => return,
+        }
+
+        self.output.set_stream_properties(&self.stream_properties);
+    }
+
     fn load_track(
-        &self,
+        &mut self,
         spotify_id: SpotifyId,
+        local_uri: Option<String>,
         position_ms: u32,
     ) -> impl Future<Output = Result<PlayerLoadedTrackData, ()>> + Send + 'static {
         // This method creates a future that returns the loaded stream and associated info.
@@ -1907,15 +3773,31 @@ impl PlayerInternal {
         // easily. Instead we spawn a thread to do the work and return a one-shot channel as the
         // future to work with.
 
+        let mut config = self.config.clone();
+        if let Some(ref mut auto_bitrate) = self.auto_bitrate {
+            // The rate of the session would count the time when nothing was downloaded.
+            let (download_rate, ping_time) = match self.state.stream_loader_controller() {
+                Some(stream_loader_controller) => (
+                    stream_loader_controller.download_rate(),
+                    Some(stream_loader_controller.ping_time())
+                        .filter(|&ping_time| ping_time > Duration::new(0, 0)),
+                ),
+                None => (None, None),
+            };
+            config.bitrate = auto_bitrate.select(download_rate, ping_time);
+        }
+
         let loader = PlayerTrackLoader {
             session: self.session.clone(),
-            config: self.config.clone(),
+            config,
+            local_files: self.local_files.clone(),
         };
 
         let (result_tx, result_rx) = oneshot::channel();
 
         std::thread::spawn(move || {
-            let data = futures_executor::block_on(loader.load_track(spotify_id, position_ms));
+            let data =
+                futures_executor::block_on(loader.load_track(spotify_id, local_uri, position_ms));
             if let Some(data) = data {
                 let _ = result_tx.send(data);
             }
@@ -1947,7 +3829,103 @@ impl PlayerInternal {
                     * bytes_per_second as f32) as usize,
                 (READ_AHEAD_BEFORE_PLAYBACK.as_secs_f32() * bytes_per_second as f32) as usize,
             );
-            stream_loader_controller.fetch_next_blocking(wait_for_data_length);
+            self.wait_for_data(wait_for_data_length);
+        }
+    }
+
+    // Blocks until `length` bytes from the read position on have been downloaded, if the track is
+    // playing. Playback stalls in the meantime, which is reported along with the progress of the
+    // download. Returns whether it did.
+    fn wait_for_data(&mut self, length: usize) -> bool {
+        let (track_id, play_request_id, position_ms, stream_loader_controller) = match self.state {
+            PlayerState::Playing {
+                track_id,
+                play_request_id,
+                stream_position_pcm,
+                ref stream_loader_controller,
+                ..
+            } if !stream_loader_controller.next_available(length) => (
+                track_id,
+                play_request_id,
+                Self::position_pcm_to_ms(stream_position_pcm),
+                stream_loader_controller.clone(),
+            ),
+            _ => return false,
+        };
+
+        let start = Instant::now();
+        let position_ms = self.heard_position_ms(position_ms);
+        self.send_event(PlayerEvent::Buffering {
+            play_request_id,
+            track_id,
+            position_ms,
+        });
+
+        while !stream_loader_controller
+            .fetch_next_blocking_timeout(length, DOWNLOAD_PROGRESS_INTERVAL)
+        {
+            self.report_download_progress(true);
+        }
+
+        debug!("Buffered for {} ms", start.elapsed().as_millis());
+        self.send_event(PlayerEvent::BufferingFinished {
+            play_request_id,
+            track_id,
+        });
+
+        // What was reported as playing before has been delayed by the stall.
+        if let PlayerState::Playing {
+            ref mut reported_nominal_start_time,
+            ..
+        } = self.state
+        {
+            *reported_nominal_start_time = None;
+        }
+        true
+    }
+
+    // Reports how far the download of the current track has come, if it is being downloaded and
+    // the last report is due for an update, or right away if `force` is set.
+    fn report_download_progress(&mut self, force: bool) {
+        let (track_id, play_request_id, progress) = match self.state {
+            PlayerState::Playing {
+                track_id,
+                play_request_id,
+                ref stream_loader_controller,
+                ..
+            }
+            | PlayerState::Paused {
+                track_id,
+                play_request_id,
+                ref stream_loader_controller,
+                ..
+            } => (
+                track_id,
+                play_request_id,
+                stream_loader_controller.download_progress(),
+            ),
+            _ => return,
+        };
+
+        let due = match self.download_progress_reported {
+            Some((reported_play_request_id, reported_time, reported_progress))
+                if reported_play_request_id == play_request_id =>
+            {
+                force
+                    || (!reported_progress.is_complete()
+                        && reported_time.elapsed() >= DOWNLOAD_PROGRESS_INTERVAL)
+            }
+            // Files that are there already (e.g. from the cache) are not reported at all.
+            _ => !progress.is_complete(),
+        };
+
+        if due {
+            self.download_progress_reported = Some((play_request_id, Instant::now(), progress));
+            self.send_event(PlayerEvent::DownloadProgress {
+                play_request_id,
+                track_id,
+                progress,
+            });
         }
     }
 }
@@ -1972,7 +3950,7 @@ impl ::std::fmt::Debug for PlayerCommand {
                 .field(&play)
                 .field(&position_ms)
                 .finish(),
-            PlayerCommand::Preload { track_id } => {
+            PlayerCommand::Preload { track_id, .. } => {
                 f.debug_tuple("Preload").field(&track_id).finish()
             }
             PlayerCommand::Play => f.debug_tuple("Play").finish(),
@@ -1990,6 +3968,28 @@ impl ::std::fmt::Debug for PlayerCommand {
                 .debug_tuple("SetAutoNormaliseAsAlbum")
                 .field(&setting)
                 .finish(),
+            PlayerCommand::SetEqualizer(ref bands) => {
+                f.debug_tuple("SetEqualizer").field(&bands).finish()
+            }
+            PlayerCommand::AddAudioFilter(_) => f.debug_tuple("AddAudioFilter").finish(),
+            PlayerCommand::SetSpeed(speed) => f.debug_tuple("SetSpeed").field(&speed).finish(),
+            PlayerCommand::SetSink {
+                ref device, format, ..
+            } => f
+                .debug_struct("SetSink")
+                .field("device", &device)
+                .field("format", &format)
+                .finish(),
+            PlayerCommand::SetFallbackSink {
+                ref device, format, ..
+            } => f
+                .debug_struct("SetFallbackSink")
+                .field("device", &device)
+                .field("format", &format)
+                .finish(),
+            PlayerCommand::SetSleepTimer(timer) => {
+                f.debug_tuple("SetSleepTimer").field(&timer).finish()
+            }
         }
     }
 }
@@ -2074,3 +4074,151 @@ impl<T: Read + Seek> Seek for Subfile<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleep_timer_fires_at_its_deadline() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let start = Instant::now();
+            let mut sleep_timer =
+                PlayerSleepTimer::new(SleepTimer::After(Duration::from_millis(50)));
+            assert!(!sleep_timer.fires_before_track(true, true));
+
+            future::poll_fn(|cx| sleep_timer.poll_deadline(cx)).await;
+            assert!(start.elapsed() >= Duration::from_millis(50));
+            assert!(sleep_timer.fires_before_track(false, false));
+
+            // It only wakes the player up once.
+            let cx = &mut Context::from_waker(futures_util::task::noop_waker_ref());
+            assert!(sleep_timer.poll_deadline(cx).is_pending());
+        });
+    }
+
+    #[test]
+    fn auto_bitrate_follows_the_download_rate() {
+        let mut auto_bitrate = PlayerAutoBitrate::new();
+        assert_eq!(auto_bitrate.bitrate, Bitrate::Bitrate160);
+        // Nothing changes before the rate is known.
+        assert_eq!(auto_bitrate.select(None, None), Bitrate::Bitrate160);
+
+        // one step at a time, and only with headroom
+        let fast = 100 * 1024;
+        assert_eq!(auto_bitrate.select(Some(fast), None), Bitrate::Bitrate320);
+        assert_eq!(auto_bitrate.select(Some(fast), None), Bitrate::Bitrate320);
+        assert_eq!(
+            auto_bitrate.select(Some(30 * 1024), None),
+            Bitrate::Bitrate160
+        );
+        assert_eq!(
+            auto_bitrate.select(Some(20 * 1024), None),
+            Bitrate::Bitrate160
+        );
+        assert_eq!(
+            auto_bitrate.select(Some(5 * 1024), None),
+            Bitrate::Bitrate96
+        );
+        assert_eq!(
+            auto_bitrate.select(Some(5 * 1024), None),
+            Bitrate::Bitrate96
+        );
+
+        // not while the servers are slow to respond
+        let slow_ping = Some(AUTO_BITRATE_MAX_PING_TIME * 2);
+        assert_eq!(
+            auto_bitrate.select(Some(fast), slow_ping),
+            Bitrate::Bitrate96
+        );
+        assert_eq!(auto_bitrate.select(Some(fast), None), Bitrate::Bitrate160);
+    }
+
+    #[test]
+    fn auto_bitrate_steps_down_on_underrun() {
+        let mut auto_bitrate = PlayerAutoBitrate::new();
+        auto_bitrate.underrun();
+        assert_eq!(auto_bitrate.bitrate, Bitrate::Bitrate96);
+        auto_bitrate.underrun();
+        assert_eq!(auto_bitrate.bitrate, Bitrate::Bitrate96);
+
+        // It takes a while without stalls to step up again, however fast the link is.
+        assert_eq!(
+            auto_bitrate.select(Some(100 * 1024), None),
+            Bitrate::Bitrate96
+        );
+        auto_bitrate.step_up_delay = Duration::default();
+        assert_eq!(
+            auto_bitrate.select(Some(100 * 1024), None),
+            Bitrate::Bitrate160
+        );
+    }
+
+    #[test]
+    fn sleep_timer_fires_at_the_end_of_a_track() {
+        let sleep_timer = PlayerSleepTimer::new(SleepTimer::EndOfTrack);
+        assert!(sleep_timer.wake.is_none());
+        // not when another track is loaded while one plays
+        assert!(!sleep_timer.fires_before_track(false, true));
+        assert!(sleep_timer.fires_before_track(true, false));
+
+        let sleep_timer = PlayerSleepTimer::new(SleepTimer::EndOfAlbum);
+        assert!(!sleep_timer.fires_before_track(true, false));
+        assert!(!sleep_timer.fires_before_track(false, true));
+        assert!(sleep_timer.fires_before_track(true, true));
+    }
+    #[test]
+    fn file_formats_follow_the_preferred_codec() {
+        let mut config = PlayerConfig {
+            bitrate: Bitrate::Bitrate320,
+            ..PlayerConfig::default()
+        };
+        let formats = PlayerTrackLoader::file_formats(&config);
+        assert_eq!(formats.len(), 10);
+        assert_eq!(
+            formats[..4],
+            [
+                FileFormat::OGG_VORBIS_320,
+                FileFormat::OGG_VORBIS_160,
+                FileFormat::OGG_VORBIS_96,
+                FileFormat::MP3_320,
+            ]
+        );
+
+        config.preferred_codec = AudioCodec::Aac;
+        let formats = PlayerTrackLoader::file_formats(&config);
+        assert_eq!(
+            formats[..4],
+            [
+                FileFormat::AAC_320,
+                FileFormat::AAC_160,
+                FileFormat::MP4_128,
+                FileFormat::OGG_VORBIS_320,
+            ]
+        );
+        assert_eq!(
+            formats[6..],
+            [
+                FileFormat::MP3_320,
+                FileFormat::MP3_256,
+                FileFormat::MP3_160,
+                FileFormat::MP3_96
+            ]
+        );
+
+        // only Vorbis can be passed through
+        config.passthrough = true;
+        assert_eq!(
+            PlayerTrackLoader::file_formats(&config),
+            [
+                FileFormat::OGG_VORBIS_320,
+                FileFormat::OGG_VORBIS_160,
+                FileFormat::OGG_VORBIS_96,
+            ]
+        );
+    }
+}
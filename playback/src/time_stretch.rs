@@ -0,0 +1,270 @@
+use std::f64::consts::PI;
+
+use crate::NUM_CHANNELS;
+
+// Length of the overlapping segments. Long enough to contain a few periods of low voices,
+// short enough not to smear transients.
+const SEGMENT_MS: u32 = 30;
+
+// How far a segment may be moved to line up with the previous one.
+const TOLERANCE_MS: u32 = 10;
+
+// Changes the tempo of a stream without changing its pitch, by means of WSOLA (waveform
+// similarity overlap-add). Segments are taken from the input at `speed` times the rate at
+// which they are laid out in the output, each one shifted slightly so that its waveform
+// lines up with the natural continuation of the segment before it.
+pub struct TimeStretcher {
+    speed: f64,
+    // Hann window, which sums to one at 50% overlap
+    window: Vec<f64>,
+    // output hop, half of the segment length in frames
+    hop: usize,
+    tolerance: usize,
+    // interleaved input frames that are still needed for upcoming segments
+    input: Vec<f64>,
+    // nominal start of the next segment, in input frames relative to the start of `input`
+    position: f64,
+    // where the last segment would have continued, in input frames relative to the
+    // start of `input`
+    continuation: Option<usize>,
+    // second half of the last windowed segment, to overlap with the next one
+    tail: Vec<f64>,
+}
+
+impl TimeStretcher {
+    pub fn new(sample_rate: u32, speed: f64) -> Self {
+        let segment = 2 * (sample_rate * SEGMENT_MS / 2000) as usize;
+        let window = (0..segment)
+            .map(|i| 0.5 - 0.5 * f64::cos(2.0 * PI * i as f64 / segment as f64))
+            .collect();
+
+        let mut time_stretcher = Self {
+            speed,
+            window,
+            hop: segment / 2,
+            tolerance: (sample_rate * TOLERANCE_MS / 1000) as usize,
+            input: Vec::new(),
+            position: 0.0,
+            continuation: None,
+            tail: Vec::new(),
+        };
+        time_stretcher.reset();
+        time_stretcher
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    // Discards any buffered input, for when the stream is not continuous (seek, stop).
+    pub fn reset(&mut self) {
+        let channels = NUM_CHANNELS as usize;
+        // Leading silence so that the first segments can be searched for in both directions.
+        self.input.clear();
+        self.input.resize(self.tolerance * channels, 0.0);
+        self.position = self.tolerance as f64;
+        self.continuation = None;
+        self.tail.clear();
+        self.tail.resize(self.hop * channels, 0.0);
+    }
+
+    pub fn process(&mut self, input: &[f64]) -> Vec<f64> {
+        let channels = NUM_CHANNELS as usize;
+        let segment = self.window.len();
+
+        self.input.extend_from_slice(input);
+        let buffered_frames = self.input.len() / channels;
+
+        let expected_frames = (input.len() / channels) as f64 / self.speed;
+        let mut output = Vec::with_capacity((expected_frames as usize + self.hop) * channels);
+
+        loop {
+            let nominal = self.position.round() as usize;
+            let needed = match self.continuation {
+                Some(continuation) => usize::max(nominal + self.tolerance, continuation),
+                None => nominal,
+            } + segment;
+            if needed > buffered_frames {
+                break;
+            }
+
+            let start = match self.continuation {
+                Some(continuation) => self.best_match(nominal, continuation),
+                None => nominal,
+            };
+
+            for (i, weight) in self.window.iter().enumerate() {
+                for channel in 0..channels {
+                    let sample = self.input[(start + i) * channels + channel] * weight;
+                    if i < self.hop {
+                        output.push(self.tail[i * channels + channel] + sample);
+                    } else {
+                        self.tail[(i - self.hop) * channels + channel] = sample;
+                    }
+                }
+            }
+
+            self.continuation = Some(start + self.hop);
+            self.position += self.hop as f64 * self.speed;
+        }
+
+        // Drop the frames that neither the search nor the continuation will look at anymore.
+        let mut consumed_frames = (self.position as usize).saturating_sub(self.tolerance);
+        if let Some(continuation) = self.continuation {
+            consumed_frames = usize::min(consumed_frames, continuation);
+        }
+        let consumed_frames = usize::min(consumed_frames, buffered_frames);
+        self.input.drain(..consumed_frames * channels);
+        self.position -= consumed_frames as f64;
+        self.continuation = self
+            .continuation
+            .map(|continuation| continuation - consumed_frames);
+
+        output
+    }
+
+    // Returns the audio that is still buffered as it is, and resets, for when the stream goes on
+    // at its own tempo. The last segment is overlapped with where it would have continued, so
+    // that the output joins on to what comes after without a gap.
+    pub fn finish(&mut self) -> Vec<f64> {
+        let channels = NUM_CHANNELS as usize;
+        let buffered_frames = self.input.len() / channels;
+
+        let (start, overlap) = match self.continuation {
+            Some(continuation) => (continuation, self.hop),
+            None => (self.position.round() as usize, 0),
+        };
+        let start = usize::min(start, buffered_frames);
+        let frames = usize::max(buffered_frames - start, overlap);
+
+        let mut output = Vec::with_capacity(frames * channels);
+        for i in 0..frames {
+            for channel in 0..channels {
+                let sample = match self.input.get((start + i) * channels + channel) {
+                    Some(&sample) if i < overlap => sample * self.window[i],
+                    Some(&sample) => sample,
+                    None => 0.0,
+                };
+                let tail = if i < overlap {
+                    self.tail[i * channels + channel]
+                } else {
+                    0.0
+                };
+                output.push(tail + sample);
+            }
+        }
+
+        self.reset();
+        output
+    }
+
+    // Finds the segment start within the tolerance around `nominal` whose first half is most
+    // similar to the first half of the natural continuation.
+    fn best_match(&self, nominal: usize, continuation: usize) -> usize {
+        let channels = NUM_CHANNELS as usize;
+        let mono = |frame: usize| -> f64 {
+            self.input[frame * channels..(frame + 1) * channels]
+                .iter()
+                .sum()
+        };
+
+        let target: Vec<f64> = (continuation..continuation + self.hop).map(mono).collect();
+        let first = nominal - self.tolerance;
+        let candidates: Vec<f64> = (first..nominal + self.tolerance + self.hop)
+            .map(mono)
+            .collect();
+
+        let mut energy: f64 = candidates[..self.hop].iter().map(|x| x * x).sum();
+        let mut best_offset = self.tolerance;
+        let mut best_score = f64::MIN;
+        for offset in 0..=2 * self.tolerance {
+            if offset > 0 {
+                let leaving = candidates[offset - 1];
+                let entering = candidates[offset + self.hop - 1];
+                energy += entering * entering - leaving * leaving;
+            }
+
+            let correlation: f64 = candidates[offset..offset + self.hop]
+                .iter()
+                .zip(target.iter())
+                .map(|(x, y)| x * y)
+                .sum();
+            let score = correlation / f64::sqrt(energy.max(f64::EPSILON));
+            if score > best_score {
+                best_score = score;
+                best_offset = offset;
+            }
+        }
+
+        first + best_offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, frames: usize) -> Vec<f64> {
+        (0..frames)
+            .flat_map(|i| {
+                let sample = f64::sin(2.0 * PI * frequency * i as f64 / 44100.0);
+                vec![sample; NUM_CHANNELS as usize]
+            })
+            .collect()
+    }
+
+    fn zero_crossings(data: &[f64]) -> usize {
+        data.chunks(NUM_CHANNELS as usize)
+            .zip(data.chunks(NUM_CHANNELS as usize).skip(1))
+            .filter(|(a, b)| (a[0] < 0.0) != (b[0] < 0.0))
+            .count()
+    }
+
+    #[test]
+    fn tempo_changes_but_pitch_does_not() {
+        for &speed in &[0.5, 1.5, 3.0] {
+            let mut time_stretcher = TimeStretcher::new(44100, speed);
+            let output: Vec<f64> = sine(440.0, 2 * 44100)
+                .chunks(2 * 1024)
+                .flat_map(|packet| time_stretcher.process(packet))
+                .collect();
+
+            let input_seconds = 2.0;
+            let output_seconds = output.len() as f64 / (NUM_CHANNELS as f64 * 44100.0);
+            assert!((output_seconds - input_seconds / speed).abs() < 0.1);
+
+            // A 440 Hz sine crosses zero 440 times in half a second, whatever the tempo.
+            let frames_per_second = 44100 * NUM_CHANNELS as usize;
+            let half_second = &output[frames_per_second / 10..frames_per_second * 6 / 10];
+            let crossings = zero_crossings(half_second) as i64;
+            assert!((crossings - 440).abs() < 10, "{} at {}x", crossings, speed);
+        }
+    }
+
+    #[test]
+    fn finish_hands_over_what_is_buffered() {
+        let input = sine(440.0, 44100);
+        let mut time_stretcher = TimeStretcher::new(44100, 1.0);
+        let mut output: Vec<f64> = input
+            .chunks(2 * 1000)
+            .flat_map(|packet| time_stretcher.process(packet))
+            .collect();
+        output.extend(time_stretcher.finish());
+
+        // At normal speed nothing is left out, give or take the shifts of the segments.
+        let frames = (output.len() / NUM_CHANNELS as usize) as i64;
+        assert!((frames - 44100).abs() <= (44100 * TOLERANCE_MS / 1000) as i64);
+        assert_eq!(output.last(), input.last());
+
+        // The last segment fades over into the rest without a jump.
+        let largest_step = output
+            .chunks(NUM_CHANNELS as usize)
+            .zip(output.chunks(NUM_CHANNELS as usize).skip(1))
+            .map(|(a, b)| (a[0] - b[0]).abs())
+            .fold(0.0, f64::max);
+        let sine_step = 2.0 * PI * 440.0 / 44100.0;
+        assert!(largest_step < 1.5 * sine_step, "{}", largest_step);
+
+        assert!(time_stretcher.finish().is_empty());
+    }
+}
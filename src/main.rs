@@ -12,13 +12,15 @@ use librespot::core::cache::Cache;
 use librespot::core::config::{ConnectConfig, DeviceType, SessionConfig};
 use librespot::core::session::Session;
 use librespot::core::version;
-use librespot::playback::audio_backend::{self, SinkBuilder, BACKENDS};
+use librespot::playback::audio_backend::{self, FanoutSink, SinkBuilder, BACKENDS};
 use librespot::playback::config::{
-    AudioFormat, Bitrate, NormalisationMethod, NormalisationType, PlayerConfig, VolumeCtrl,
+    AudioCodec, AudioFormat, Bitrate, EqualizerBand, FadeCurve, NormalisationMethod,
+    NormalisationType, PlayerConfig, SleepTimer, VolumeCtrl,
 };
 use librespot::playback::dither;
 #[cfg(feature = "alsa-backend")]
 use librespot::playback::mixer::alsamixer::AlsaMixer;
+use librespot::playback::mixer::external::ExternalMixer;
 use librespot::playback::mixer::{self, MixerConfig, MixerFn};
 use librespot::playback::player::{db_to_ratio, ratio_to_db, Player};
 
@@ -27,7 +29,7 @@ use player_event_handler::{emit_sink_event, run_program_on_events};
 
 use std::env;
 use std::ops::RangeInclusive;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::process::exit;
 use std::str::FromStr;
@@ -181,6 +183,9 @@ struct Setup {
     zeroconf_port: u16,
     player_event_program: Option<String>,
     emit_sink_events: bool,
+    fallback_backend: Option<(SinkBuilder, Option<String>)>,
+    sleep_timer: Option<SleepTimer>,
+    speed: Option<f64>,
 }
 
 fn get_setup() -> Setup {
@@ -191,6 +196,15 @@ fn get_setup() -> Setup {
     const VALID_NORMALISATION_THRESHOLD_RANGE: RangeInclusive<f64> = -10.0..=0.0;
     const VALID_NORMALISATION_ATTACK_RANGE: RangeInclusive<u64> = 1..=500;
     const VALID_NORMALISATION_RELEASE_RANGE: RangeInclusive<u64> = 1..=1000;
+    const VALID_CROSSFADE_RANGE: RangeInclusive<u64> = 0..=20000;
+    const VALID_FADE_RANGE: RangeInclusive<u64> = 0..=500;
+    const VALID_SAMPLE_RATE_RANGE: RangeInclusive<u32> = 8000..=192000;
+    const VALID_SLEEP_TIMER_RANGE: RangeInclusive<u64> = 1..=1440;
+    const VALID_SLEEP_TIMER_FADE_RANGE: RangeInclusive<u64> = 0..=300000;
+    const VALID_OUTPUT_BUFFER_RANGE: RangeInclusive<u64> = 0..=10000;
+    const VALID_SINK_RETRIES_RANGE: RangeInclusive<u32> = 0..=100;
+    const VALID_SINK_RETRY_BACKOFF_RANGE: RangeInclusive<u64> = 1..=60000;
+    const VALID_VOLUME_RAMP_RANGE: RangeInclusive<u64> = 0..=1000;
 
     const AP_PORT: &str = "ap-port";
     const AUTOPLAY: &str = "autoplay";
@@ -198,6 +212,8 @@ fn get_setup() -> Setup {
     const BITRATE: &str = "bitrate";
     const CACHE: &str = "cache";
     const CACHE_SIZE_LIMIT: &str = "cache-size-limit";
+    const CROSSFADE: &str = "crossfade";
+    const CROSSFADE_CURVE: &str = "crossfade-curve";
     const DEVICE: &str = "device";
     const DEVICE_TYPE: &str = "device-type";
     const DISABLE_AUDIO_CACHE: &str = "disable-audio-cache";
@@ -207,9 +223,15 @@ fn get_setup() -> Setup {
     const DITHER: &str = "dither";
     const EMIT_SINK_EVENTS: &str = "emit-sink-events";
     const ENABLE_VOLUME_NORMALISATION: &str = "enable-volume-normalisation";
+    const EQUALIZER: &str = "equalizer";
+    const EXTERNAL_MIXER: &str = "external-mixer";
+    const EXTERNAL_MIXER_QUERY: &str = "external-mixer-query";
+    const FADE: &str = "fade";
+    const FALLBACK_BACKEND: &str = "fallback-backend";
     const FORMAT: &str = "format";
     const HELP: &str = "help";
     const INITIAL_VOLUME: &str = "initial-volume";
+    const LOCAL_FILES: &str = "local-files";
     const MIXER_TYPE: &str = "mixer";
     const ALSA_MIXER_DEVICE: &str = "alsa-mixer-device";
     const ALSA_MIXER_INDEX: &str = "alsa-mixer-index";
@@ -223,15 +245,24 @@ fn get_setup() -> Setup {
     const NORMALISATION_RELEASE: &str = "normalisation-release";
     const NORMALISATION_THRESHOLD: &str = "normalisation-threshold";
     const ONEVENT: &str = "onevent";
+    const OUTPUT_BUFFER: &str = "output-buffer";
     const PASSTHROUGH: &str = "passthrough";
     const PASSWORD: &str = "password";
+    const PREFERRED_CODEC: &str = "preferred-codec";
     const PROXY: &str = "proxy";
     const QUIET: &str = "quiet";
+    const SAMPLE_RATE: &str = "sample-rate";
+    const SINK_RETRIES: &str = "sink-retries";
+    const SINK_RETRY_BACKOFF: &str = "sink-retry-backoff";
+    const SLEEP_TIMER: &str = "sleep-timer";
+    const SLEEP_TIMER_FADE: &str = "sleep-timer-fade";
+    const SPEED: &str = "speed";
     const SYSTEM_CACHE: &str = "system-cache";
     const USERNAME: &str = "username";
     const VERBOSE: &str = "verbose";
     const VERSION: &str = "version";
     const VOLUME_CTRL: &str = "volume-ctrl";
+    const VOLUME_RAMP: &str = "volume-ramp";
     const VOLUME_RANGE: &str = "volume-range";
     const ZEROCONF_PORT: &str = "zeroconf-port";
 
@@ -252,6 +283,14 @@ fn get_setup() -> Setup {
     const DISABLE_GAPLESS_SHORT: &str = "g";
     const DISABLE_CREDENTIAL_CACHE_SHORT: &str = "H";
     const HELP_SHORT: &str = "h";
+    const OUTPUT_BUFFER_SHORT: &str = "I";
+    const FADE_SHORT: &str = "i";
+    const SLEEP_TIMER_FADE_SHORT: &str = "J";
+    const EQUALIZER_SHORT: &str = "j";
+    const CROSSFADE_CURVE_SHORT: &str = "K";
+    const CROSSFADE_SHORT: &str = "k";
+    const FALLBACK_BACKEND_SHORT: &str = "L";
+    const LOCAL_FILES_SHORT: &str = "l";
     const CACHE_SIZE_LIMIT_SHORT: &str = "M";
     const MIXER_TYPE_SHORT: &str = "m";
     const ENABLE_VOLUME_NORMALISATION_SHORT: &str = "N";
@@ -262,10 +301,12 @@ fn get_setup() -> Setup {
     const PASSWORD_SHORT: &str = "p";
     const EMIT_SINK_EVENTS_SHORT: &str = "Q";
     const QUIET_SHORT: &str = "q";
+    const SAMPLE_RATE_SHORT: &str = "r";
     const INITIAL_VOLUME_SHORT: &str = "R";
     const ALSA_MIXER_DEVICE_SHORT: &str = "S";
     const ALSA_MIXER_INDEX_SHORT: &str = "s";
     const ALSA_MIXER_CONTROL_SHORT: &str = "T";
+    const SLEEP_TIMER_SHORT: &str = "t";
     const NORMALISATION_ATTACK_SHORT: &str = "U";
     const USERNAME_SHORT: &str = "u";
     const VERSION_SHORT: &str = "V";
@@ -278,25 +319,37 @@ fn get_setup() -> Setup {
     const NORMALISATION_RELEASE_SHORT: &str = "y";
     const NORMALISATION_THRESHOLD_SHORT: &str = "Z";
     const ZEROCONF_PORT_SHORT: &str = "z";
+    // We're out of letters.
+    const EXTERNAL_MIXER_SHORT: &str = "";
+    const EXTERNAL_MIXER_QUERY_SHORT: &str = "";
+    const VOLUME_RAMP_SHORT: &str = "";
+    const SPEED_SHORT: &str = "";
+    const SINK_RETRIES_SHORT: &str = "";
+    const SINK_RETRY_BACKOFF_SHORT: &str = "";
+    const PREFERRED_CODEC_SHORT: &str = "";
 
     // Options that have different desc's
     // depending on what backends were enabled at build time.
     #[cfg(feature = "alsa-backend")]
-    const MIXER_TYPE_DESC: &str = "Mixer to use {alsa|softvol}. Defaults to softvol.";
+    const MIXER_TYPE_DESC: &str = "Mixer to use {alsa|external|softvol}. Defaults to softvol.";
     #[cfg(not(feature = "alsa-backend"))]
-    const MIXER_TYPE_DESC: &str = "Not supported by the included audio backend(s).";
+    const MIXER_TYPE_DESC: &str = "Mixer to use {external|softvol}. Defaults to softvol.";
+    #[cfg(feature = "alsa-backend")]
+    const MIXER_TYPE_VALUES: &str = "alsa, external, softvol";
+    #[cfg(not(feature = "alsa-backend"))]
+    const MIXER_TYPE_VALUES: &str = "external, softvol";
     #[cfg(any(
         feature = "alsa-backend",
         feature = "rodio-backend",
         feature = "portaudio-backend"
     ))]
-    const DEVICE_DESC: &str = "Audio device to use. Use ? to list options if using alsa, portaudio or rodio. Defaults to the backend's default.";
+    const DEVICE_DESC: &str = "Audio device to use. Use ? to list options if using alsa, portaudio or rodio. For rtp, the destination as HOST:PORT[?pt=TYPE&ssrc=SSRC&ptime=MS&ttl=TTL&bind=ADDRESS]. For snapcast, the server as HOST:PORT. For fanout, the outputs as BACKEND[@FORMAT][:DEVICE] separated by ;. For null, realtime to consume the audio at the pace it plays at. Defaults to the backend's default.";
     #[cfg(not(any(
         feature = "alsa-backend",
         feature = "rodio-backend",
         feature = "portaudio-backend"
     )))]
-    const DEVICE_DESC: &str = "Not supported by the included audio backend(s).";
+    const DEVICE_DESC: &str = "Output file for pipe, shell command for subprocess, HOST:PORT[?pt=TYPE&ssrc=SSRC&ptime=MS&ttl=TTL&bind=ADDRESS] for rtp, HOST:PORT for snapcast, outputs as BACKEND[@FORMAT][:DEVICE] separated by ; for fanout, or realtime for null to consume the audio at the pace it plays at.";
     #[cfg(feature = "alsa-backend")]
     const ALSA_MIXER_CONTROL_DESC: &str =
         "Alsa mixer control, e.g. PCM, Master or similar. Defaults to PCM.";
@@ -390,15 +443,27 @@ fn get_setup() -> Setup {
     .optopt(
         BITRATE_SHORT,
         BITRATE,
-        "Bitrate (kbps) {96|160|320}. Defaults to 160.",
+        "Bitrate (kbps) {96|160|320|auto}. auto adapts to the speed of the connection. Defaults to 160.",
         "BITRATE",
     )
+    .optopt(
+        PREFERRED_CODEC_SHORT,
+        PREFERRED_CODEC,
+        "Codec {vorbis|mp3|aac} that tracks are played in when they come in several, falling back to the others. mp3 and aac need the symphonia-decoder feature. Defaults to vorbis.",
+        "CODEC",
+    )
     .optopt(
         FORMAT_SHORT,
         FORMAT,
         "Output format {F64|F32|S32|S24|S24_3|S16}. Defaults to S16.",
         "FORMAT",
     )
+    .optopt(
+        SAMPLE_RATE_SHORT,
+        SAMPLE_RATE,
+        "Output sample rate in Hz, e.g. 48000 or 96000. Audio is resampled if it differs from 44100. Defaults to 44100.",
+        "RATE",
+    )
     .optopt(
         DITHER_SHORT,
         DITHER,
@@ -423,6 +488,12 @@ fn get_setup() -> Setup {
         "Path to a directory where system files (credentials, volume) will be cached. May be different from the `--cache` option value.",
         "PATH",
     )
+    .optopt(
+        LOCAL_FILES_SHORT,
+        LOCAL_FILES,
+        "Path to a directory with music files that local files in playlists are played from. They are found by their tags.",
+        "PATH",
+    )
     .optopt(
         CACHE_SIZE_LIMIT_SHORT,
         CACHE_SIZE_LIMIT,
@@ -435,6 +506,24 @@ fn get_setup() -> Setup {
         "Audio backend to use. Use ? to list options.",
         "NAME",
     )
+    .optopt(
+        FALLBACK_BACKEND_SHORT,
+        FALLBACK_BACKEND,
+        "Audio backend and optionally device to switch to when the output fails and can't be restarted, as NAME[:DEVICE].",
+        "BACKEND",
+    )
+    .optopt(
+        SINK_RETRIES_SHORT,
+        SINK_RETRIES,
+        "Number of times to restart the output when it fails, from 0 to 100. Defaults to 3.",
+        "RETRIES",
+    )
+    .optopt(
+        SINK_RETRY_BACKOFF_SHORT,
+        SINK_RETRY_BACKOFF,
+        "Delay (ms) before the first restart of a failed output from 1 to 60000, doubling with every further restart. Defaults to 500.",
+        "DELAY",
+    )
     .optopt(
         USERNAME_SHORT,
         USERNAME,
@@ -477,6 +566,17 @@ fn get_setup() -> Setup {
         MIXER_TYPE_DESC,
         "MIXER",
     )
+    .optopt(
+        EXTERNAL_MIXER_SHORT,
+        EXTERNAL_MIXER,
+        "Command that the external mixer runs with `set VOLUME` to set the volume and with `get` to query it, or the socket it writes these lines to as tcp:HOST:PORT or unix:PATH. VOLUME is mapped by the volume control, from 0.0 to 1.0.",
+        "TARGET",
+    )
+    .optflag(
+        EXTERNAL_MIXER_QUERY_SHORT,
+        EXTERNAL_MIXER_QUERY,
+        "Query the volume from the external mixer, which answers `get` with the volume from 0.0 to 1.0.",
+    )
     .optopt(
         DEVICE_SHORT,
         DEVICE,
@@ -492,7 +592,7 @@ fn get_setup() -> Setup {
     .optopt(
         VOLUME_CTRL_SHORT,
         VOLUME_CTRL,
-        "Volume control scale type {cubic|fixed|linear|log}. Defaults to log.",
+        "Volume control scale type {cubic|fixed|linear|log|table:FILE}. A table has a volume in % and a gain in dB per line, e.g. 50, -30. Defaults to log.",
         "VOLUME_CTRL"
     )
     .optopt(
@@ -501,6 +601,12 @@ fn get_setup() -> Setup {
         VOLUME_RANGE_DESC,
         "RANGE",
     )
+    .optopt(
+        VOLUME_RAMP_SHORT,
+        VOLUME_RAMP,
+        "Duration (ms) of the glide from one volume to the next with softvol, against zipper noise while the volume is changed, from 0 to 1000. Defaults to 50.",
+        "DURATION",
+    )
     .optopt(
         NORMALISATION_METHOD_SHORT,
         NORMALISATION_METHOD,
@@ -543,6 +649,54 @@ fn get_setup() -> Setup {
         "Knee steepness of the dynamic limiter from 0.0 to 2.0. Defaults to 1.0.",
         "KNEE",
     )
+    .optopt(
+        CROSSFADE_SHORT,
+        CROSSFADE,
+        "Crossfade duration (ms) between consecutive tracks from 0 to 20000. Defaults to 0 (disabled).",
+        "DURATION",
+    )
+    .optopt(
+        CROSSFADE_CURVE_SHORT,
+        CROSSFADE_CURVE,
+        "Shape of the crossfade {linear|equal-power|s-curve}. Defaults to equal-power.",
+        "CURVE",
+    )
+    .optopt(
+        FADE_SHORT,
+        FADE,
+        "Fade duration (ms) when playback is started, paused, seeked or stopped in the middle of a track from 0 to 500. Defaults to 0 (disabled).",
+        "DURATION",
+    )
+    .optopt(
+        OUTPUT_BUFFER_SHORT,
+        OUTPUT_BUFFER,
+        "Duration (ms) of decoded audio that is buffered ahead of the output, against dropouts when decoding is held up, from 0 to 10000. Defaults to 500.",
+        "DURATION",
+    )
+    .optopt(
+        SLEEP_TIMER_SHORT,
+        SLEEP_TIMER,
+        "Pause playback after MINUTES from 1 to 1440, or at the {end-of-track|end-of-album}.",
+        "TIMER",
+    )
+    .optopt(
+        SLEEP_TIMER_FADE_SHORT,
+        SLEEP_TIMER_FADE,
+        "Duration (ms) of the fade out before the sleep timer pauses playback from 0 to 300000. Defaults to 30000.",
+        "DURATION",
+    )
+    .optopt(
+        SPEED_SHORT,
+        SPEED,
+        "Playback speed from 0.5 to 3.0, which changes the tempo without changing the pitch, e.g. for podcasts. Defaults to 1.0.",
+        "SPEED",
+    )
+    .optopt(
+        EQUALIZER_SHORT,
+        EQUALIZER,
+        "Comma separated equalizer bands <type>:<frequency>[:<gain>[:<q>]] with type {peak|lowshelf|highshelf|lowpass|highpass}, frequency (Hz), gain (dB, defaults to 0.0) and q (defaults to 0.707), e.g. lowshelf:100:3.0,peak:2500:-2.5:1.4.",
+        "BANDS",
+    )
     .optopt(
         ZEROCONF_PORT_SHORT,
         ZEROCONF_PORT,
@@ -681,12 +835,7 @@ fn get_setup() -> Setup {
     }
 
     #[cfg(not(feature = "alsa-backend"))]
-    for a in &[
-        MIXER_TYPE,
-        ALSA_MIXER_DEVICE,
-        ALSA_MIXER_INDEX,
-        ALSA_MIXER_CONTROL,
-    ] {
+    for a in &[ALSA_MIXER_DEVICE, ALSA_MIXER_INDEX, ALSA_MIXER_CONTROL] {
         if opt_present(a) {
             warn!("Alsa specific options have no effect if the alsa backend is not enabled at build time.");
             break;
@@ -701,10 +850,16 @@ fn get_setup() -> Setup {
 
     let invalid_error_msg =
         |long: &str, short: &str, invalid: &str, valid_values: &str, default_value: &str| {
-            error!("Invalid `--{}` / `-{}`: \"{}\"", long, short, invalid);
+            let opt = if short.is_empty() {
+                format!("`--{}`", long)
+            } else {
+                format!("`--{}` / `-{}`", long, short)
+            };
+
+            error!("Invalid {}: \"{}\"", opt, invalid);
 
             if !valid_values.is_empty() {
-                println!("Valid `--{}` / `-{}` values: {}", long, short, valid_values);
+                println!("Valid {} values: {}", opt, valid_values);
             }
 
             if !default_value.is_empty() {
@@ -713,7 +868,11 @@ fn get_setup() -> Setup {
         };
 
     let empty_string_error_msg = |long: &str, short: &str| {
-        error!("`--{}` / `-{}` can not be an empty string", long, short);
+        if short.is_empty() {
+            error!("`--{}` can not be an empty string", long);
+        } else {
+            error!("`--{}` / `-{}` can not be an empty string", long, short);
+        }
         exit(1);
     };
 
@@ -748,57 +907,90 @@ fn get_setup() -> Setup {
         })
         .unwrap_or_default();
 
-    #[cfg(any(
-        feature = "alsa-backend",
-        feature = "rodio-backend",
-        feature = "portaudio-backend"
-    ))]
+    // Passthrough hands the encoded stream to the sink as is, which is always at 44.1 kHz.
+    let sample_rate = opt_str(SAMPLE_RATE)
+        .filter(|_| {
+            if opt_present(PASSTHROUGH) {
+                warn!(
+                    "With the `--{}` / `-{}` flag set the audio is not resampled, the `--{}` / `-{}` option has no effect.",
+                    PASSTHROUGH, PASSTHROUGH_SHORT, SAMPLE_RATE, SAMPLE_RATE_SHORT
+                );
+                false
+            } else {
+                true
+            }
+        })
+        .map(|sample_rate| match sample_rate.parse::<u32>() {
+            Ok(value) if (VALID_SAMPLE_RATE_RANGE).contains(&value) => value,
+            _ => {
+                let valid_values = &format!(
+                    "{} - {}",
+                    VALID_SAMPLE_RATE_RANGE.start(),
+                    VALID_SAMPLE_RATE_RANGE.end()
+                );
+
+                invalid_error_msg(
+                    SAMPLE_RATE,
+                    SAMPLE_RATE_SHORT,
+                    &sample_rate,
+                    valid_values,
+                    &PlayerConfig::default().sample_rate.to_string(),
+                );
+
+                exit(1);
+            }
+        })
+        .unwrap_or_else(|| PlayerConfig::default().sample_rate);
+
+    // The pipe, subprocess, rtp, snapcast, fanout and null backends are always included and take a device.
     let device = opt_str(DEVICE);
 
-    #[cfg(any(
-        feature = "alsa-backend",
-        feature = "rodio-backend",
-        feature = "portaudio-backend"
-    ))]
     if let Some(ref value) = device {
         if value == "?" {
-            backend(device, format);
+            backend(device, format, sample_rate);
             exit(0);
         } else if value.is_empty() {
             empty_string_error_msg(DEVICE, DEVICE_SHORT);
         }
     }
 
-    #[cfg(not(any(
-        feature = "alsa-backend",
-        feature = "rodio-backend",
-        feature = "portaudio-backend"
-    )))]
-    let device: Option<String> = None;
-
-    #[cfg(not(any(
-        feature = "alsa-backend",
-        feature = "rodio-backend",
-        feature = "portaudio-backend"
-    )))]
-    if opt_present(DEVICE) {
-        warn!(
-            "The `--{}` / `-{}` option is not supported by the included audio backend(s), and has no effect.",
-            DEVICE, DEVICE_SHORT,
-        );
+    if opt_str(BACKEND).as_deref() == Some(FanoutSink::NAME) {
+        if let Err(e) = FanoutSink::check_device(device.as_deref()) {
+            invalid_error_msg(
+                DEVICE,
+                DEVICE_SHORT,
+                &device.unwrap_or_default(),
+                "BACKEND[@FORMAT][:DEVICE] separated by ;",
+                "",
+            );
+            error!("{}", e);
+            exit(1);
+        }
     }
 
-    #[cfg(feature = "alsa-backend")]
+    let fallback_backend = opt_str(FALLBACK_BACKEND).map(|fallback| {
+        let mut parts = fallback.splitn(2, ':');
+        let name = parts.next().unwrap_or_default().to_string();
+        let device = parts.next().map(|device| device.to_string());
+
+        let builder = audio_backend::find(Some(name)).unwrap_or_else(|| {
+            invalid_error_msg(FALLBACK_BACKEND, FALLBACK_BACKEND_SHORT, &fallback, "", "");
+
+            list_backends();
+            exit(1);
+        });
+
+        (builder, device)
+    });
+
     let mixer_type = opt_str(MIXER_TYPE);
-    #[cfg(not(feature = "alsa-backend"))]
-    let mixer_type: Option<String> = None;
 
     let mixer = mixer::find(mixer_type.as_deref()).unwrap_or_else(|| {
         invalid_error_msg(
             MIXER_TYPE,
             MIXER_TYPE_SHORT,
             &opt_str(MIXER_TYPE).unwrap_or_default(),
-            "alsa, softvol",
+            MIXER_TYPE_VALUES,
             "softvol",
         );
 
@@ -811,6 +1003,29 @@ fn get_setup() -> Setup {
         _ => false,
     };
 
+    let is_external_mixer = mixer_type.as_deref() == Some(ExternalMixer::NAME);
+
+    if is_external_mixer {
+        if !opt_present(EXTERNAL_MIXER) {
+            error!(
+                "`--{}` must be specified when `--{}` / `-{}` is set to \"{}\"",
+                EXTERNAL_MIXER,
+                MIXER_TYPE,
+                MIXER_TYPE_SHORT,
+                ExternalMixer::NAME
+            );
+
+            exit(1);
+        }
+    } else {
+        for a in &[EXTERNAL_MIXER, EXTERNAL_MIXER_QUERY] {
+            if opt_present(a) {
+                warn!("External mixer options have no effect if not using the external mixer.");
+                break;
+            }
+        }
+    }
+
     #[cfg(feature = "alsa-backend")]
     if !is_alsa_mixer {
         for a in &[ALSA_MIXER_DEVICE, ALSA_MIXER_INDEX, ALSA_MIXER_CONTROL] {
@@ -992,7 +1207,7 @@ fn get_setup() -> Setup {
                         VOLUME_CTRL,
                         VOLUME_CTRL_SHORT,
                         volume_ctrl,
-                        "cubic, fixed, linear, log",
+                        "cubic, fixed, linear, log, table:FILE",
                         "log",
                     );
 
@@ -1001,11 +1216,66 @@ fn get_setup() -> Setup {
             })
             .unwrap_or_else(|| VolumeCtrl::Log(volume_range));
 
+        let external = if is_external_mixer {
+            let external = opt_str(EXTERNAL_MIXER).unwrap_or_default();
+            if external.trim().is_empty() {
+                empty_string_error_msg(EXTERNAL_MIXER, EXTERNAL_MIXER_SHORT);
+            }
+
+            external
+        } else {
+            mixer_default_config.external
+        };
+
+        let external_query = opt_present(EXTERNAL_MIXER_QUERY);
+
+        let default_volume_ramp = mixer_default_config.volume_ramp;
+
+        let volume_ramp = if is_alsa_mixer || is_external_mixer {
+            if opt_present(VOLUME_RAMP) {
+                warn!(
+                    "The `--{}` option has no effect if not using the softvol mixer.",
+                    VOLUME_RAMP
+                );
+            }
+
+            default_volume_ramp
+        } else {
+            opt_str(VOLUME_RAMP)
+                .map(|ramp| match ramp.parse::<u64>() {
+                    Ok(value) if (VALID_VOLUME_RAMP_RANGE).contains(&value) => {
+                        Duration::from_millis(value)
+                    }
+                    _ => {
+                        let valid_values = &format!(
+                            "{} - {}",
+                            VALID_VOLUME_RAMP_RANGE.start(),
+                            VALID_VOLUME_RAMP_RANGE.end()
+                        );
+
+                        invalid_error_msg(
+                            VOLUME_RAMP,
+                            VOLUME_RAMP_SHORT,
+                            &ramp,
+                            valid_values,
+                            &default_volume_ramp.as_millis().to_string(),
+                        );
+
+                        exit(1);
+                    }
+                })
+                .unwrap_or(default_volume_ramp)
+        };
+
         MixerConfig {
             device,
             control,
             index,
             volume_ctrl,
+            volume_ramp,
+            sample_rate,
+            external,
+            external_query,
         }
     };
 
@@ -1191,7 +1461,7 @@ fn get_setup() -> Setup {
                 (volume as f32 / 100.0 * VolumeCtrl::MAX_VOLUME as f32) as u16
             })
             .or_else(|| {
-                if is_alsa_mixer {
+                if is_alsa_mixer || (is_external_mixer && mixer_config.external_query) {
                     None
                 } else {
                     cache.as_ref().and_then(Cache::volume)
@@ -1275,14 +1545,245 @@ fn get_setup() -> Setup {
             .as_deref()
             .map(|bitrate| {
                 Bitrate::from_str(bitrate).unwrap_or_else(|_| {
-                    invalid_error_msg(BITRATE, BITRATE_SHORT, bitrate, "96, 160, 320", "160");
+                    invalid_error_msg(BITRATE, BITRATE_SHORT, bitrate, "96, 160, 320, auto", "160");
                     exit(1);
                 })
             })
             .unwrap_or(player_default_config.bitrate);
 
+        let preferred_codec = opt_str(PREFERRED_CODEC)
+            .as_deref()
+            .map(|codec| {
+                AudioCodec::from_str(codec).unwrap_or_else(|_| {
+                    invalid_error_msg(
+                        PREFERRED_CODEC,
+                        PREFERRED_CODEC_SHORT,
+                        codec,
+                        "vorbis, mp3, aac",
+                        "vorbis",
+                    );
+                    exit(1);
+                })
+            })
+            .unwrap_or(player_default_config.preferred_codec);
+
         let gapless = !opt_present(DISABLE_GAPLESS);
 
+        let passthrough = opt_present(PASSTHROUGH);
+
+        if preferred_codec != AudioCodec::Vorbis {
+            if passthrough {
+                warn!(
+                    "With the `--{}` / `-{}` flag set only Vorbis is played, `--{}` has no effect.",
+                    PASSTHROUGH, PASSTHROUGH_SHORT, PREFERRED_CODEC
+                );
+            } else if cfg!(not(feature = "symphonia-decoder")) {
+                warn!(
+                    "Without the symphonia-decoder feature only Vorbis is played, `--{}` has no effect.",
+                    PREFERRED_CODEC
+                );
+            }
+        }
+
+        let crossfade = opt_str(CROSSFADE)
+            .map(|crossfade| match crossfade.parse::<u64>() {
+                Ok(value) if (VALID_CROSSFADE_RANGE).contains(&value) => {
+                    Duration::from_millis(value)
+                }
+                _ => {
+                    let valid_values = &format!(
+                        "{} - {}",
+                        VALID_CROSSFADE_RANGE.start(),
+                        VALID_CROSSFADE_RANGE.end()
+                    );
+
+                    invalid_error_msg(
+                        CROSSFADE,
+                        CROSSFADE_SHORT,
+                        &crossfade,
+                        valid_values,
+                        &player_default_config.crossfade.as_millis().to_string(),
+                    );
+
+                    exit(1);
+                }
+            })
+            .unwrap_or(player_default_config.crossfade);
+
+        if (!gapless || passthrough) && crossfade > Duration::default() {
+            warn!(
+                "With the `--{}` / `-{}` or `--{}` / `-{}` flag set `--{}` / `-{}` has no effect.",
+                DISABLE_GAPLESS,
+                DISABLE_GAPLESS_SHORT,
+                PASSTHROUGH,
+                PASSTHROUGH_SHORT,
+                CROSSFADE,
+                CROSSFADE_SHORT
+            );
+        }
+
+        if crossfade == Duration::default() && opt_present(CROSSFADE_CURVE) {
+            warn!(
+                "Without a `--{}` / `-{}` duration `--{}` / `-{}` has no effect.",
+                CROSSFADE, CROSSFADE_SHORT, CROSSFADE_CURVE, CROSSFADE_CURVE_SHORT
+            );
+        }
+
+        let crossfade_curve = opt_str(CROSSFADE_CURVE)
+            .as_deref()
+            .map(|curve| {
+                FadeCurve::from_str(curve).unwrap_or_else(|_| {
+                    invalid_error_msg(
+                        CROSSFADE_CURVE,
+                        CROSSFADE_CURVE_SHORT,
+                        curve,
+                        "linear, equal-power, s-curve",
+                        "equal-power",
+                    );
+
+                    exit(1);
+                })
+            })
+            .unwrap_or(player_default_config.crossfade_curve);
+
+        let fade = opt_str(FADE)
+            .map(|fade| match fade.parse::<u64>() {
+                Ok(value) if (VALID_FADE_RANGE).contains(&value) => Duration::from_millis(value),
+                _ => {
+                    let valid_values =
+                        &format!("{} - {}", VALID_FADE_RANGE.start(), VALID_FADE_RANGE.end());
+
+                    invalid_error_msg(
+                        FADE,
+                        FADE_SHORT,
+                        &fade,
+                        valid_values,
+                        &player_default_config.fade.as_millis().to_string(),
+                    );
+
+                    exit(1);
+                }
+            })
+            .unwrap_or(player_default_config.fade);
+
+        if passthrough && fade > Duration::default() {
+            warn!(
+                "With the `--{}` / `-{}` flag set `--{}` / `-{}` has no effect.",
+                PASSTHROUGH, PASSTHROUGH_SHORT, FADE, FADE_SHORT
+            );
+        }
+
+        let output_buffer = opt_str(OUTPUT_BUFFER)
+            .map(|buffer| match buffer.parse::<u64>() {
+                Ok(value) if (VALID_OUTPUT_BUFFER_RANGE).contains(&value) => {
+                    Duration::from_millis(value)
+                }
+                _ => {
+                    let valid_values = &format!(
+                        "{} - {}",
+                        VALID_OUTPUT_BUFFER_RANGE.start(),
+                        VALID_OUTPUT_BUFFER_RANGE.end()
+                    );
+
+                    invalid_error_msg(
+                        OUTPUT_BUFFER,
+                        OUTPUT_BUFFER_SHORT,
+                        &buffer,
+                        valid_values,
+                        &player_default_config.output_buffer.as_millis().to_string(),
+                    );
+
+                    exit(1);
+                }
+            })
+            .unwrap_or(player_default_config.output_buffer);
+
+        let sink_retries = opt_str(SINK_RETRIES)
+            .map(|retries| match retries.parse::<u32>() {
+                Ok(value) if (VALID_SINK_RETRIES_RANGE).contains(&value) => value,
+                _ => {
+                    let valid_values = &format!(
+                        "{} - {}",
+                        VALID_SINK_RETRIES_RANGE.start(),
+                        VALID_SINK_RETRIES_RANGE.end()
+                    );
+
+                    invalid_error_msg(
+                        SINK_RETRIES,
+                        SINK_RETRIES_SHORT,
+                        &retries,
+                        valid_values,
+                        &player_default_config.sink_retries.to_string(),
+                    );
+
+                    exit(1);
+                }
+            })
+            .unwrap_or(player_default_config.sink_retries);
+
+        let sink_retry_backoff = opt_str(SINK_RETRY_BACKOFF)
+            .map(|backoff| match backoff.parse::<u64>() {
+                Ok(value) if (VALID_SINK_RETRY_BACKOFF_RANGE).contains(&value) => {
+                    Duration::from_millis(value)
+                }
+                _ => {
+                    let valid_values = &format!(
+                        "{} - {}",
+                        VALID_SINK_RETRY_BACKOFF_RANGE.start(),
+                        VALID_SINK_RETRY_BACKOFF_RANGE.end()
+                    );
+
+                    invalid_error_msg(
+                        SINK_RETRY_BACKOFF,
+                        SINK_RETRY_BACKOFF_SHORT,
+                        &backoff,
+                        valid_values,
+                        &player_default_config
+                            .sink_retry_backoff
+                            .as_millis()
+                            .to_string(),
+                    );
+
+                    exit(1);
+                }
+            })
+            .unwrap_or(player_default_config.sink_retry_backoff);
+
+        let sleep_timer_fade = opt_str(SLEEP_TIMER_FADE)
+            .map(|fade| match fade.parse::<u64>() {
+                Ok(value) if (VALID_SLEEP_TIMER_FADE_RANGE).contains(&value) => {
+                    Duration::from_millis(value)
+                }
+                _ => {
+                    let valid_values = &format!(
+                        "{} - {}",
+                        VALID_SLEEP_TIMER_FADE_RANGE.start(),
+                        VALID_SLEEP_TIMER_FADE_RANGE.end()
+                    );
+
+                    invalid_error_msg(
+                        SLEEP_TIMER_FADE,
+                        SLEEP_TIMER_FADE_SHORT,
+                        &fade,
+                        valid_values,
+                        &player_default_config
+                            .sleep_timer_fade
+                            .as_millis()
+                            .to_string(),
+                    );
+
+                    exit(1);
+                }
+            })
+            .unwrap_or(player_default_config.sleep_timer_fade);
+
+        if !opt_present(SLEEP_TIMER) && opt_present(SLEEP_TIMER_FADE) {
+            warn!(
+                "Without the `--{}` / `-{}` option set `--{}` / `-{}` only applies to sleep timers set through the API.",
+                SLEEP_TIMER, SLEEP_TIMER_SHORT, SLEEP_TIMER_FADE, SLEEP_TIMER_FADE_SHORT
+            );
+        }
+
         let normalisation = opt_present(ENABLE_VOLUME_NORMALISATION);
 
         let normalisation_method;
@@ -1496,6 +1997,27 @@ fn get_setup() -> Setup {
                 .unwrap_or(player_default_config.normalisation_knee);
         }
 
+        let equalizer = opt_str(EQUALIZER)
+            .map(|bands| {
+                bands
+                    .split(',')
+                    .map(|band| {
+                        EqualizerBand::from_str(band).unwrap_or_else(|_| {
+                            invalid_error_msg(
+                                EQUALIZER,
+                                EQUALIZER_SHORT,
+                                band,
+                                "<type>:<frequency>[:<gain>[:<q>]] with type peak, lowshelf, highshelf, lowpass, highpass",
+                                "",
+                            );
+
+                            exit(1);
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(|| player_default_config.equalizer.clone());
+
         let ditherer_name = opt_str(DITHER);
         let ditherer = match ditherer_name.as_deref() {
             Some(value) => match value {
@@ -1526,12 +2048,32 @@ fn get_setup() -> Setup {
             },
         };
 
-        let passthrough = opt_present(PASSTHROUGH);
+        let local_files_dir = opt_str(LOCAL_FILES).map(PathBuf::from);
+        if let Some(ref dir) = local_files_dir {
+            if !dir.is_dir() {
+                invalid_error_msg(
+                    LOCAL_FILES,
+                    LOCAL_FILES_SHORT,
+                    &dir.to_string_lossy(),
+                    "a path to a directory",
+                    "",
+                );
+
+                exit(1);
+            }
+        }
 
         PlayerConfig {
             bitrate,
+            preferred_codec,
             gapless,
             passthrough,
+            sample_rate,
+            crossfade,
+            crossfade_curve,
+            fade,
+            sleep_timer_fade,
+            output_buffer,
             normalisation,
             normalisation_type,
             normalisation_method,
@@ -1540,10 +2082,59 @@ fn get_setup() -> Setup {
             normalisation_attack,
             normalisation_release,
             normalisation_knee,
+            equalizer,
+            sink_retries,
+            sink_retry_backoff,
+            local_files_dir,
             ditherer,
         }
     };
 
+    let sleep_timer = opt_str(SLEEP_TIMER).map(|timer| match SleepTimer::from_str(&timer) {
+        Ok(SleepTimer::After(duration))
+            if VALID_SLEEP_TIMER_RANGE.contains(&(duration.as_secs() / 60)) =>
+        {
+            SleepTimer::After(duration)
+        }
+        Ok(timer @ SleepTimer::EndOfTrack) | Ok(timer @ SleepTimer::EndOfAlbum) => timer,
+        _ => {
+            let valid_values = &format!(
+                "{} - {}, end-of-track, end-of-album",
+                VALID_SLEEP_TIMER_RANGE.start(),
+                VALID_SLEEP_TIMER_RANGE.end()
+            );
+
+            invalid_error_msg(SLEEP_TIMER, SLEEP_TIMER_SHORT, &timer, valid_values, "");
+
+            exit(1);
+        }
+    });
+
+    let speed = opt_str(SPEED).and_then(|speed| {
+        if player_config.passthrough {
+            warn!(
+                "With the `--{}` / `-{}` flag set the tempo can't be changed, the `--{}` option has no effect.",
+                PASSTHROUGH, PASSTHROUGH_SHORT, SPEED
+            );
+            return None;
+        }
+
+        match speed.parse::<f64>() {
+            Ok(value) if Player::SPEED_RANGE.contains(&value) => Some(value),
+            _ => {
+                let valid_values = &format!(
+                    "{} - {}",
+                    Player::SPEED_RANGE.start(),
+                    Player::SPEED_RANGE.end()
+                );
+
+                invalid_error_msg(SPEED, SPEED_SHORT, &speed, valid_values, "1.0");
+
+                exit(1);
+            }
+        }
+    });
+
     let player_event_program = opt_str(ONEVENT);
     let emit_sink_events = opt_present(EMIT_SINK_EVENTS);
 
@@ -1562,6 +2153,9 @@ fn get_setup() -> Setup {
         zeroconf_port,
         player_event_program,
         emit_sink_events,
+        fallback_backend,
+        sleep_timer,
+        speed,
     }
 }
 
@@ -1582,6 +2176,8 @@ async fn main() {
     let mut spirc_task: Option<Pin<_>> = None;
     let mut player_event_channel: Option<UnboundedReceiver<PlayerEvent>> = None;
     let mut auto_connect_times: Vec<Instant> = vec![];
+    let mut sleep_timer = setup.sleep_timer;
+    let mut sleep_timer_deadline = None;
     let mut discovery = None;
     let mut connecting: Pin<Box<dyn future::FusedFuture<Output = _>>> = Box::pin(future::pending());
 
@@ -1658,11 +2254,29 @@ async fn main() {
                     let format = setup.format;
                     let backend = setup.backend;
                     let device = setup.device.clone();
+                    let sample_rate = player_config.sample_rate;
                     let (player, event_channel) =
                         Player::new(player_config, session.clone(), audio_filter, move || {
-                            (backend)(device, format)
+                            (backend)(device, format, sample_rate)
                         });
 
+                    if let Some((fallback_backend, fallback_device)) = setup.fallback_backend.clone() {
+                        player.set_fallback_sink(fallback_backend, fallback_device, format);
+                    }
+
+                    if let Some(timer) = sleep_timer {
+                        // A timer that counts down carries on where it was after reconnecting.
+                        let timer = match timer {
+                            SleepTimer::After(duration) => {
+                                let deadline = *sleep_timer_deadline
+                                    .get_or_insert_with(|| Instant::now() + duration);
+                                SleepTimer::After(deadline.saturating_duration_since(Instant::now()))
+                            }
+                            timer => timer,
+                        };
+                        player.set_sleep_timer(Some(timer));
+                    }
+
                     if setup.emit_sink_events {
                         if let Some(player_event_program) = setup.player_event_program.clone() {
                             player.set_sink_event_callback(Some(Box::new(move |sink_status| {
@@ -1685,6 +2299,10 @@ async fn main() {
 
                     let (spirc_, spirc_task_) = Spirc::new(connect_config, session, player, mixer);
 
+                    if let Some(speed) = setup.speed {
+                        spirc_.set_speed(speed);
+                    }
+
                     spirc = Some(spirc_);
                     spirc_task = Some(Box::pin(spirc_task_));
                     player_event_channel = Some(event_channel);
@@ -1731,6 +2349,10 @@ async fn main() {
                 }
             }, if player_event_channel.is_some() => match event {
                 Some(event) => {
+                    if let PlayerEvent::SleepTimerFired { .. } = event {
+                        sleep_timer = None;
+                    }
+
                     if let Some(program) = &setup.player_event_program {
                         if let Some(child) = run_program_on_events(event, program) {
                             if let Ok(mut child) = child {
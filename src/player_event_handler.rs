@@ -1,3 +1,4 @@
+use librespot::playback::config::SleepTimer;
 use librespot::playback::player::PlayerEvent;
 use librespot::playback::player::SinkStatus;
 use log::info;
@@ -7,6 +8,19 @@ use std::collections::HashMap;
 use std::io;
 use std::process::{Command, ExitStatus};
 
+fn insert_sleep_timer(env_vars: &mut HashMap<&str, String>, timer: Option<SleepTimer>) {
+    let timer = match timer {
+        Some(SleepTimer::After(duration)) => {
+            env_vars.insert("DURATION_MS", duration.as_millis().to_string());
+            "after"
+        }
+        Some(SleepTimer::EndOfTrack) => "end-of-track",
+        Some(SleepTimer::EndOfAlbum) => "end-of-album",
+        None => "none",
+    };
+    env_vars.insert("SLEEP_TIMER", timer.to_string());
+}
+
 pub fn run_program_on_events(event: PlayerEvent, onevent: &str) -> Option<io::Result<AsyncChild>> {
     let mut env_vars = HashMap::new();
     match event {
@@ -48,14 +62,82 @@ pub fn run_program_on_events(event: PlayerEvent, onevent: &str) -> Option<io::Re
             env_vars.insert("DURATION_MS", duration_ms.to_string());
             env_vars.insert("POSITION_MS", position_ms.to_string());
         }
+        PlayerEvent::TrackChanged { audio_item, .. } => {
+            env_vars.insert("PLAYER_EVENT", "track_changed".to_string());
+            env_vars.insert("TRACK_ID", audio_item.id.to_base62());
+            env_vars.insert("URI", audio_item.uri);
+            env_vars.insert("NAME", audio_item.name);
+            // one per line
+            env_vars.insert("ARTISTS", audio_item.artists.join("\n"));
+            env_vars.insert("ALBUM", audio_item.album);
+            env_vars.insert("DURATION_MS", audio_item.duration.to_string());
+            env_vars.insert(
+                "COVERS",
+                audio_item
+                    .covers
+                    .iter()
+                    .map(|cover| cover.to_base16())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+            env_vars.insert("IS_PODCAST", audio_item.is_podcast.to_string());
+        }
+        PlayerEvent::FormatSelected {
+            track_id, format, ..
+        } => {
+            env_vars.insert("PLAYER_EVENT", "format_selected".to_string());
+            env_vars.insert("TRACK_ID", track_id.to_base62());
+            env_vars.insert("FORMAT", format!("{:?}", format));
+        }
         PlayerEvent::Preloading { track_id, .. } => {
             env_vars.insert("PLAYER_EVENT", "preloading".to_string());
             env_vars.insert("TRACK_ID", track_id.to_base62());
         }
+        PlayerEvent::Buffering {
+            track_id,
+            position_ms,
+            ..
+        } => {
+            env_vars.insert("PLAYER_EVENT", "buffering".to_string());
+            env_vars.insert("TRACK_ID", track_id.to_base62());
+            env_vars.insert("POSITION_MS", position_ms.to_string());
+        }
+        PlayerEvent::BufferingFinished { track_id, .. } => {
+            env_vars.insert("PLAYER_EVENT", "buffering_finished".to_string());
+            env_vars.insert("TRACK_ID", track_id.to_base62());
+        }
         PlayerEvent::VolumeSet { volume } => {
             env_vars.insert("PLAYER_EVENT", "volume_set".to_string());
             env_vars.insert("VOLUME", volume.to_string());
         }
+        PlayerEvent::SpeedChanged { speed } => {
+            env_vars.insert("PLAYER_EVENT", "speed_changed".to_string());
+            env_vars.insert("SPEED", speed.to_string());
+        }
+        PlayerEvent::OutputLost { error } => {
+            env_vars.insert("PLAYER_EVENT", "output_lost".to_string());
+            env_vars.insert("ERROR", error);
+        }
+        PlayerEvent::OutputReconnected { fallback } => {
+            env_vars.insert("PLAYER_EVENT", "output_reconnected".to_string());
+            env_vars.insert("FALLBACK", fallback.to_string());
+        }
+        PlayerEvent::SleepTimerArmed { timer } => {
+            env_vars.insert("PLAYER_EVENT", "sleep_timer_armed".to_string());
+            insert_sleep_timer(&mut env_vars, Some(timer));
+        }
+        PlayerEvent::SleepTimerChanged { timer } => {
+            env_vars.insert("PLAYER_EVENT", "sleep_timer_changed".to_string());
+            insert_sleep_timer(&mut env_vars, timer);
+        }
+        PlayerEvent::SleepTimerFired { timer } => {
+            env_vars.insert("PLAYER_EVENT", "sleep_timer_fired".to_string());
+            insert_sleep_timer(&mut env_vars, Some(timer));
+        }
+        PlayerEvent::Error { error } => {
+            env_vars.insert("PLAYER_EVENT", "error".to_string());
+            env_vars.insert("ERROR", error.to_string());
+        }
         _ => return None,
     }
 